@@ -1,10 +1,12 @@
 //! Provides functions to parse input IP addresses, CIDRs or files.
+use std::collections::HashMap;
 use std::fs::{self, File};
 use std::io::{prelude::*, BufReader};
 use std::net::{IpAddr, SocketAddr, ToSocketAddrs};
 use std::path::Path;
 use std::str::FromStr;
 
+use anyhow::{anyhow, Result};
 use cidr_utils::cidr::IpCidr;
 use hickory_resolver::{
     config::{NameServerConfig, Protocol, ResolverConfig, ResolverOpts},
@@ -15,6 +17,9 @@ use log::debug;
 use crate::input::Opts;
 use crate::warning;
 
+mod targets;
+pub use targets::{AddressRange, Targets};
+
 /// Parses the string(s) into IP addresses.
 ///
 /// Goes through all possible IP inputs (files or via argparsing).
@@ -28,14 +33,50 @@ use crate::warning;
 /// let ips = parse_addresses(&opts);
 /// ```
 pub fn parse_addresses(input: &Opts) -> Vec<IpAddr> {
-    let mut ips: Vec<IpAddr> = Vec::new();
+    parse_addresses_with_hostnames(input).0.iter().collect()
+}
+
+/// Same as [`parse_addresses`], but keeps the addresses as lazily expanded
+/// [`Targets`], and also returns the host names each IP was resolved from.
+/// IPs given directly or through a CIDR have no entry.
+///
+/// The addresses of `--exclude` and `--exclude-file` are left out of both.
+/// When they can't all be read, nothing is returned at all rather than
+/// hosts that were meant to be left alone.
+///
+/// ```rust
+/// # use rustscan::input::Opts;
+/// # use rustscan::address::parse_addresses_with_hostnames;
+/// let mut opts = Opts::default();
+/// opts.addresses = vec!["localhost".to_owned()];
+///
+/// let (targets, hostnames) = parse_addresses_with_hostnames(&opts);
+/// ```
+pub fn parse_addresses_with_hostnames(input: &Opts) -> (Targets, HashMap<IpAddr, Vec<String>>) {
+    match parse_excluded(input) {
+        Ok(excluded) => parse_addresses_excluding(input, &excluded),
+        Err(e) => {
+            warning!(e.to_string(), input.greppable, input.accessible);
+            (Targets::new(), HashMap::new())
+        }
+    }
+}
+
+/// Same as [`parse_addresses_with_hostnames`], with the addresses not to
+/// scan already parsed by [`parse_excluded`].
+pub fn parse_addresses_excluding(
+    input: &Opts,
+    excluded: &Targets,
+) -> (Targets, HashMap<IpAddr, Vec<String>>) {
+    let mut targets = Targets::new();
+    let mut hostnames: HashMap<IpAddr, Vec<String>> = HashMap::new();
     let mut unresolved_addresses: Vec<&str> = Vec::new();
     let backup_resolver = get_resolver(&input.resolver);
 
     for address in &input.addresses {
-        let parsed_ips = parse_address(address, &backup_resolver);
-        if !parsed_ips.is_empty() {
-            ips.extend(parsed_ips);
+        let ranges = parse_address_ranges(address, &backup_resolver);
+        if !ranges.is_empty() {
+            add_targets(address, ranges, &mut targets, &mut hostnames);
         } else {
             unresolved_addresses.push(address);
         }
@@ -55,9 +96,7 @@ pub fn parse_addresses(input: &Opts) -> Vec<IpAddr> {
             continue;
         }
 
-        if let Ok(x) = read_ips_from_file(file_path, &backup_resolver) {
-            ips.extend(x);
-        } else {
+        if read_ips_from_file(file_path, &backup_resolver, &mut targets, &mut hostnames).is_err() {
             warning!(
                 format!("Host {file_path:?} could not be resolved."),
                 input.greppable,
@@ -66,7 +105,68 @@ pub fn parse_addresses(input: &Opts) -> Vec<IpAddr> {
         }
     }
 
-    ips
+    if !excluded.is_empty() {
+        targets = targets.without(excluded);
+        hostnames.retain(|ip, _| targets.contains(*ip));
+    }
+
+    (targets, hostnames)
+}
+
+/// The addresses not to scan, from `--exclude` and the lines of
+/// `--exclude-file`. They are kept as ranges, so that excluding large CIDRs
+/// costs no more than excluding single IPs.
+///
+/// Fails when the file can't be read or a host doesn't resolve, as the
+/// scan would otherwise reach the very hosts it was told to leave alone.
+pub fn parse_excluded(input: &Opts) -> Result<Targets> {
+    let mut lines = Vec::new();
+    if let Some(path) = &input.exclude_file {
+        let content = fs::read_to_string(path)
+            .map_err(|e| anyhow!("Exclude file {path:?} could not be read: {e}"))?;
+        lines.extend(content.lines().map(str::to_owned));
+    }
+
+    let resolver = get_resolver(&input.resolver);
+    let mut excluded = Targets::new();
+    let addresses = input.exclude.iter().chain(&lines).map(|a| a.trim());
+    for address in addresses.filter(|a| !a.is_empty() && !a.starts_with('#')) {
+        let ranges = parse_address_ranges(address, &resolver);
+        if ranges.is_empty() {
+            return Err(anyhow!("Excluded host {address:?} could not be resolved."));
+        }
+        for range in ranges {
+            excluded.push(range);
+        }
+    }
+    Ok(excluded)
+}
+
+/// Adds the parsed `ranges` of `address` to the targets, and remembers
+/// `address` as the host name of their IPs when it is not an IP or CIDR.
+///
+/// The same IP can come from several inputs, e.g. "127.0.0.1" and
+/// "localhost", the targets only keep it once.
+fn add_targets(
+    address: &str,
+    ranges: Vec<AddressRange>,
+    targets: &mut Targets,
+    hostnames: &mut HashMap<IpAddr, Vec<String>>,
+) {
+    if IpCidr::from_str(address).is_err() {
+        // Host names resolve to a handful of single IPs, never to a CIDR.
+        let ips: Targets = ranges.iter().copied().collect();
+        for ip in ips.iter() {
+            let names = hostnames.entry(ip).or_default();
+            if !names.iter().any(|name| name == address) {
+                names.push(address.to_owned());
+            }
+        }
+    }
+
+    for range in ranges {
+        targets.push(range);
+    }
 }
 
 /// Given a string, parse it as a host, IP address, or CIDR.
@@ -83,16 +183,30 @@ pub fn parse_addresses(input: &Opts) -> Vec<IpAddr> {
 /// let ips = parse_address("127.0.0.1", &Resolver::default().unwrap());
 /// ```
 pub fn parse_address(address: &str, resolver: &Resolver) -> Vec<IpAddr> {
+    parse_address_ranges(address, resolver)
+        .into_iter()
+        .collect::<Targets>()
+        .iter()
+        .collect()
+}
+
+/// Same as [`parse_address`], without expanding CIDRs.
+fn parse_address_ranges(address: &str, resolver: &Resolver) -> Vec<AddressRange> {
     IpCidr::from_str(address)
-        .map(|cidr| cidr.iter().map(|c| c.address()).collect())
+        .map(|cidr| vec![AddressRange::from(&cidr)])
         .ok()
         .or_else(|| {
             format!("{}:{}", &address, 80)
                 .to_socket_addrs()
                 .ok()
-                .map(|mut iter| vec![iter.next().unwrap().ip()])
+                .map(|mut iter| vec![iter.next().unwrap().ip().into()])
+        })
+        .unwrap_or_else(|| {
+            resolve_ips_from_host(address, resolver)
+                .into_iter()
+                .map(AddressRange::from)
+                .collect()
         })
-        .unwrap_or_else(|| resolve_ips_from_host(address, resolver))
 }
 
 /// Uses DNS to get the IPS associated with host
@@ -163,26 +277,30 @@ fn read_resolver_from_file(path: &str) -> Result<Vec<IpAddr>, std::io::Error> {
 fn read_ips_from_file(
     ips: &std::path::Path,
     backup_resolver: &Resolver,
-) -> Result<Vec<IpAddr>, std::io::Error> {
+    targets: &mut Targets,
+    hostnames: &mut HashMap<IpAddr, Vec<String>>,
+) -> Result<(), std::io::Error> {
     let file = File::open(ips)?;
     let reader = BufReader::new(file);
 
-    let mut ips: Vec<IpAddr> = Vec::new();
-
     for address_line in reader.lines() {
         if let Ok(address) = address_line {
-            ips.extend(parse_address(&address, backup_resolver));
+            let ranges = parse_address_ranges(&address, backup_resolver);
+            add_targets(&address, ranges, targets, hostnames);
         } else {
             debug!("Line in file is not valid");
         }
     }
 
-    Ok(ips)
+    Ok(())
 }
 
 #[cfg(test)]
+#[allow(clippy::field_reassign_with_default)]
 mod tests {
-    use super::{get_resolver, parse_addresses, Opts};
+    use super::{
+        get_resolver, parse_addresses, parse_addresses_with_hostnames, parse_excluded, Opts,
+    };
     use std::net::Ipv4Addr;
 
     #[test]
@@ -203,6 +321,75 @@ mod tests {
         );
     }
 
+    #[test]
+    fn hostnames_are_kept_for_resolved_hosts_only() {
+        let mut opts = Opts::default();
+        opts.addresses = vec!["localhost".to_owned(), "192.168.0.0/31".to_owned()];
+        let (targets, hostnames) = parse_addresses_with_hostnames(&opts);
+
+        assert_eq!(targets.len(), 3);
+        assert_eq!(hostnames.len(), 1);
+        assert_eq!(
+            hostnames[&targets.get(0).unwrap()],
+            vec!["localhost".to_owned()]
+        );
+    }
+
+    #[test]
+    fn excluded_addresses_are_not_scanned() {
+        let mut opts = Opts::default();
+        opts.addresses = vec![
+            "localhost".to_owned(),
+            "192.168.0.0/29".to_owned(),
+            "10.0.0.1".to_owned(),
+        ];
+        opts.exclude = vec![
+            "127.0.0.1".to_owned(),
+            "192.168.0.2/31".to_owned(),
+            "192.168.0.6".to_owned(),
+            "10.0.0.0/8".to_owned(),
+        ];
+        let (targets, hostnames) = parse_addresses_with_hostnames(&opts);
+
+        assert_eq!(
+            targets.iter().collect::<Vec<_>>(),
+            [
+                Ipv4Addr::new(192, 168, 0, 0),
+                Ipv4Addr::new(192, 168, 0, 1),
+                Ipv4Addr::new(192, 168, 0, 4),
+                Ipv4Addr::new(192, 168, 0, 5),
+                Ipv4Addr::new(192, 168, 0, 7)
+            ]
+        );
+        assert!(hostnames.is_empty());
+    }
+
+    #[test]
+    fn exclusions_that_cant_be_read_are_errors() {
+        let mut opts = Opts::default();
+        opts.addresses = vec!["127.0.0.1".to_owned()];
+        opts.exclude = vec!["im_wrong".to_owned()];
+        assert!(parse_excluded(&opts).is_err());
+        assert!(parse_addresses_with_hostnames(&opts).0.is_empty());
+
+        opts.exclude = vec![];
+        opts.exclude_file = Some("this_file_does_not_exist".into());
+        assert!(parse_excluded(&opts).is_err());
+        assert!(parse_addresses_with_hostnames(&opts).0.is_empty());
+    }
+
+    #[test]
+    fn duplicate_addresses_are_scanned_once() {
+        let mut opts = Opts::default();
+        opts.addresses = vec!["127.0.0.1".to_owned(), "127.0.0.0/31".to_owned()];
+        let ips = parse_addresses(&opts);
+
+        assert_eq!(
+            ips,
+            [Ipv4Addr::new(127, 0, 0, 1), Ipv4Addr::new(127, 0, 0, 0)]
+        );
+    }
+
     #[test]
     fn parse_correct_host_addresses() {
         let mut opts = Opts::default();
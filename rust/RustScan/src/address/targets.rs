@@ -0,0 +1,407 @@
+//! The set of hosts to scan, kept as ranges of addresses.
+//!
+//! A `/8` is already 16 million addresses and an IPv6 `/64` could never be
+//! listed at all, so CIDRs are never expanded. Each input becomes an
+//! inclusive range of integers, and an address is only computed when the
+//! scan gets to it, either in order through [`Targets::iter`] or by its
+//! position through [`Targets::get`]. Memory grows with the number of
+//! inputs, not with the number of addresses they cover.
+use std::collections::BTreeMap;
+use std::iter::FromIterator;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use cidr_utils::cidr::IpCidr;
+
+/// An inclusive range of addresses of a single family.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AddressRange {
+    v6: bool,
+    start: u128,
+    end: u128,
+}
+
+impl AddressRange {
+    /// The addresses from `first` to `last`, both included. `None` when
+    /// they are of different families or `last` comes before `first`.
+    pub fn new(first: IpAddr, last: IpAddr) -> Option<Self> {
+        let (v6, start) = to_int(first);
+        let (last_v6, end) = to_int(last);
+        if v6 != last_v6 || end < start {
+            return None;
+        }
+        Some(Self { v6, start, end })
+    }
+
+    /// The number of addresses in the range. Only `::/0` doesn't fit, it
+    /// saturates at `u128::MAX`.
+    pub fn len(&self) -> u128 {
+        (self.end - self.start).saturating_add(1)
+    }
+
+    /// Ranges always hold at least one address.
+    pub fn is_empty(&self) -> bool {
+        false
+    }
+
+    /// The address at `index` in the range, `index` must be below `len`.
+    fn get(&self, index: u128) -> IpAddr {
+        from_int(self.v6, self.start + index)
+    }
+}
+
+impl From<IpAddr> for AddressRange {
+    fn from(ip: IpAddr) -> Self {
+        let (v6, n) = to_int(ip);
+        Self {
+            v6,
+            start: n,
+            end: n,
+        }
+    }
+}
+
+impl From<&IpCidr> for AddressRange {
+    fn from(cidr: &IpCidr) -> Self {
+        Self::new(cidr.first_address(), cidr.last_address())
+            .expect("the first address of a CIDR can't come after its last")
+    }
+}
+
+fn to_int(ip: IpAddr) -> (bool, u128) {
+    match ip {
+        IpAddr::V4(ip) => (false, u128::from(u32::from(ip))),
+        IpAddr::V6(ip) => (true, u128::from(ip)),
+    }
+}
+
+fn from_int(v6: bool, n: u128) -> IpAddr {
+    if v6 {
+        IpAddr::V6(Ipv6Addr::from(n))
+    } else {
+        // IPv4 ranges only ever hold 32 bit values.
+        IpAddr::V4(Ipv4Addr::from(n as u32))
+    }
+}
+
+/// The hosts to scan, in the order they were given, without duplicates.
+#[derive(Debug, Clone, Default)]
+pub struct Targets {
+    ranges: Vec<AddressRange>,
+    /// The number of addresses before each range, to find them by index.
+    offsets: Vec<u128>,
+    len: u128,
+    /// Every address added so far, as merged `(v6, start) -> end` ranges.
+    covered: BTreeMap<(bool, u128), u128>,
+    /// The index in `ranges` of the range starting at `(v6, start)`.
+    starts: BTreeMap<(bool, u128), usize>,
+}
+
+impl Targets {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the addresses of `range` that are not targets yet.
+    pub fn push(&mut self, range: AddressRange) {
+        let AddressRange { v6, start, end } = range;
+
+        // The covered ranges that overlap or touch the new one.
+        let lower = start.saturating_sub(1);
+        let upper = end.saturating_add(1);
+        let mut nearby: Vec<(u128, u128)> = Vec::new();
+        if let Some((&(_, s), &e)) = self.covered.range((v6, 0)..(v6, lower)).next_back() {
+            if e >= lower {
+                nearby.push((s, e));
+            }
+        }
+        nearby.extend(
+            self.covered
+                .range((v6, lower)..=(v6, upper))
+                .map(|(&(_, s), &e)| (s, e)),
+        );
+
+        // Add the gaps between them, in order.
+        let mut cursor = Some(start);
+        for &(s, e) in &nearby {
+            let Some(c) = cursor else { break };
+            if s > c {
+                self.append(v6, c, (s - 1).min(end));
+            }
+            cursor = e.checked_add(1).map(|next| next.max(c));
+        }
+        if let Some(c) = cursor {
+            if c <= end {
+                self.append(v6, c, end);
+            }
+        }
+
+        // And merge it all into a single covered range.
+        let merged_start = nearby.first().map_or(start, |&(s, _)| s.min(start));
+        let merged_end = nearby.last().map_or(end, |&(_, e)| e.max(end));
+        for (s, _) in nearby {
+            self.covered.remove(&(v6, s));
+        }
+        self.covered.insert((v6, merged_start), merged_end);
+    }
+
+    /// Appends a range that is known to be new, extending the last range
+    /// when it follows it directly, like the lines of a sorted hosts file.
+    fn append(&mut self, v6: bool, start: u128, end: u128) {
+        let range = AddressRange { v6, start, end };
+        match self.ranges.last_mut() {
+            Some(last) if last.v6 == v6 && last.end.checked_add(1) == Some(start) => {
+                last.end = end;
+            }
+            _ => {
+                self.starts.insert((v6, start), self.ranges.len());
+                self.offsets.push(self.len);
+                self.ranges.push(range);
+            }
+        }
+        self.len = self.len.saturating_add(range.len());
+    }
+
+    /// The number of addresses to scan.
+    pub fn len(&self) -> u128 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The address at position `index`, in the order of [`Targets::iter`].
+    pub fn get(&self, index: u128) -> Option<IpAddr> {
+        if index >= self.len {
+            return None;
+        }
+        let range = self.offsets.partition_point(|&offset| offset <= index) - 1;
+        Some(self.ranges[range].get(index - self.offsets[range]))
+    }
+
+    /// The position of `ip` in the order of [`Targets::iter`], the reverse
+    /// of [`Targets::get`].
+    pub fn position(&self, ip: IpAddr) -> Option<u128> {
+        let (v6, n) = to_int(ip);
+        let (&(_, start), &index) = self.starts.range((v6, 0)..=(v6, n)).next_back()?;
+        if self.ranges[index].end < n {
+            return None;
+        }
+        Some(self.offsets[index] + (n - start))
+    }
+
+    /// Walks the addresses in order, computing them on the way.
+    pub fn iter(&self) -> impl Iterator<Item = IpAddr> + '_ {
+        self.ranges
+            .iter()
+            .flat_map(|range| (0..range.len()).map(move |i| range.get(i)))
+    }
+
+    /// The targets without the addresses of `excluded`, in the same order.
+    /// Each range is cut by the merged excluded ranges overlapping it, so
+    /// large CIDRs and long exclusion lists are never expanded either.
+    #[must_use]
+    pub fn without(&self, excluded: &Targets) -> Self {
+        let mut remaining = Self::new();
+        for &AddressRange { v6, start, end } in &self.ranges {
+            // The excluded range starting before this one may still cover
+            // its start.
+            let before = excluded.covered.range((v6, 0)..(v6, start)).next_back();
+            let overlapping = before
+                .into_iter()
+                .chain(excluded.covered.range((v6, start)..=(v6, end)));
+
+            let mut cursor = Some(start);
+            for (&(_, s), &e) in overlapping {
+                let Some(c) = cursor else { break };
+                if e < c {
+                    continue;
+                }
+                if s > c {
+                    remaining.push(AddressRange {
+                        v6,
+                        start: c,
+                        end: s - 1,
+                    });
+                }
+                cursor = e.checked_add(1);
+            }
+            if let Some(c) = cursor {
+                if c <= end {
+                    remaining.push(AddressRange { v6, start: c, end });
+                }
+            }
+        }
+        remaining
+    }
+
+    /// The first and last address of each run of consecutive targets,
+    /// merged and sorted, whatever order they were given in.
+    pub fn spans(&self) -> impl Iterator<Item = (IpAddr, IpAddr)> + '_ {
+        self.covered
+            .iter()
+            .map(|(&(v6, start), &end)| (from_int(v6, start), from_int(v6, end)))
+    }
+
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        let (v6, n) = to_int(ip);
+        self.covered
+            .range((v6, 0)..=(v6, n))
+            .next_back()
+            .is_some_and(|(_, &end)| end >= n)
+    }
+}
+
+impl<R: Into<AddressRange>> FromIterator<R> for Targets {
+    fn from_iter<T: IntoIterator<Item = R>>(iter: T) -> Self {
+        let mut targets = Self::new();
+        for range in iter {
+            targets.push(range.into());
+        }
+        targets
+    }
+}
+
+impl From<&[IpAddr]> for Targets {
+    fn from(ips: &[IpAddr]) -> Self {
+        ips.iter().copied().collect()
+    }
+}
+
+impl From<&Vec<IpAddr>> for Targets {
+    fn from(ips: &Vec<IpAddr>) -> Self {
+        ips.iter().copied().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AddressRange, Targets};
+    use cidr_utils::cidr::IpCidr;
+    use std::net::IpAddr;
+    use std::str::FromStr;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    fn cidr(s: &str) -> AddressRange {
+        (&IpCidr::from_str(s).unwrap()).into()
+    }
+
+    #[test]
+    fn large_cidrs_are_not_expanded() {
+        let mut targets = Targets::new();
+        targets.push(cidr("10.0.0.0/8"));
+        targets.push(cidr("2001:db8::/64"));
+
+        assert_eq!(targets.len(), (1 << 24) + (1 << 64));
+        assert_eq!(targets.get(0), Some(ip("10.0.0.0")));
+        assert_eq!(targets.get((1 << 24) - 1), Some(ip("10.255.255.255")));
+        assert_eq!(targets.get(1 << 24), Some(ip("2001:db8::")));
+        assert_eq!(targets.get((1 << 24) + 5), Some(ip("2001:db8::5")));
+        assert_eq!(targets.get(targets.len()), None);
+        assert!(targets.contains(ip("10.1.2.3")));
+        assert!(!targets.contains(ip("11.0.0.0")));
+        assert_eq!(targets.iter().nth(300), Some(ip("10.0.1.44")));
+    }
+
+    #[test]
+    fn duplicates_are_dropped_and_order_kept() {
+        let mut targets = Targets::new();
+        targets.push(ip("192.168.0.2").into());
+        targets.push(cidr("192.168.0.0/30"));
+        targets.push(ip("127.0.0.1").into());
+        targets.push(cidr("192.168.0.0/31"));
+
+        let ips: Vec<IpAddr> = targets.iter().collect();
+        assert_eq!(
+            ips,
+            [
+                ip("192.168.0.2"),
+                ip("192.168.0.0"),
+                ip("192.168.0.1"),
+                ip("192.168.0.3"),
+                ip("127.0.0.1"),
+            ]
+        );
+        assert_eq!(targets.len(), 5);
+        for (i, expected) in ips.iter().enumerate() {
+            assert_eq!(targets.get(i as u128), Some(*expected));
+            assert_eq!(targets.position(*expected), Some(i as u128));
+        }
+        assert_eq!(targets.position(ip("192.168.0.4")), None);
+    }
+
+    #[test]
+    fn consecutive_addresses_share_a_range() {
+        let targets: Targets = (0..=255).map(|i| ip(&format!("10.0.0.{i}"))).collect();
+        assert_eq!(targets.ranges.len(), 1);
+        assert_eq!(targets.len(), 256);
+        assert_eq!(targets.covered.len(), 1);
+    }
+
+    #[test]
+    fn spans_are_merged_and_sorted() {
+        let targets: Targets = vec![
+            cidr("2001:db8::/127"),
+            cidr("10.0.1.0/24"),
+            ip("192.168.0.1").into(),
+            cidr("10.0.0.0/24"),
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(
+            targets.spans().collect::<Vec<_>>(),
+            [
+                (ip("10.0.0.0"), ip("10.0.1.255")),
+                (ip("192.168.0.1"), ip("192.168.0.1")),
+                (ip("2001:db8::"), ip("2001:db8::1")),
+            ]
+        );
+    }
+
+    #[test]
+    fn excluded_addresses_are_cut_out_in_order() {
+        let targets: Targets = vec![
+            cidr("10.0.0.0/8"),
+            ip("192.168.0.1").into(),
+            cidr("2001:db8::/64"),
+        ]
+        .into_iter()
+        .collect();
+        let excluded: Targets = vec![
+            cidr("10.0.0.0/24"),
+            cidr("10.1.0.0/16"),
+            ip("10.255.255.255").into(),
+            ip("192.168.0.1").into(),
+            cidr("2001:db8::/65"),
+            cidr("172.16.0.0/12"),
+        ]
+        .into_iter()
+        .collect();
+
+        let remaining = targets.without(&excluded);
+        assert_eq!(remaining.len(), (1 << 24) - 256 - (1 << 16) - 1 + (1 << 63));
+        assert_eq!(remaining.get(0), Some(ip("10.0.1.0")));
+        assert!(!remaining.contains(ip("10.1.2.3")));
+        assert!(remaining.contains(ip("10.2.0.0")));
+        assert!(!remaining.contains(ip("192.168.0.1")));
+        assert_eq!(
+            remaining.get((1 << 24) - 256 - (1 << 16) - 1),
+            Some(ip("2001:db8::8000:0:0:0"))
+        );
+        assert_eq!(targets.without(&targets).len(), 0);
+        assert_eq!(targets.without(&Targets::new()).len(), targets.len());
+    }
+
+    #[test]
+    fn whole_address_space_saturates() {
+        let mut targets = Targets::new();
+        targets.push(cidr("::/0"));
+        targets.push(cidr("::1/128"));
+        assert_eq!(targets.len(), u128::MAX);
+        assert_eq!(targets.get(1), Some(ip("::1")));
+        assert!(targets.contains(ip("ffff::")));
+    }
+}
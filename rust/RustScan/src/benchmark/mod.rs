@@ -16,7 +16,7 @@
 //! // Print Benchmark Summary
 //! info!("{}", bm.summary());
 //! ```
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
 /// A Benchmark struct to hold NamedTimers with name, start and end Instants,
 #[derive(Debug)]
@@ -41,18 +41,26 @@ impl Benchmark {
     pub fn summary(&self) -> String {
         let mut summary = String::from("\nRustScan Benchmark Summary");
 
-        for timer in &self.named_timers {
-            if timer.start.is_some() && timer.end.is_some() {
-                let runtime_secs = timer
-                    .end
-                    .unwrap()
-                    .saturating_duration_since(timer.start.unwrap())
-                    .as_secs_f32();
-                summary.push_str(&format!("\n{0: <10} | {1: <10}s", timer.name, runtime_secs));
-            }
+        for (name, runtime) in self.runtimes() {
+            let runtime_secs = runtime.as_secs_f32();
+            summary.push_str(&format!("\n{0: <10} | {1: <10}s", name, runtime_secs));
         }
         summary
     }
+
+    /// Returns the name and runtime of every timer that was ended,
+    /// in the order they were pushed.
+    pub fn runtimes(&self) -> Vec<(&'static str, Duration)> {
+        self.named_timers
+            .iter()
+            .filter_map(|timer| match (timer.start, timer.end) {
+                (Some(start), Some(end)) => {
+                    Some((timer.name, end.saturating_duration_since(start)))
+                }
+                _ => None,
+            })
+            .collect()
+    }
 }
 
 /// The purpose of NamedTimer is to hold a name,
@@ -0,0 +1,275 @@
+//! Checkpoint files of `--resume-file`, to continue an interrupted scan.
+//!
+//! A checkpoint holds what the scan was, how far the scanner got (see
+//! [`Scanner::checkpoint`](crate::scanner::Scanner::checkpoint)) and the
+//! results found until then. It is saved as JSON through an
+//! [`AtomicFile`], so a run killed while saving still leaves the previous
+//! checkpoint behind. A [`CheckpointWriter`] saves them on a thread of its
+//! own, so the scan doesn't wait for the disk.
+use crate::input::{PortRange, ScanOrder, Shard};
+use crate::output::AtomicFile;
+use crate::scanner::{PortDetails, PortState};
+use log::error;
+use serde_derive::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Sender};
+use std::thread::{self, JoinHandle};
+
+/// The version of the checkpoint format, bumped on incompatible changes.
+const CHECKPOINT_VERSION: u32 = 1;
+
+/// The progress and results of a scan.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub version: u32,
+    pub scan: ScanSpec,
+    /// Every socket before this position of the scan is done.
+    pub position: u128,
+    /// The positions after `position` that are done too.
+    pub done: Vec<u128>,
+    /// The hosts that answered so far, silent hosts are left out.
+    pub hosts: Vec<HostProgress>,
+    /// The number of sockets in each state so far, over all hosts.
+    pub states: BTreeMap<PortState, usize>,
+}
+
+/// The options that decide which sockets are scanned and in which order.
+/// A scan can only be resumed with the very same ones.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ScanSpec {
+    pub addresses: Vec<String>,
+    /// The first and last address of each excluded range, as resolved when
+    /// the scan started. A host name or an `--exclude-file` that changed
+    /// since then would leave other hosts out.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub excluded: Vec<(IpAddr, IpAddr)>,
+    pub ports: Option<Vec<u16>>,
+    pub range: Option<PortRange>,
+    pub exclude_ports: Vec<u16>,
+    pub scan_order: ScanOrder,
+    pub seed: u64,
+    pub shard: Option<Shard>,
+    pub udp: bool,
+}
+
+/// The results of a single host so far.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HostProgress {
+    pub ip: IpAddr,
+    pub open_ports: Vec<u16>,
+    /// The number of its ports in each state, except the silent one.
+    pub states: BTreeMap<PortState, usize>,
+    /// What `--banners`, `--tls` and `--services` found out about its open
+    /// ports.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub details: BTreeMap<u16, PortDetails>,
+}
+
+impl Checkpoint {
+    pub fn new(
+        scan: ScanSpec,
+        (position, done): (u128, Vec<u128>),
+        hosts: Vec<HostProgress>,
+        states: BTreeMap<PortState, usize>,
+    ) -> Self {
+        Self {
+            version: CHECKPOINT_VERSION,
+            scan,
+            position,
+            done,
+            hosts,
+            states,
+        }
+    }
+
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let reader = BufReader::new(File::open(path)?);
+        let checkpoint: Self = serde_json::from_reader(reader)?;
+        if checkpoint.version != CHECKPOINT_VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "checkpoint version {} is not supported, expected {}",
+                    checkpoint.version, CHECKPOINT_VERSION
+                ),
+            ));
+        }
+        Ok(checkpoint)
+    }
+
+    /// Replaces the checkpoint at `path` with this one, atomically.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut file = AtomicFile::create(path)?;
+        serde_json::to_writer(&mut file, self)?;
+        file.persist()
+    }
+}
+
+/// Saves the checkpoints it is sent to a file, in the background.
+///
+/// Only the latest checkpoint matters, so the ones sent while an earlier
+/// one is being saved are skipped but for the newest.
+#[derive(Debug)]
+pub struct CheckpointWriter {
+    sender: Sender<Checkpoint>,
+    thread: JoinHandle<()>,
+}
+
+impl CheckpointWriter {
+    /// Starts the thread saving to `path`. A checkpoint that can't be saved
+    /// is handed to `on_error` with the error, and the next one is tried.
+    pub fn spawn(path: PathBuf, on_error: impl Fn(&Path, io::Error) + Send + 'static) -> Self {
+        let (sender, receiver) = mpsc::channel::<Checkpoint>();
+        let thread = thread::spawn(move || {
+            while let Ok(mut checkpoint) = receiver.recv() {
+                while let Ok(newer) = receiver.try_recv() {
+                    checkpoint = newer;
+                }
+                if let Err(e) = checkpoint.save(&path) {
+                    on_error(&path, e);
+                }
+            }
+        });
+        Self { sender, thread }
+    }
+
+    /// Has `checkpoint` saved, without waiting for it.
+    pub fn save(&self, checkpoint: Checkpoint) {
+        // The thread only ends once the sender is dropped.
+        let _ = self.sender.send(checkpoint);
+    }
+
+    /// Waits for the checkpoints sent so far to be saved.
+    pub fn finish(self) {
+        drop(self.sender);
+        if self.thread.join().is_err() {
+            error!("The checkpoint writer panicked");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Checkpoint, CheckpointWriter, HostProgress, ScanSpec};
+    use crate::input::{PortRange, ScanOrder, Shard};
+    use crate::scanner::{PortDetails, PortState, TlsInfo};
+    use crate::service::Service;
+    use std::collections::BTreeMap;
+    use std::fs;
+    use std::sync::{Arc, Mutex};
+
+    fn scan() -> ScanSpec {
+        ScanSpec {
+            addresses: vec!["10.0.0.0/8".to_owned()],
+            excluded: vec![("10.0.0.0".parse().unwrap(), "10.0.0.255".parse().unwrap())],
+            ports: None,
+            range: Some(PortRange {
+                start: 1,
+                end: 65_535,
+            }),
+            exclude_ports: vec![9100],
+            scan_order: ScanOrder::Interleaved,
+            seed: u64::MAX,
+            shard: Some(Shard { index: 2, count: 3 }),
+            udp: false,
+        }
+    }
+
+    #[test]
+    fn checkpoint_survives_a_round_trip() {
+        let states: BTreeMap<PortState, usize> = vec![(PortState::Open, 1), (PortState::Closed, 3)]
+            .into_iter()
+            .collect();
+        let checkpoint = Checkpoint::new(
+            scan(),
+            (1 << 70, vec![(1 << 70) + 2]),
+            vec![HostProgress {
+                ip: "10.0.0.1".parse().unwrap(),
+                open_ports: vec![22],
+                states: states.clone(),
+                details: vec![
+                    (
+                        22,
+                        PortDetails {
+                            banner: Some("SSH-2.0-OpenSSH_9.6".to_owned()),
+                            tls: None,
+                            service: Some(Service {
+                                name: "ssh".to_owned(),
+                                product: Some("OpenSSH".to_owned()),
+                                version: Some("9.6".to_owned()),
+                                info: None,
+                            }),
+                        },
+                    ),
+                    (
+                        443,
+                        PortDetails {
+                            tls: Some(TlsInfo {
+                                version: "TLSv1.2".to_owned(),
+                                certificate: None,
+                            }),
+                            ..PortDetails::default()
+                        },
+                    ),
+                ]
+                .into_iter()
+                .collect(),
+            }],
+            states,
+        );
+
+        let path = std::env::temp_dir().join(format!("rustscan-checkpoint-{}", std::process::id()));
+        checkpoint.save(&path).unwrap();
+        let loaded = Checkpoint::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded, checkpoint);
+    }
+
+    #[test]
+    fn writer_saves_the_latest_checkpoint() {
+        let path =
+            std::env::temp_dir().join(format!("rustscan-checkpoint-writer-{}", std::process::id()));
+        let writer = CheckpointWriter::spawn(path.clone(), |_, e| panic!("{}", e));
+        for position in 1..=10 {
+            writer.save(Checkpoint::new(
+                scan(),
+                (position, vec![]),
+                vec![],
+                BTreeMap::new(),
+            ));
+        }
+        writer.finish();
+        let loaded = Checkpoint::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.position, 10);
+    }
+
+    #[test]
+    fn writer_reports_the_checkpoints_it_cant_save() {
+        let path = std::env::temp_dir()
+            .join("rustscan-no-such-directory")
+            .join("checkpoint");
+        let errors = Arc::new(Mutex::new(Vec::new()));
+        let seen = Arc::clone(&errors);
+        let writer = CheckpointWriter::spawn(path.clone(), move |path, e| {
+            seen.lock().unwrap().push((path.to_owned(), e.kind()));
+        });
+        writer.save(Checkpoint::new(
+            scan(),
+            (1, vec![]),
+            vec![],
+            BTreeMap::new(),
+        ));
+        writer.finish();
+
+        let errors = errors.lock().unwrap();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].0, path);
+    }
+}
@@ -0,0 +1,109 @@
+//! ICMP echo requests through unprivileged ping sockets.
+//!
+//! Raw ICMP sockets need root, but Linux (within `net.ipv4.ping_group_range`)
+//! and macOS hand out datagram sockets that only send echo requests and only
+//! receive the replies to them. Where there are none, discovery makes do
+//! with TCP.
+use crate::scanner::Source;
+use async_std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+
+const ECHO_REQUEST_V4: u8 = 8;
+const ECHO_REPLY_V4: u8 = 0;
+const ECHO_REQUEST_V6: u8 = 128;
+const ECHO_REPLY_V6: u8 = 129;
+
+const PAYLOAD: &[u8] = b"rustscan";
+
+/// Whether ping sockets of the family of `ip` can be opened from `source`.
+pub(super) fn available(source: &Source, ip: IpAddr) -> bool {
+    source.ping_socket(ip).is_ok()
+}
+
+/// Sends an echo request to `ip` and waits up to `timeout` for the reply.
+pub(super) async fn ping(source: &Source, ip: IpAddr, timeout: Duration) -> io::Result<()> {
+    let socket = source.ping_socket(ip)?;
+    io::timeout(timeout, async {
+        socket.connect(SocketAddr::new(ip, 0)).await?;
+        socket.send(&echo_request(ip.is_ipv6())).await?;
+        let mut buf = [0; 1500];
+        loop {
+            let len = socket.recv(&mut buf).await?;
+            if is_echo_reply(&buf[..len], ip.is_ipv6()) {
+                return Ok(());
+            }
+        }
+    })
+    .await
+}
+
+fn echo_request(v6: bool) -> Vec<u8> {
+    let kind = if v6 { ECHO_REQUEST_V6 } else { ECHO_REQUEST_V4 };
+    // The identifier is left to the OS, it tells the replies apart with it.
+    let mut packet = vec![kind, 0, 0, 0, 0, 0, 0, 1];
+    packet.extend_from_slice(PAYLOAD);
+    // The OS fills in the checksum of ICMPv6, which covers the IP header.
+    if !v6 {
+        let checksum = checksum(&packet);
+        packet[2..4].copy_from_slice(&checksum.to_be_bytes());
+    }
+    packet
+}
+
+/// The internet checksum of RFC 1071.
+pub(crate) fn checksum(data: &[u8]) -> u16 {
+    let mut sum: u32 = data
+        .chunks(2)
+        .map(|pair| u32::from(u16::from_be_bytes([pair[0], *pair.get(1).unwrap_or(&0)])))
+        .sum();
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+fn is_echo_reply(packet: &[u8], v6: bool) -> bool {
+    // macOS hands out the IPv4 replies with their IP header.
+    let packet = match packet.first() {
+        Some(first) if !v6 && first >> 4 == 4 => packet
+            .get(usize::from(first & 0x0f) * 4..)
+            .unwrap_or_default(),
+        _ => packet,
+    };
+    let reply = if v6 { ECHO_REPLY_V6 } else { ECHO_REPLY_V4 };
+    packet.first() == Some(&reply)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{checksum, echo_request, is_echo_reply};
+
+    #[test]
+    fn echo_requests_carry_a_valid_checksum() {
+        let request = echo_request(false);
+        assert_eq!(request[0], 8);
+        assert_eq!(checksum(&request), 0);
+        // Odd lengths are padded with a zero byte.
+        assert_eq!(checksum(&[0x01]), !0x0100);
+
+        let request = echo_request(true);
+        assert_eq!(request[0], 128);
+        assert_eq!(&request[2..4], &[0, 0]);
+    }
+
+    #[test]
+    fn replies_are_found_behind_an_ip_header() {
+        let reply = [0, 0, 0xf7, 0xff, 0, 0, 0, 1];
+        assert!(is_echo_reply(&reply, false));
+        assert!(!is_echo_reply(&echo_request(false), false));
+
+        let mut with_header = vec![0x45];
+        with_header.extend_from_slice(&[0; 19]);
+        with_header.extend_from_slice(&reply);
+        assert!(is_echo_reply(&with_header, false));
+
+        assert!(is_echo_reply(&[129, 0, 0, 0], true));
+        assert!(!is_echo_reply(&[], true));
+    }
+}
@@ -0,0 +1,209 @@
+//! Host discovery, to only scan the targets that are up.
+//!
+//! Every port of a host that isn't there costs a full timeout, so on a
+//! sparse range nearly the whole scan goes to dead addresses. Discovery
+//! first sends each target a few cheap probes at once: a TCP connection to
+//! each of the [`DISCOVERY_PORTS`], and an ICMP echo request where
+//! unprivileged ping sockets are available. Any answer, a refused
+//! connection included, means the host is up. Before a SYN scan, the TCP
+//! probes are SYNs too, so no handshake completes there either.
+use crate::address::Targets;
+use crate::scanner::Source;
+#[cfg(feature = "syn")]
+use crate::scanner::SynScan;
+use async_std::io;
+use futures::future::{self, FutureExt, LocalBoxFuture};
+use futures::stream::{FuturesUnordered, StreamExt};
+use log::debug;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+pub(crate) mod icmp;
+
+/// The ports connected to on every target, the ones most often open or
+/// at least not dropped by firewalls.
+pub const DISCOVERY_PORTS: [u16; 5] = [80, 443, 22, 445, 3389];
+
+/// Finds which of the targets are up.
+#[derive(Debug)]
+pub struct Discovery {
+    ports: Vec<u16>,
+    batch_size: u16,
+    timeout: Duration,
+    source: Source,
+    stop: Arc<AtomicBool>,
+    #[cfg(feature = "syn")]
+    syn: Option<SynScan>,
+}
+
+impl Discovery {
+    /// Probes at most `batch_size` sockets at a time, and waits up to
+    /// `timeout` for each to answer.
+    pub fn new(batch_size: u16, timeout: Duration) -> Self {
+        Self {
+            ports: DISCOVERY_PORTS.to_vec(),
+            batch_size,
+            timeout,
+            source: Source::default(),
+            stop: Arc::new(AtomicBool::new(false)),
+            #[cfg(feature = "syn")]
+            syn: None,
+        }
+    }
+
+    /// Connects to `ports` instead of the [`DISCOVERY_PORTS`].
+    #[must_use]
+    pub fn with_ports(mut self, ports: Vec<u16>) -> Self {
+        self.ports = ports;
+        self
+    }
+
+    /// Sends the probes from `source`, like the scan after it.
+    #[must_use]
+    pub fn with_source(mut self, source: Source) -> Self {
+        self.source = source;
+        self
+    }
+
+    /// Sends SYNs through `syn` instead of connecting to the ports.
+    #[cfg(feature = "syn")]
+    #[must_use]
+    pub fn with_syn_scan(mut self, syn: SynScan) -> Self {
+        self.syn = Some(syn);
+        self
+    }
+
+    /// Stops probing new hosts once `stop` is set, the hosts found up
+    /// until then are returned.
+    #[must_use]
+    pub fn with_stop_signal(mut self, stop: Arc<AtomicBool>) -> Self {
+        self.stop = stop;
+        self
+    }
+
+    /// The targets that answered any of the probes.
+    pub async fn run(&self, targets: &Targets) -> Targets {
+        let icmp_v4 = icmp::available(&self.source, Ipv4Addr::LOCALHOST.into());
+        let icmp_v6 = icmp::available(&self.source, Ipv6Addr::LOCALHOST.into());
+        debug!("ICMP echo discovery, IPv4: {}, IPv6: {}", icmp_v4, icmp_v6);
+
+        // Every host in flight takes a socket per probe.
+        let probes = self.ports.len() + 1;
+        let hosts_at_once = std::cmp::max(usize::from(self.batch_size) / probes, 1);
+
+        let mut hosts = targets.iter();
+        let mut pending = FuturesUnordered::new();
+        let mut up = Targets::new();
+        loop {
+            while pending.len() < hosts_at_once && !self.stop.load(Ordering::Relaxed) {
+                let Some(ip) = hosts.next() else { break };
+                let icmp = if ip.is_ipv4() { icmp_v4 } else { icmp_v6 };
+                pending.push(self.probe(ip, icmp));
+            }
+            match pending.next().await {
+                Some((ip, true)) => up.push(ip.into()),
+                Some((_, false)) => {}
+                None => break,
+            }
+        }
+        up
+    }
+
+    /// Whether `ip` answers any of the probes, sent all at once.
+    async fn probe(&self, ip: IpAddr, icmp: bool) -> (IpAddr, bool) {
+        let mut probes: Vec<LocalBoxFuture<'_, io::Result<()>>> = self
+            .ports
+            .iter()
+            .map(|&port| self.tcp_ping(SocketAddr::new(ip, port)).boxed_local())
+            .collect();
+        if icmp {
+            probes.push(icmp::ping(&self.source, ip, self.timeout).boxed_local());
+        }
+        if probes.is_empty() {
+            return (ip, false);
+        }
+        (ip, future::select_ok(probes).await.is_ok())
+    }
+
+    async fn tcp_ping(&self, socket: SocketAddr) -> io::Result<()> {
+        // A SYN/ACK and a RST both come from a host that is up.
+        #[cfg(feature = "syn")]
+        if let Some(syn) = &self.syn {
+            return syn.probe(socket, self.timeout).await.map(|_| ());
+        }
+        match io::timeout(self.timeout, self.source.connect(socket)).await {
+            Ok(_) => Ok(()),
+            // Only a host that is up resets the connection.
+            Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{icmp, Discovery};
+    use crate::address::Targets;
+    use crate::scanner::Source;
+    use crate::test_utils::closed_port;
+    use async_std::task::block_on;
+    use std::net::IpAddr;
+    use std::sync::atomic::AtomicBool;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[test]
+    fn hosts_refusing_connections_are_up() {
+        let targets: Targets = vec!["127.0.0.1".parse::<IpAddr>().unwrap()]
+            .into_iter()
+            .collect();
+        let discovery =
+            Discovery::new(100, Duration::from_millis(500)).with_ports(vec![closed_port()]);
+
+        let up = block_on(discovery.run(&targets));
+        assert_eq!(up.len(), 1);
+        assert!(up.contains("127.0.0.1".parse().unwrap()));
+    }
+
+    #[cfg(feature = "syn")]
+    #[test]
+    #[ignore = "needs CAP_NET_RAW"]
+    fn hosts_resetting_syns_are_up() {
+        let targets: Targets = vec!["127.0.0.1".parse::<IpAddr>().unwrap()]
+            .into_iter()
+            .collect();
+        let syn = crate::scanner::SynScan::new(&Source::default()).unwrap();
+        let discovery = Discovery::new(100, Duration::from_millis(500))
+            .with_ports(vec![closed_port()])
+            .with_syn_scan(syn);
+
+        assert_eq!(block_on(discovery.run(&targets)).len(), 1);
+    }
+
+    #[test]
+    fn hosts_answering_pings_are_up() {
+        let localhost: IpAddr = "127.0.0.1".parse().unwrap();
+        // Only where the OS hands out ping sockets.
+        if !icmp::available(&Source::default(), localhost) {
+            return;
+        }
+        let targets: Targets = vec![localhost].into_iter().collect();
+        let discovery = Discovery::new(100, Duration::from_millis(500)).with_ports(vec![]);
+
+        assert!(block_on(discovery.run(&targets)).contains(localhost));
+    }
+
+    #[test]
+    fn stopped_discovery_probes_nothing() {
+        let targets: Targets = vec!["127.0.0.1".parse::<IpAddr>().unwrap()]
+            .into_iter()
+            .collect();
+        let discovery = Discovery::new(100, Duration::from_millis(500))
+            .with_ports(vec![closed_port()])
+            .with_stop_signal(Arc::new(AtomicBool::new(true)));
+
+        assert!(block_on(discovery.run(&targets)).is_empty());
+    }
+}
@@ -1,7 +1,9 @@
 //! Provides a means to read, parse and hold configuration options for scans.
-use serde_derive::Deserialize;
+use crate::port_strategy::PortSpec;
+use serde_derive::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::fs;
+use std::net::IpAddr;
 use std::path::PathBuf;
 use structopt::{clap::arg_enum, StructOpt};
 
@@ -12,10 +14,13 @@ arg_enum! {
     /// Represents the strategy in which the port scanning will run.
     ///   - Serial will run from start to end, for example 1 to 1_000.
     ///   - Random will randomize the order in which ports will be scanned.
-    #[derive(Deserialize, Debug, StructOpt, Clone, Copy, PartialEq, Eq)]
+    ///   - Interleaved will randomize the order of every IP and port combination,
+    ///     so neither the hosts nor the ports are scanned one after the other.
+    #[derive(Deserialize, Serialize, Debug, StructOpt, Clone, Copy, PartialEq, Eq)]
     pub enum ScanOrder {
         Serial,
         Random,
+        Interleaved,
     }
 }
 
@@ -32,8 +37,23 @@ arg_enum! {
     }
 }
 
+arg_enum! {
+    /// Represents the format the scan results are reported in.
+    ///   - text is the human friendly terminal output, or the greppable `ip -> [ports]` lines.
+    ///   - json writes one JSON document with every host once the run is over.
+    ///   - ndjson writes one JSON object per line as soon as each host is done.
+    ///   - xml writes the same document as nmap's `-oX` once the run is over.
+    #[derive(Deserialize, Debug, StructOpt, Clone, Copy, PartialEq, Eq)]
+    pub enum OutputFormat {
+        Text,
+        Json,
+        Ndjson,
+        Xml,
+    }
+}
+
 /// Represents the range of ports to be scanned.
-#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
 pub struct PortRange {
     pub start: u16,
     pub end: u16,
@@ -63,6 +83,29 @@ fn parse_range(input: &str) -> Result<PortRange, String> {
     }
 }
 
+/// Represents the part of the sockets a run takes when a scan is split
+/// across several runs, `index` counts from 1 to `count`.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Shard {
+    pub index: u32,
+    pub count: u32,
+}
+
+#[cfg(not(tarpaulin_include))]
+fn parse_shard(input: &str) -> Result<Shard, String> {
+    let error = || String::from("the shard format must be 'index/count'. Example: 2/4.");
+    let (index, count) = input.split_once('/').ok_or_else(error)?;
+    let shard = Shard {
+        index: index.trim().parse().map_err(|_| error())?,
+        count: count.trim().parse().map_err(|_| error())?,
+    };
+
+    if shard.index == 0 || shard.index > shard.count {
+        return Err(error());
+    }
+    Ok(shard)
+}
+
 #[derive(StructOpt, Debug, Clone)]
 #[structopt(name = "rustscan", setting = structopt::clap::AppSettings::TrailingVarArg)]
 #[allow(clippy::struct_excessive_bools)]
@@ -76,9 +119,22 @@ pub struct Opts {
     #[structopt(short, long, use_delimiter = true)]
     pub addresses: Vec<String>,
 
-    /// A list of comma separated ports to be scanned. Example: 80,443,8080.
-    #[structopt(short, long, use_delimiter = true)]
-    pub ports: Option<Vec<u16>>,
+    /// A comma-delimited list of CIDRs, IPs, or hosts not to be scanned,
+    /// even when they are part of the addresses.
+    #[structopt(long, use_delimiter = true)]
+    pub exclude: Vec<String>,
+
+    /// A newline-delimited file of CIDRs, IPs, or hosts not to be scanned,
+    /// like --exclude. Lines starting with # are skipped.
+    #[structopt(long, parse(from_os_str))]
+    pub exclude_file: Option<PathBuf>,
+
+    /// The ports to be scanned, in the syntax of nmap's -p: ports, ranges
+    /// and service names, separated by commas. "-" is every port, "[1-1024]"
+    /// the ports of the known services in the range, and "T:" or "U:" make
+    /// the ports after it TCP or UDP only. Example: 22,80,8000-8100,U:53,T:443.
+    #[structopt(short, long, parse(try_from_str = PortSpec::parse))]
+    pub ports: Option<PortSpec>,
 
     /// A range of ports with format start-end. Example: 1-1000.
     #[structopt(short, long, conflicts_with = "ports", parse(try_from_str = parse_range))]
@@ -120,24 +176,70 @@ pub struct Opts {
     #[structopt(long, default_value = "1")]
     pub tries: u8,
 
+    /// Measure the round-trip time of every host and derive its timeout from
+    /// it, like nmap does. The --timeout is used until a host answers.
+    #[structopt(long)]
+    pub adaptive_timeout: bool,
+
+    /// The lowest timeout in milliseconds the adaptive timeout goes down to.
+    /// Defaults to 100.
+    #[structopt(long)]
+    pub min_rtt_timeout: Option<u32>,
+
+    /// The highest timeout in milliseconds the adaptive timeout goes up to.
+    /// Defaults to 10000.
+    #[structopt(long)]
+    pub max_rtt_timeout: Option<u32>,
+
     /// Automatically ups the ULIMIT with the value you provided.
     #[structopt(short, long)]
     pub ulimit: Option<u64>,
 
     /// The order of scanning to be performed. The "serial" option will
     /// scan ports in ascending order while the "random" option will scan
-    /// ports randomly.
+    /// ports randomly. The "interleaved" option scans every IP and port
+    /// combination in a random order, mixing hosts and ports together.
     #[structopt(long, possible_values = &ScanOrder::variants(), case_insensitive = true, default_value = "serial")]
     pub scan_order: ScanOrder,
 
+    /// The seed of the "random" and "interleaved" scan orders. The same seed
+    /// scans in the exact same order again. A random seed is used and
+    /// printed when it is not given.
+    #[structopt(long)]
+    pub seed: Option<u64>,
+
+    /// Only scan one part of the IP and port combinations, to split a scan
+    /// across several machines. Every run takes the same targets and ports
+    /// with its own index, e.g. 1/3, 2/3 and 3/3, and together they scan
+    /// every combination exactly once. The random scan orders need the same
+    /// --seed on every run.
+    #[structopt(long, parse(try_from_str = parse_shard))]
+    pub shard: Option<Shard>,
+
+    /// Periodically save the progress and the results of the scan to this
+    /// file, to continue it with --resume if it gets interrupted.
+    #[structopt(long, parse(from_os_str))]
+    pub resume_file: Option<PathBuf>,
+
+    /// Continue the scan saved in the --resume-file instead of starting
+    /// over. It needs the same targets, ports and scan order options.
+    #[structopt(long)]
+    pub resume: bool,
+
     /// Level of scripting required for the run.
     #[structopt(long, possible_values = &ScriptsRequired::variants(), case_insensitive = true, default_value = "default")]
     pub scripts: ScriptsRequired,
 
-    /// Use the top 1000 ports.
+    /// Use the ports of the [ports] table of the configuration file, the
+    /// top 1000 ports in the default one.
     #[structopt(long)]
     pub top: bool,
 
+    /// Scan the N ports most often found open, the most frequent first,
+    /// from the port frequencies built into RustScan.
+    #[structopt(long, value_name = "N", conflicts_with_all = &["ports", "range", "top"])]
+    pub top_ports: Option<u16>,
+
     /// The Script arguments to run.
     /// To use the argument -A, end RustScan's args with '-- -A'.
     /// Example: 'rustscan -t 1500 -a 127.0.0.1 -- -A -sC'.
@@ -146,9 +248,104 @@ pub struct Opts {
     #[structopt(last = true)]
     pub command: Vec<String>,
 
-    /// A list of comma separated ports to be excluded from scanning. Example: 80,443,8080.
-    #[structopt(short, long, use_delimiter = true)]
-    pub exclude_ports: Option<Vec<u16>>,
+    /// The ports to be excluded from scanning, in the syntax of --ports.
+    /// Example: 80,443,8080.
+    #[structopt(short, long, parse(try_from_str = PortSpec::parse))]
+    pub exclude_ports: Option<PortSpec>,
+
+    /// UDP scanning mode. Sends a protocol specific probe to each port and
+    /// reports the ports that answer back as open.
+    #[structopt(long)]
+    pub udp: bool,
+
+    /// The format of the scan results. "json" and "ndjson" print machine
+    /// readable results with the hosts, open ports, script results and
+    /// timings, "xml" prints them in nmap's XML format. All of them turn off
+    /// the other terminal output.
+    #[structopt(long, possible_values = &OutputFormat::variants(), case_insensitive = true, default_value = "text")]
+    pub output_format: OutputFormat,
+
+    /// Write the scan results in the selected output format to this file
+    /// instead of stdout, the terminal keeps the human friendly output.
+    /// The file is only replaced once the run is over.
+    #[structopt(short, long, parse(from_os_str))]
+    pub output: Option<PathBuf>,
+
+    /// The most connections started per second, over all hosts. Unlike the
+    /// batch size, this limits the rate and not how many are in flight.
+    #[structopt(long)]
+    pub max_rate: Option<u32>,
+
+    /// The fewest connections started per second. Goes past the batch size
+    /// when the scan falls behind this rate.
+    #[structopt(long)]
+    pub min_rate: Option<u32>,
+
+    /// The most connections started per second to a single host.
+    #[structopt(long)]
+    pub max_host_rate: Option<u32>,
+
+    /// Make every connection through a proxy, socks5://host:port or
+    /// http://host:port. Scripts get it in the RUSTSCAN_PROXY environment
+    /// variable. Only works with TCP scans.
+    #[structopt(long)]
+    pub proxy: Option<String>,
+
+    /// The local address the connections leave from, instead of the one
+    /// the OS picks. It has to be an address of a local interface.
+    #[structopt(long)]
+    pub source_ip: Option<IpAddr>,
+
+    /// The network interface the connections leave from, e.g. eth1.
+    #[structopt(long)]
+    pub interface: Option<String>,
+
+    /// The local port every connection leaves from. Connections to the
+    /// same target can't share it, so it slows down retries.
+    #[structopt(long)]
+    pub source_port: Option<u16>,
+
+    /// Scan every target, without first checking which hosts are up. By
+    /// default a host is only scanned when it answers a ping or a
+    /// connection to one of a few common ports.
+    #[structopt(long)]
+    pub skip_discovery: bool,
+
+    /// SYN scanning mode. Sends only the first packet of the handshake and
+    /// tells the state from the answer, without ever connecting, host
+    /// discovery included. Needs raw sockets, so root or CAP_NET_RAW on
+    /// Linux, and a build with the "syn" feature.
+    #[structopt(long)]
+    pub syn: bool,
+
+    /// Read the banner of every open port, what the service sends first or
+    /// answers to a generic request, and show it with the port. Only works
+    /// with connect scans.
+    #[structopt(long)]
+    pub banners: bool,
+
+    /// How long in milliseconds to wait for the banner of an open port, the
+    /// generic request included. Defaults to 500.
+    #[structopt(long)]
+    pub banner_timeout: Option<u32>,
+
+    /// Try a TLS handshake on every open port, and show the protocol
+    /// version and certificate of the ones that speak TLS. Only TLS 1.2 and
+    /// 1.3 are offered, ports that only speak older versions go undetected.
+    /// Only works with TCP scans.
+    #[structopt(long)]
+    pub tls: bool,
+
+    /// Send probes to every open port to tell the service on it, and its
+    /// product and version when they show.
+    #[structopt(long)]
+    pub services: bool,
+
+    /// Read the probes of --services from this file, in the format of
+    /// nmap-service-probes, instead of using the built-in ones. Implies
+    /// --services.
+    #[structopt(long, parse(from_os_str))]
+    pub service_probes: Option<PathBuf>,
 }
 
 #[cfg(not(tarpaulin_include))]
@@ -187,8 +384,24 @@ impl Opts {
         }
 
         merge_required!(
-            addresses, greppable, accessible, batch_size, timeout, tries, scan_order, scripts,
-            command
+            addresses,
+            exclude,
+            greppable,
+            accessible,
+            batch_size,
+            timeout,
+            tries,
+            scan_order,
+            scripts,
+            command,
+            udp,
+            output_format,
+            adaptive_timeout,
+            skip_discovery,
+            syn,
+            banners,
+            tls,
+            services
         );
     }
 
@@ -203,16 +416,44 @@ impl Opts {
             }
         }
 
-        // Only use top ports when the user asks for them
-        if self.top && config.ports.is_some() {
-            let mut ports: Vec<u16> = Vec::with_capacity(config.ports.clone().unwrap().len());
-            for entry in config.ports.clone().unwrap().keys() {
-                ports.push(entry.parse().unwrap());
-            }
-            self.ports = Some(ports);
+        // Ports picked on the command line win over the configured
+        // top_ports, as they conflict with --top-ports there.
+        let ports_picked =
+            self.ports.is_some() || self.range.is_some() || self.top || self.top_ports.is_some();
+        if !ports_picked && config.top_ports.is_some() {
+            self.top_ports = config.top_ports;
+        }
+
+        // Only use top ports when the user asks for them, the keys that are
+        // not port numbers are skipped.
+        if let (true, Some(top)) = (self.top, &config.ports) {
+            let mut ports: Vec<u16> = top.keys().filter_map(|key| key.parse().ok()).collect();
+            ports.sort_unstable();
+            self.ports = Some(PortSpec::from(ports));
         }
 
-        merge_optional!(range, resolver, ulimit, exclude_ports);
+        merge_optional!(
+            range,
+            resolver,
+            ulimit,
+            exclude_ports,
+            output,
+            max_rate,
+            min_rate,
+            max_host_rate,
+            min_rtt_timeout,
+            max_rtt_timeout,
+            banner_timeout,
+            seed,
+            shard,
+            resume_file,
+            proxy,
+            source_ip,
+            interface,
+            source_port,
+            service_probes,
+            exclude_file
+        );
     }
 }
 
@@ -220,22 +461,49 @@ impl Default for Opts {
     fn default() -> Self {
         Self {
             addresses: vec![],
+            exclude: vec![],
+            exclude_file: None,
             ports: None,
             range: None,
             greppable: true,
             batch_size: 0,
             timeout: 0,
             tries: 0,
+            adaptive_timeout: false,
+            min_rtt_timeout: None,
+            max_rtt_timeout: None,
             ulimit: None,
             command: vec![],
             accessible: false,
             resolver: None,
             scan_order: ScanOrder::Serial,
+            seed: None,
+            shard: None,
+            resume_file: None,
+            resume: false,
             no_config: true,
             top: false,
             scripts: ScriptsRequired::Default,
             config_path: None,
             exclude_ports: None,
+            udp: false,
+            output_format: OutputFormat::Text,
+            output: None,
+            max_rate: None,
+            min_rate: None,
+            max_host_rate: None,
+            proxy: None,
+            source_ip: None,
+            interface: None,
+            source_port: None,
+            skip_discovery: false,
+            syn: false,
+            banners: false,
+            banner_timeout: None,
+            tls: false,
+            services: false,
+            service_probes: None,
+            top_ports: None,
         }
     }
 }
@@ -254,12 +522,38 @@ pub struct Config {
     batch_size: Option<u16>,
     timeout: Option<u32>,
     tries: Option<u8>,
+    adaptive_timeout: Option<bool>,
+    min_rtt_timeout: Option<u32>,
+    max_rtt_timeout: Option<u32>,
     ulimit: Option<u64>,
     resolver: Option<String>,
     scan_order: Option<ScanOrder>,
+    seed: Option<u64>,
+    shard: Option<Shard>,
+    resume_file: Option<PathBuf>,
     command: Option<Vec<String>>,
     scripts: Option<ScriptsRequired>,
-    exclude_ports: Option<Vec<u16>>,
+    exclude_ports: Option<PortSpec>,
+    udp: Option<bool>,
+    output_format: Option<OutputFormat>,
+    output: Option<PathBuf>,
+    max_rate: Option<u32>,
+    min_rate: Option<u32>,
+    max_host_rate: Option<u32>,
+    proxy: Option<String>,
+    source_ip: Option<IpAddr>,
+    interface: Option<String>,
+    source_port: Option<u16>,
+    skip_discovery: Option<bool>,
+    syn: Option<bool>,
+    banners: Option<bool>,
+    banner_timeout: Option<u32>,
+    tls: Option<bool>,
+    services: Option<bool>,
+    service_probes: Option<PathBuf>,
+    top_ports: Option<u16>,
+    exclude: Option<Vec<String>>,
+    exclude_file: Option<PathBuf>,
 }
 
 #[cfg(not(tarpaulin_include))]
@@ -271,19 +565,18 @@ impl Config {
     /// # Format
     ///
     /// addresses = ["127.0.0.1", "127.0.0.1"]
+    /// exclude = ["10.0.0.0/24", "gateway.lan"]
     /// ports = [80, 443, 8080]
     /// greppable = true
     /// scan_order: "Serial"
-    /// exclude_ports = [8080, 9090, 80]
+    /// exclude_ports = [8080, 9090, 80], or "8080,9090,http"
+    /// output = "scan.json"
     ///
     pub fn read(custom_config_path: Option<PathBuf>) -> Self {
         let mut content = String::new();
         let config_path = custom_config_path.unwrap_or_else(default_config_path);
         if config_path.exists() {
-            content = match fs::read_to_string(config_path) {
-                Ok(content) => content,
-                Err(_) => String::new(),
-            }
+            content = fs::read_to_string(config_path).unwrap_or_default();
         }
 
         let config: Config = match toml::from_str(&content) {
@@ -309,7 +602,9 @@ pub fn default_config_path() -> PathBuf {
 
 #[cfg(test)]
 mod tests {
-    use super::{Config, Opts, PortRange, ScanOrder, ScriptsRequired};
+    use super::{
+        parse_shard, Config, Opts, PortRange, PortSpec, ScanOrder, ScriptsRequired, Shard,
+    };
     impl Config {
         fn default() -> Self {
             Self {
@@ -320,13 +615,39 @@ mod tests {
                 batch_size: Some(25_000),
                 timeout: Some(1_000),
                 tries: Some(1),
+                adaptive_timeout: None,
+                min_rtt_timeout: None,
+                max_rtt_timeout: None,
                 ulimit: None,
                 command: Some(vec!["-A".to_owned()]),
                 accessible: Some(true),
                 resolver: None,
                 scan_order: Some(ScanOrder::Random),
+                seed: None,
+                shard: None,
+                resume_file: None,
                 scripts: None,
                 exclude_ports: None,
+                udp: Some(false),
+                output_format: None,
+                output: None,
+                max_rate: None,
+                min_rate: None,
+                max_host_rate: None,
+                proxy: None,
+                source_ip: None,
+                interface: None,
+                source_port: None,
+                skip_discovery: None,
+                syn: None,
+                banners: None,
+                banner_timeout: None,
+                tls: None,
+                services: None,
+                service_probes: None,
+                top_ports: None,
+                exclude: None,
+                exclude_file: None,
             }
         }
     }
@@ -372,11 +693,78 @@ mod tests {
         });
         config.ulimit = Some(1_000);
         config.resolver = Some("1.1.1.1".to_owned());
+        config.max_rate = Some(500);
+        config.proxy = Some("socks5://127.0.0.1:1080".to_owned());
 
         opts.merge_optional(&config);
 
         assert_eq!(opts.range, config.range);
         assert_eq!(opts.ulimit, config.ulimit);
         assert_eq!(opts.resolver, config.resolver);
+        assert_eq!(opts.max_rate, config.max_rate);
+        assert_eq!(opts.proxy, config.proxy);
+    }
+
+    #[test]
+    fn ports_on_the_command_line_win_over_configured_top_ports() {
+        let mut config = Config::default();
+        config.top_ports = Some(100);
+
+        let mut opts = Opts::default();
+        opts.merge_optional(&config);
+        assert_eq!(opts.top_ports, Some(100));
+
+        let mut opts = Opts {
+            ports: Some(PortSpec::from(vec![80])),
+            ..Opts::default()
+        };
+        opts.merge_optional(&config);
+        assert_eq!(opts.top_ports, None);
+
+        let mut opts = Opts {
+            range: Some(PortRange { start: 1, end: 10 }),
+            ..Opts::default()
+        };
+        opts.merge_optional(&config);
+        assert_eq!(opts.top_ports, None);
+
+        let mut opts = Opts {
+            top_ports: Some(10),
+            ..Opts::default()
+        };
+        opts.merge_optional(&config);
+        assert_eq!(opts.top_ports, Some(10));
+    }
+
+    #[test]
+    fn top_skips_the_configured_ports_that_are_not_numbers() {
+        let config: Config =
+            toml::from_str("[ports]\n443 = 1\nhttp = 1\n80 = 1\n70000 = 1").unwrap();
+        let mut opts = Opts {
+            top: true,
+            ..Opts::default()
+        };
+        opts.merge_optional(&config);
+        assert_eq!(opts.ports.unwrap().ports(false), [80, 443]);
+    }
+
+    #[test]
+    fn config_exclude_ports_take_a_list_or_a_spec() {
+        let config: Config = toml::from_str("exclude_ports = [8080, 80]").unwrap();
+        assert_eq!(config.exclude_ports.unwrap().ports(false), [80, 8080]);
+        let config: Config = toml::from_str(r#"exclude_ports = "ssh,U:53""#).unwrap();
+        let spec = config.exclude_ports.unwrap();
+        assert_eq!(spec.ports(false), [22]);
+        assert_eq!(spec.ports(true), [53]);
+    }
+
+    #[test]
+    fn shard_is_parsed_from_index_and_count() {
+        assert_eq!(parse_shard("2/4"), Ok(Shard { index: 2, count: 4 }));
+        assert_eq!(parse_shard("1/1"), Ok(Shard { index: 1, count: 1 }));
+        assert!(parse_shard("0/4").is_err());
+        assert!(parse_shard("5/4").is_err());
+        assert!(parse_shard("2").is_err());
+        assert!(parse_shard("a/b").is_err());
     }
 }
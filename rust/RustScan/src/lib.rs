@@ -53,3 +53,14 @@ pub mod benchmark;
 pub mod scripts;
 
 pub mod address;
+
+pub mod output;
+
+pub mod checkpoint;
+
+pub mod discovery;
+
+pub mod service;
+
+#[cfg(test)]
+mod test_utils;
@@ -3,20 +3,29 @@
 #![allow(clippy::doc_markdown, clippy::if_not_else, clippy::non_ascii_literal)]
 
 use rustscan::benchmark::{Benchmark, NamedTimer};
-use rustscan::input::{self, Config, Opts, ScriptsRequired};
-use rustscan::port_strategy::PortStrategy;
-use rustscan::scanner::Scanner;
-use rustscan::scripts::{init_scripts, Script, ScriptFile};
+use rustscan::checkpoint::{Checkpoint, CheckpointWriter, HostProgress, ScanSpec};
+use rustscan::discovery::Discovery;
+use rustscan::input::{self, Config, Opts, OutputFormat, ScanOrder, ScriptsRequired};
+use rustscan::output::{HostReport, Reporter, ScanInfo, ScriptReport};
+use rustscan::port_strategy::{top_ports, PortStrategy};
+#[cfg(feature = "syn")]
+use rustscan::scanner::SynScan;
+use rustscan::scanner::{PortDetails, PortState, Proxy, RateLimit, ScanResult, Scanner, Source};
+use rustscan::scripts::{init_scripts, Script, ScriptFile, PROTOCOL_ENV_VAR, PROXY_ENV_VAR};
+use rustscan::service::ServiceProbes;
 use rustscan::{detail, funny_opening, output, warning};
 
 use colorful::{Color, Colorful};
 use futures::executor::block_on;
-use std::collections::HashMap;
+use futures::StreamExt;
+use std::collections::{BTreeMap, HashMap};
 use std::net::IpAddr;
 use std::string::ToString;
-use std::time::Duration;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use rustscan::address::parse_addresses;
+use rustscan::address::{parse_addresses_excluding, parse_excluded, Targets};
 
 // *extern crate语句本身并不会被视为"导入"。它只是告诉Rust编译器这个crate存在。实际的导入是通过use语句完成的。
 extern crate colorful;
@@ -27,6 +36,13 @@ extern crate dirs;
 const DEFAULT_FILE_DESCRIPTORS_LIMIT: u64 = 8000;
 // Safest batch size based on experimentation
 const AVERAGE_BATCH_SIZE: u16 = 3000;
+// Bounds of the adaptive timeout, in milliseconds, when not set by the user
+const DEFAULT_MIN_RTT_TIMEOUT: u32 = 100;
+const DEFAULT_MAX_RTT_TIMEOUT: u32 = 10_000;
+// How long to wait for a banner, in milliseconds, when not set by the user
+const DEFAULT_BANNER_TIMEOUT: u32 = 500;
+// How often the progress is saved to the --resume-file
+const CHECKPOINT_INTERVAL: Duration = Duration::from_secs(5);
 
 #[macro_use]    // *同时引入包中定义的宏
 extern crate log;
@@ -46,7 +62,17 @@ fn main() {
 
     debug!("Main() `opts` arguments are {:?}", opts);   // *打印opts参数
 
-    let scripts_to_run: Vec<ScriptFile> = match init_scripts(opts.scripts) {    // *初始化脚本
+    // Greppable mode never runs scripts, decide it before the machine
+    // readable formats below switch greppable mode on.
+    let run_scripts = !opts.greppable && opts.scripts != ScriptsRequired::None;
+
+    // Machine readable output owns stdout, so the rest of the terminal output is
+    // silenced the same way greppable mode does it. Unless it goes to a file.
+    if opts.output.is_none() && opts.output_format != OutputFormat::Text {
+        opts.greppable = true;
+    }
+
+    let scripts_to_run: Vec<ScriptFile> = match init_scripts(opts.scripts, opts.udp) {    // *初始化脚本
         Ok(scripts_to_run) => scripts_to_run,   // *成功返回脚本
         Err(e) => { // *失败
             warning!(
@@ -64,14 +90,22 @@ fn main() {
         print_opening(&opts);
     }
 
-    let ips: Vec<IpAddr> = parse_addresses(&opts);  // *解析IP地址
+    let excluded = match parse_excluded(&opts) {
+        Ok(excluded) => excluded,
+        Err(e) => {
+            warning!(e.to_string(), opts.greppable, opts.accessible);
+            std::process::exit(1);
+        }
+    };
+    let (targets, hostnames) = parse_addresses_excluding(&opts, &excluded);  // *解析IP地址
 
-    if ips.is_empty() { // *如果IP地址为空
-        warning!(
-            "No IPs could be resolved, aborting scan.",
-            opts.greppable,
-            opts.accessible
-        );
+    if targets.is_empty() { // *如果IP地址为空
+        let message = if opts.exclude.is_empty() && opts.exclude_file.is_none() {
+            "No IPs could be resolved, aborting scan."
+        } else {
+            "No IPs could be resolved or they are all excluded, aborting scan."
+        };
+        warning!(message, opts.greppable, opts.accessible);
         std::process::exit(1);
     }
 
@@ -84,64 +118,333 @@ fn main() {
     // Added by wasuaje - 01/26/2024:
     // exclude_ports  is an exclusion port list
     //
-    let scanner = Scanner::new( // *创建扫描器
-        &ips,
+    let rate_limit = rate_limit(&opts);
+    let rtt_bounds = rtt_timeout_bounds(&opts);
+    let proxy = scan_proxy(&opts);
+    let source = scan_source(&opts);
+    if opts.banners && (opts.udp || opts.syn) {
+        warning!(
+            "--banners needs a connect scan, it can't go with --udp or --syn.",
+            opts.greppable,
+            opts.accessible
+        );
+        std::process::exit(1);
+    }
+    if opts.tls && opts.udp {
+        warning!(
+            "--tls needs a TCP scan, it can't go with --udp.",
+            opts.greppable,
+            opts.accessible
+        );
+        std::process::exit(1);
+    }
+    let service_probes = service_probes(&opts);
+    #[cfg(feature = "syn")]
+    let syn = syn_scan(&opts, &source);
+    #[cfg(not(feature = "syn"))]
+    if opts.syn {
+        warning!(
+            "This build of RustScan can't SYN scan, build it with --features syn.",
+            opts.greppable,
+            opts.accessible
+        );
+        std::process::exit(1);
+    }
+    let resumed = if opts.resume {
+        Some(load_checkpoint(&opts))
+    } else {
+        None
+    };
+    let seed = match (&resumed, opts.seed) {
+        (Some(checkpoint), None) => checkpoint.scan.seed,
+        _ => scan_seed(&opts),
+    };
+    let scan_spec = ScanSpec {
+        addresses: opts.addresses.clone(),
+        excluded: excluded.spans().collect(),
+        ports: scan_ports(&opts),
+        range: opts.range.clone(),
+        exclude_ports: opts
+            .exclude_ports
+            .as_ref()
+            .map(|spec| spec.ports(opts.udp).to_vec())
+            .unwrap_or_default(),
+        scan_order: opts.scan_order,
+        seed,
+        shard: opts.shard,
+        udp: opts.udp,
+    };
+    let stop = Arc::new(AtomicBool::new(false));
+    handle_interrupts(Arc::clone(&stop), &opts);
+    // A proxy only tells about the connections of the scan itself, so
+    // through one every target is scanned.
+    let live_hosts = if opts.skip_discovery || proxy.is_some() {
+        None
+    } else {
+        let mut discovery_bench = NamedTimer::start("Discovery");
+        let live_hosts = discover_hosts(
+            &opts,
+            &targets,
+            batch_size,
+            &source,
+            &stop,
+            #[cfg(feature = "syn")]
+            syn.as_ref(),
+        );
+        discovery_bench.end();
+        benchmarks.push(discovery_bench);
+        Some(live_hosts)
+    };
+    let mut scanner = Scanner::new( // *创建扫描器
+        targets.clone(),
         batch_size,
         Duration::from_millis(opts.timeout.into()), // *超时时间
         opts.tries,
         opts.greppable,
-        PortStrategy::pick(&opts.range, opts.ports, opts.scan_order),
+        PortStrategy::pick_with_seed(&opts.range, scan_spec.ports.clone(), opts.scan_order, seed),
         opts.accessible,
-        opts.exclude_ports.unwrap_or_default(),
-    );
+        scan_spec.exclude_ports.clone(),
+    )
+    .with_udp(opts.udp)
+    .with_rate_limit(rate_limit)
+    .with_stop_signal(stop)
+    .with_source(source);
+    #[cfg(feature = "syn")]
+    if let Some(syn) = syn {
+        scanner = scanner.with_syn_scan(syn);
+    }
+    if let Some((min, max)) = rtt_bounds {
+        scanner = scanner.with_adaptive_timeout(min, max);
+    }
+    if let Some(proxy) = &proxy {
+        scanner = scanner.with_proxy(proxy.clone());
+    }
+    if opts.scan_order == ScanOrder::Interleaved {
+        scanner = scanner.with_interleaved_order(seed);
+    }
+    if let Some(shard) = opts.shard {
+        scanner = scanner.with_shard(shard);
+    }
+    if let Some(live_hosts) = live_hosts {
+        scanner = scanner.with_live_hosts(live_hosts);
+    }
+    if opts.banners {
+        let wait = opts.banner_timeout.unwrap_or(DEFAULT_BANNER_TIMEOUT);
+        scanner = scanner.with_banners(Duration::from_millis(wait.into()));
+    }
+    if opts.tls {
+        scanner = scanner.with_tls_detection(Duration::from_millis(opts.timeout.into()));
+    }
+    if let Some(probes) = service_probes {
+        scanner =
+            scanner.with_service_detection(probes, Duration::from_millis(opts.timeout.into()));
+    }
+    if let Some(checkpoint) = &resumed {
+        if checkpoint.scan != scan_spec {
+            warning!(
+                "The checkpoint to resume is of another scan. Run it with the same targets, ports and scan order options.",
+                opts.greppable,
+                opts.accessible
+            );
+            std::process::exit(1);
+        }
+        scanner = scanner.with_resume(checkpoint.position, checkpoint.done.clone());
+    }
     debug!("Scanner finished building: {:?}", scanner);
 
+    let protocol = if opts.udp { "udp" } else { "tcp" };
+    // Unanswered ports are the bulk of the results on large ranges, they
+    // are only counted per host once the scan is done, as the ports that
+    // got no other state.
+    let silent_state = if opts.udp {
+        PortState::OpenFiltered
+    } else {
+        PortState::Filtered
+    };
+    let mut scan_info = ScanInfo::new(protocol, scanner.port_ranges());
+    if opts.scan_order != ScanOrder::Serial {
+        scan_info = scan_info.with_seed(seed);
+    }
+    if let Some(shard) = opts.shard {
+        scan_info = scan_info.with_shard(shard);
+    }
+    if opts.syn {
+        scan_info = scan_info.with_syn_scan();
+    }
+    let mut reporter = match &opts.output {
+        Some(path) => match Reporter::to_file(opts.output_format, path, scan_info) {
+            Ok(reporter) => reporter,
+            Err(e) => {
+                eprintln!("Could not create the output file {}: {e}", path.display());
+                std::process::exit(1);
+            }
+        },
+        // The text format is already printed as the scan goes.
+        None if opts.output_format == OutputFormat::Text => {
+            Reporter::new(opts.output_format, Box::new(std::io::sink()), scan_info)
+        }
+        None => Reporter::new(opts.output_format, Box::new(std::io::stdout()), scan_info),
+    };
+
+    let mut findings = Findings::default();
+    let mut total_states: HashMap<PortState, usize> = HashMap::new();
+    // A resumed scan starts with the results of the runs before it, their
+    // hosts are reported once their remaining sockets are done.
+    if let Some(checkpoint) = resumed {
+        for host in checkpoint.hosts {
+            scanner.watch(host.ip);
+            findings.restore(host);
+        }
+        total_states = checkpoint.states.into_iter().collect();
+    }
+
+    // The progress is saved in the background while the scan goes on, and
+    // failing to save it is not fatal.
+    let checkpoints = opts.resume_file.clone().map(|path| {
+        let (greppable, accessible) = (opts.greppable, opts.accessible);
+        CheckpointWriter::spawn(path, move |path, e| {
+            warning!(
+                format!("Could not save the progress to {}: {e}", path.display()),
+                greppable,
+                accessible
+            );
+        })
+    });
     let mut portscan_bench = NamedTimer::start("Portscan"); // *计时器,开始计时,名字为Portscan
-    let scan_result = block_on(scanner.run());  // *扫描器运行
+    block_on(async {
+        // Hosts are reported as soon as their last socket is done, while
+        // the scan goes on with the others.
+        let mut scan_results = Box::pin(scanner.stream()); // *扫描器运行
+        let mut last_checkpoint = Instant::now();
+        while let Some(result) = scan_results.next().await { // *遍历扫描结果,将端口号和ip地址放入HashMap
+            *total_states.entry(result.state).or_insert(0) += 1;
+            let ip = result.socket.ip();
+            if findings.record(result, silent_state) {
+                scanner.watch(ip);
+            }
+            for ip in scanner.completed_hosts() {
+                let host = findings.report(
+                    ip,
+                    hostnames.get(&ip).cloned().unwrap_or_default(),
+                    protocol,
+                    silent_state,
+                    scanner.port_count_on(ip),
+                );
+                finish_host(&mut reporter, &opts, host, true, !run_scripts);
+            }
+
+            if let Some(checkpoints) = &checkpoints {
+                if last_checkpoint.elapsed() >= CHECKPOINT_INTERVAL {
+                    checkpoints.save(progress_checkpoint(
+                        &scan_spec,
+                        &scanner,
+                        &findings,
+                        &total_states,
+                    ));
+                    last_checkpoint = Instant::now();
+                }
+            }
+        }
+    });
+    if let Some(checkpoints) = checkpoints {
+        checkpoints.save(progress_checkpoint(
+            &scan_spec,
+            &scanner,
+            &findings,
+            &total_states,
+        ));
+        checkpoints.finish();
+    }
     portscan_bench.end();   // *计时器,结束计时
+    // An interrupted scan still reports what it found, but only the ports
+    // that answered are known for the hosts it didn't finish, so no silent
+    // ports are added to them and the hosts the scan never got an answer
+    // from are left out.
+    let interrupted = scanner.stopped();
+    if interrupted {
+        let mut message = String::from("The scan was interrupted, the results are incomplete.");
+        if opts.resume_file.is_some() {
+            message.push_str(" Run it again with --resume to continue it.");
+        }
+        if opts.greppable {
+            eprintln!("{message}");
+        } else {
+            warning!(message, opts.greppable, opts.accessible);
+        }
+        reporter.mark_incomplete();
+    }
+    let finished = scanner.completed_hosts().into_iter().map(|ip| (ip, true));
+    let unfinished = scanner.unfinished_hosts().into_iter().map(|ip| (ip, false));
+    for (ip, complete) in finished.chain(unfinished) {
+        let host = findings.report(
+            ip,
+            hostnames.get(&ip).cloned().unwrap_or_default(),
+            protocol,
+            silent_state,
+            if complete {
+                scanner.port_count_on(ip)
+            } else {
+                0
+            },
+        );
+        finish_host(&mut reporter, &opts, host, complete, !run_scripts);
+    }
+    if let Some(reduced) = scanner.reduced_batch_size() {
+        warning!(
+            format!("Ran out of file descriptors, the batch size was reduced from {batch_size} to {reduced}. Consider lowering it with -b or raising the limit with --ulimit."),
+            opts.greppable,
+            opts.accessible
+        );
+    }
     benchmarks.push(portscan_bench);    // *将计时器放入benchmarks
 
-    let mut ports_per_ip = HashMap::new();  // *创建HashMap,ip地址和对应的端口号
+    detail!(
+        format!("Port states: {}", format_state_counts(&total_states)),
+        opts.greppable,
+        opts.accessible
+    );
 
-    for socket in scan_result { // *遍历扫描结果,将端口号和ip地址放入HashMap
-        ports_per_ip
-            .entry(socket.ip())
-            .or_insert_with(Vec::new)
-            .push(socket.port());
+    // Only the hosts that answered on some port are reported one by one,
+    // the others are counted, as they can be most of a large range.
+    let silent_hosts = scanner
+        .host_count()
+        .saturating_sub(findings.states.len() as u128);
+    if !interrupted {
+        reporter.silent_hosts(silent_hosts);
     }
 
-    for ip in ips {
-        if ports_per_ip.contains_key(&ip) {
-            continue;
-        }
-
-        // If we got here it means the IP was not found within the HashMap, this
-        // means the scan couldn't find any open ports for it.
-
-        let x = format!("Looks like I didn't find any open ports for {:?}. This is usually caused by a high batch size.
+    if silent_hosts > 0 && !interrupted {
+        let x = format!("Looks like I didn't find any open ports for {} host{}, none of their ports answered. This is usually caused by a high batch size.
         \n*I used {} batch size, consider lowering it with {} or a comfortable number for your system.
-        \n Alternatively, increase the timeout if your ping is high. Rustscan -t 2000 for 2000 milliseconds (2s) timeout.\n",
-        ip,
+        \n Alternatively, increase the timeout if your ping is high. Rustscan -t 2000 for 2000 milliseconds (2s) timeout,
+        \n or let RustScan measure it for every host with --adaptive-timeout.\n",
+        silent_hosts,
+        if silent_hosts == 1 { "" } else { "s" },
         opts.batch_size,
         "'rustscan -b <batch_size> -a <ip address>'");
         warning!(x, opts.greppable, opts.accessible);
-    }   // *如果ip地址没有找到,说明扫描没有找到任何开放的端口
+    }
 
     let mut script_bench = NamedTimer::start("Scripts");    // *计时器,开始计时,名字为Scripts
-    for (ip, ports) in &ports_per_ip {
+    for (ip, ports) in &findings.ports {
         let vec_str_ports: Vec<String> = ports.iter().map(ToString::to_string).collect();
 
         // nmap port style is 80,443. Comma separated with no spaces.
         let ports_str = vec_str_ports.join(",");
 
-        // if option scripts is none, no script will be spawned
-        if opts.greppable || opts.scripts == ScriptsRequired::None {
-            println!("{} -> [{}]", &ip, ports_str);
+        // if option scripts is none, no script will be spawned, nor when
+        // the user asked to stop
+        if !run_scripts || interrupted {
+            // The ports were left for the scripts when the host was done.
+            if run_scripts && opts.output_format == OutputFormat::Text {
+                println!("{} -> [{}]", &ip, ports_str);
+            }
             continue;
         }
         detail!("Starting Script(s)", opts.greppable, opts.accessible);
 
         // Run all the scripts we found and parsed based on the script config file tags field.
+        let mut scripts = Vec::new();
         for mut script_f in scripts_to_run.clone() {
             // This part allows us to add commandline arguments to the Script call_format, appending them to the end of the command.
             if !opts.command.is_empty() {
@@ -162,7 +465,8 @@ fn main() {
             }
 
             // Building the script with the arguments from the ScriptFile, and ip-ports.
-            let script = Script::build(
+            let call_format = script_f.call_format.clone();
+            let mut script = Script::build(
                 script_f.path,
                 *ip,
                 ports.clone(),
@@ -170,16 +474,34 @@ fn main() {
                 script_f.ports_separator,
                 script_f.tags,
                 script_f.call_format,
-            );
+            )
+            .with_env(PROTOCOL_ENV_VAR, protocol);
+            if let Some(proxy) = &proxy {
+                script = script.with_env(PROXY_ENV_VAR, proxy.url());
+            }
             match script.run() {
                 Ok(script_result) => {
-                    detail!(script_result.to_string(), opts.greppable, opts.accessible);
+                    detail!(&script_result, opts.greppable, opts.accessible);
+                    scripts.push(ScriptReport {
+                        call_format,
+                        output: Some(script_result),
+                        error: None,
+                    });
                 }
                 Err(e) => {
                     warning!(&format!("Error {e}"), opts.greppable, opts.accessible);
+                    scripts.push(ScriptReport {
+                        call_format,
+                        output: None,
+                        error: Some(e.to_string()),
+                    });
                 }
             }
         }
+        if let Err(e) = reporter.scripts(*ip, scripts) {
+            eprintln!("Could not write the scan results: {e}");
+            std::process::exit(1);
+        }
     }   // *运行一组脚本,并打印结果
 
     // To use the runtime benchmark, run the process as: RUST_LOG=info ./rustscan
@@ -190,6 +512,487 @@ fn main() {
     benchmarks.push(rustscan_bench);
     debug!("Benchmarks raw {:?}", benchmarks);
     info!("{}", benchmarks.summary());
+
+    if let Err(e) = reporter.finish(&benchmarks) {
+        eprintln!("Could not write the scan results: {e}");
+        std::process::exit(1);
+    }
+    if interrupted {
+        std::process::exit(130);
+    }
+}
+
+/// Stops the scan on the first Ctrl-C, the sockets in flight are waited
+/// for and the results so far are still reported. A second Ctrl-C exits
+/// right away, with nothing reported.
+fn handle_interrupts(stop: Arc<AtomicBool>, opts: &Opts) {
+    let greppable = opts.greppable;
+    let result = ctrlc::set_handler(move || {
+        if stop.swap(true, Ordering::Relaxed) {
+            std::process::exit(130);
+        }
+        if !greppable {
+            eprintln!("Stopping the scan, waiting for the sockets in flight. Press Ctrl-C again to quit now.");
+        }
+    });
+    if let Err(e) = result {
+        warning!(
+            format!("Could not handle Ctrl-C, interrupting the scan will lose its results: {e}"),
+            opts.greppable,
+            opts.accessible
+        );
+    }
+}
+
+/// What the hosts that answered showed so far. Results are folded in as
+/// they arrive, so only the open ports and the state counts are kept in
+/// memory, not one record per socket, and hosts that never answer are not
+/// kept at all.
+#[derive(Default)]
+struct Findings {
+    ports: HashMap<IpAddr, Vec<u16>>,
+    states: HashMap<IpAddr, HashMap<PortState, usize>>,
+    details: HashMap<IpAddr, BTreeMap<u16, PortDetails>>,
+}
+
+impl Findings {
+    /// Folds in a result, and tells whether it is the first answer of its
+    /// host. The `silent` results are only counted once the host is done.
+    fn record(&mut self, result: ScanResult, silent: PortState) -> bool {
+        if result.state == silent {
+            return false;
+        }
+        let ip = result.socket.ip();
+        let first = !self.states.contains_key(&ip);
+        *self
+            .states
+            .entry(ip)
+            .or_default()
+            .entry(result.state)
+            .or_insert(0) += 1;
+
+        if result.state == PortState::Open {
+            self.ports.entry(ip).or_default().push(result.socket.port());
+            if !result.details.is_empty() {
+                self.details
+                    .entry(ip)
+                    .or_default()
+                    .insert(result.socket.port(), result.details);
+            }
+        }
+        first
+    }
+
+    /// Takes back what a host showed in an earlier run.
+    fn restore(&mut self, host: HostProgress) {
+        if !host.open_ports.is_empty() {
+            self.ports.insert(host.ip, host.open_ports);
+        }
+        self.states
+            .insert(host.ip, host.states.into_iter().collect());
+        if !host.details.is_empty() {
+            self.details.insert(host.ip, host.details);
+        }
+    }
+
+    /// The report of `ip`, whose ports that got no answer out of
+    /// `port_count` are added as `silent` ones.
+    fn report(
+        &self,
+        ip: IpAddr,
+        hostnames: Vec<String>,
+        protocol: &'static str,
+        silent: PortState,
+        port_count: usize,
+    ) -> HostReport {
+        HostReport::new(
+            ip,
+            hostnames,
+            self.ports.get(&ip).map_or(&[], Vec::as_slice),
+            protocol,
+            &with_silent_ports(
+                self.states.get(&ip).cloned().unwrap_or_default(),
+                silent,
+                port_count,
+            ),
+        )
+        .with_details(self.details.get(&ip).cloned().unwrap_or_default())
+    }
+
+    /// What every host showed so far, for a checkpoint.
+    fn progress(&self) -> Vec<HostProgress> {
+        self.states
+            .iter()
+            .map(|(ip, states)| HostProgress {
+                ip: *ip,
+                open_ports: self.ports.get(ip).cloned().unwrap_or_default(),
+                states: states.iter().map(|(state, n)| (*state, *n)).collect(),
+                details: self.details.get(ip).cloned().unwrap_or_default(),
+            })
+            .collect()
+    }
+}
+
+/// Reports a host that answered, once all of its sockets are done or,
+/// when `complete` is false, once the scan was interrupted. Its open ports
+/// are printed as `ip -> [ports]` with `print_ports`, and the hosts without
+/// any get a warning.
+fn finish_host(
+    reporter: &mut Reporter,
+    opts: &Opts,
+    host: HostReport,
+    complete: bool,
+    print_ports: bool,
+) {
+    let ip = host.ip;
+    if !host.ports.is_empty() {
+        if print_ports && opts.output_format == OutputFormat::Text {
+            println!("{}", host.greppable());
+        }
+        report_host(reporter, host);
+        return;
+    }
+
+    let unreachable = !host.port_states.is_empty()
+        && host
+            .port_states
+            .keys()
+            .all(|state| *state == PortState::Unreachable);
+    let states = format_state_counts(&host.port_states);
+    report_host(reporter, host);
+    if unreachable {
+        warning!(
+            format!("Host {ip} is unreachable, every connection attempt failed with host or network unreachable."),
+            opts.greppable,
+            opts.accessible
+        );
+        return;
+    }
+
+    // Its other ports may just not have been scanned yet.
+    if !complete {
+        return;
+    }
+
+    // If we got here it means the IP answered but without any open port.
+    let x = format!("Looks like I didn't find any open ports for {:?}. This is usually caused by a high batch size.
+        \n*I used {} batch size, consider lowering it with {} or a comfortable number for your system.
+        \n Alternatively, increase the timeout if your ping is high. Rustscan -t 2000 for 2000 milliseconds (2s) timeout,
+        \n or let RustScan measure it for every host with --adaptive-timeout.\n",
+        ip,
+        opts.batch_size,
+        "'rustscan -b <batch_size> -a <ip address>'");
+    warning!(x, opts.greppable, opts.accessible);
+    detail!(
+        format!("Port states for {ip}: {states}"),
+        opts.greppable,
+        opts.accessible
+    );
+}
+
+/// Adds the ports of a host that got no answer, as `silent` ports, to the
+/// states it recorded.
+fn with_silent_ports(
+    mut states: HashMap<PortState, usize>,
+    silent: PortState,
+    port_count: usize,
+) -> HashMap<PortState, usize> {
+    let answered: usize = states.values().sum();
+    if port_count > answered {
+        *states.entry(silent).or_insert(0) += port_count - answered;
+    }
+    states
+}
+
+/// The seed of the random scan orders, either the user's or a random one.
+/// A random seed is printed so the scan can be replayed with `--seed`, on
+/// stderr when stdout is meant for other programs.
+///
+/// Shards of a random scan order must all use the same seed, so it has to
+/// be given. Exits when it isn't.
+fn scan_seed(opts: &Opts) -> u64 {
+    if let Some(seed) = opts.seed {
+        return seed;
+    }
+    if opts.shard.is_some() && opts.scan_order != ScanOrder::Serial {
+        warning!(
+            "--shard with a random scan order needs the same --seed on every shard.",
+            opts.greppable,
+            opts.accessible
+        );
+        std::process::exit(1);
+    }
+
+    let seed = rand::random();
+    if opts.scan_order != ScanOrder::Serial {
+        let message =
+            format!("Scan order seed {seed}, use --seed {seed} to scan in the same order again.");
+        if opts.greppable {
+            eprintln!("{message}");
+        } else {
+            detail!(message, opts.greppable, opts.accessible);
+        }
+    }
+    seed
+}
+
+/// Reads the checkpoint of the --resume-file. Exits when there is none.
+fn load_checkpoint(opts: &Opts) -> Checkpoint {
+    // The path can come from the config file, so clap can't require it.
+    let Some(path) = opts.resume_file.as_deref() else {
+        eprintln!("--resume needs a --resume-file to continue from.");
+        std::process::exit(1);
+    };
+    match Checkpoint::load(path) {
+        Ok(checkpoint) => checkpoint,
+        Err(e) => {
+            eprintln!("Could not resume from {}: {e}", path.display());
+            std::process::exit(1);
+        }
+    }
+}
+
+/// The progress of the scan and its results so far.
+fn progress_checkpoint(
+    scan: &ScanSpec,
+    scanner: &Scanner,
+    findings: &Findings,
+    total_states: &HashMap<PortState, usize>,
+) -> Checkpoint {
+    let states = total_states.iter().map(|(state, n)| (*state, *n)).collect();
+    Checkpoint::new(
+        scan.clone(),
+        scanner.checkpoint(),
+        findings.progress(),
+        states,
+    )
+}
+
+/// The proxy of the scan, if any. Exits when it can't be parsed or
+/// reached, or when the scan is a UDP one, which can't go through it.
+fn scan_proxy(opts: &Opts) -> Option<Proxy> {
+    let url = opts.proxy.as_deref()?;
+    let exit = |message: String| -> ! {
+        warning!(message, opts.greppable, opts.accessible);
+        std::process::exit(1);
+    };
+
+    if opts.udp {
+        exit(String::from(
+            "--proxy only works with TCP scans, not with --udp.",
+        ));
+    }
+    let proxy = Proxy::parse(url).unwrap_or_else(|e| exit(format!("Invalid --proxy: {e}")));
+    // Every port would look filtered behind a proxy that is down.
+    let timeout = Duration::from_millis(opts.timeout.into()).max(Duration::from_secs(1));
+    if let Err(e) = std::net::TcpStream::connect_timeout(&proxy.addr(), timeout) {
+        exit(format!("Could not connect to the proxy {proxy}: {e}"));
+    }
+    Some(proxy)
+}
+
+/// The ports of --top-ports or -p for the protocol of the scan. Exits when
+/// there is none.
+fn scan_ports(opts: &Opts) -> Option<Vec<u16>> {
+    if let Some(count) = opts.top_ports {
+        if count == 0 {
+            warning!(
+                "--top-ports needs at least 1 port.",
+                opts.greppable,
+                opts.accessible
+            );
+            std::process::exit(1);
+        }
+        return Some(top_ports(count.into(), opts.udp));
+    }
+    let ports = opts.ports.as_ref()?.ports(opts.udp);
+    if ports.is_empty() {
+        let message = if opts.udp {
+            "No UDP ports to scan, only the ones without a prefix or after U: are."
+        } else {
+            "No TCP ports to scan, only the ones without a prefix or after T: are, the U: ones need --udp."
+        };
+        warning!(message, opts.greppable, opts.accessible);
+        std::process::exit(1);
+    }
+    Some(ports.to_vec())
+}
+
+/// The probes of --services, from --service-probes or the built-in ones.
+/// Exits when they can't be read.
+fn service_probes(opts: &Opts) -> Option<ServiceProbes> {
+    let exit = |message: String| -> ! {
+        warning!(message, opts.greppable, opts.accessible);
+        std::process::exit(1);
+    };
+
+    let probes = match &opts.service_probes {
+        Some(path) => ServiceProbes::load(path).unwrap_or_else(|e| {
+            exit(format!(
+                "Could not read the service probes in {}: {e}",
+                path.display()
+            ))
+        }),
+        None if opts.services => ServiceProbes::default(),
+        None => return None,
+    };
+    if opts.udp {
+        exit(String::from(
+            "--services needs a TCP scan, it can't go with --udp.",
+        ));
+    }
+    Some(probes)
+}
+
+/// The local end of the connections. Exits when it isn't one of the local
+/// interfaces.
+fn scan_source(opts: &Opts) -> Source {
+    match Source::local(opts.source_ip, opts.interface.clone(), opts.source_port) {
+        Ok(source) => source,
+        Err(e) => {
+            warning!(
+                format!("Invalid connection source: {e}."),
+                opts.greppable,
+                opts.accessible
+            );
+            std::process::exit(1);
+        }
+    }
+}
+
+/// The targets that answer host discovery.
+fn discover_hosts(
+    opts: &Opts,
+    targets: &Targets,
+    batch_size: u16,
+    source: &Source,
+    stop: &Arc<AtomicBool>,
+    #[cfg(feature = "syn")] syn: Option<&SynScan>,
+) -> Targets {
+    let discovery = Discovery::new(batch_size, Duration::from_millis(opts.timeout.into()))
+        .with_source(source.clone())
+        .with_stop_signal(Arc::clone(stop));
+    #[cfg(feature = "syn")]
+    let discovery = match syn {
+        Some(syn) => discovery.with_syn_scan(syn.clone()),
+        None => discovery,
+    };
+    let live_hosts = block_on(discovery.run(targets));
+
+    let down = targets.len() - live_hosts.len();
+    if live_hosts.is_empty() {
+        warning!(
+            "No host seems up, nothing is scanned. Run with --skip-discovery to scan them anyway.",
+            opts.greppable,
+            opts.accessible
+        );
+    } else if down > 0 {
+        detail!(
+            format!(
+                "{down} of {} hosts seem down and are not scanned. Run with --skip-discovery to scan them anyway.",
+                targets.len()
+            ),
+            opts.greppable,
+            opts.accessible
+        );
+    }
+    live_hosts
+}
+
+/// The raw sockets of a SYN scan, with --syn. Exits when they can't be
+/// opened, or with options a SYN scan can't go with.
+#[cfg(feature = "syn")]
+fn syn_scan(opts: &Opts, source: &Source) -> Option<SynScan> {
+    if !opts.syn {
+        return None;
+    }
+    if opts.udp || opts.proxy.is_some() {
+        warning!(
+            "--syn only works for TCP scans without a proxy.",
+            opts.greppable,
+            opts.accessible
+        );
+        std::process::exit(1);
+    }
+    match SynScan::new(source) {
+        Ok(syn) => Some(syn),
+        Err(e) => {
+            warning!(
+                format!("Could not open the raw sockets of a SYN scan: {e}. It needs root or CAP_NET_RAW."),
+                opts.greppable,
+                opts.accessible
+            );
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Builds the scanner rate limit from the options, a rate of 0 means no
+/// limit. Exits when the minimum rate is above the maximum one.
+fn rate_limit(opts: &Opts) -> RateLimit {
+    let limit = RateLimit {
+        max_rate: opts.max_rate.filter(|rate| *rate > 0),
+        min_rate: opts.min_rate.filter(|rate| *rate > 0),
+        max_host_rate: opts.max_host_rate.filter(|rate| *rate > 0),
+    };
+    if let (Some(min), Some(max)) = (limit.min_rate, limit.max_rate) {
+        if min > max {
+            warning!(
+                format!("The minimum rate {min} is above the maximum rate {max}."),
+                opts.greppable,
+                opts.accessible
+            );
+            std::process::exit(1);
+        }
+    }
+    limit
+}
+
+/// The bounds of the adaptive timeout, when it is on. They default to
+/// nmap's 100ms and 10s. Exits when the minimum is above the maximum.
+fn rtt_timeout_bounds(opts: &Opts) -> Option<(Duration, Duration)> {
+    if !opts.adaptive_timeout {
+        return None;
+    }
+    let min = opts.min_rtt_timeout.unwrap_or(DEFAULT_MIN_RTT_TIMEOUT);
+    let max = opts.max_rtt_timeout.unwrap_or(DEFAULT_MAX_RTT_TIMEOUT);
+    if min > max {
+        warning!(
+            format!("The minimum RTT timeout {min}ms is above the maximum RTT timeout {max}ms."),
+            opts.greppable,
+            opts.accessible
+        );
+        std::process::exit(1);
+    }
+    Some((
+        Duration::from_millis(min.into()),
+        Duration::from_millis(max.into()),
+    ))
+}
+
+/// Hands a finished host over to the reporter. Failing to write the
+/// results is fatal, there is no point in scanning further hosts.
+fn report_host(reporter: &mut Reporter, host: HostReport) {
+    if let Err(e) = reporter.host(host) {
+        eprintln!("Could not write the scan results: {e}");
+        std::process::exit(1);
+    }
+}
+
+/// Formats port state counts as "2 open, 998 closed", in `PortState` order
+/// and skipping states that were never seen.
+fn format_state_counts<'a>(counts: impl IntoIterator<Item = (&'a PortState, &'a usize)>) -> String {
+    let mut states: Vec<(&PortState, &usize)> =
+        counts.into_iter().filter(|(_, n)| **n > 0).collect();
+    if states.is_empty() {
+        return String::from("no sockets scanned");
+    }
+    states.sort();
+    states
+        .iter()
+        .map(|(state, count)| format!("{count} {state}"))
+        .collect::<Vec<String>>()
+        .join(", ")
 }
 
 /// Prints the opening title of RustScan
@@ -297,10 +1100,13 @@ fn infer_batch_size(opts: &Opts, ulimit: u64) -> u16 {
 }
 
 #[cfg(test)]
+#[allow(clippy::field_reassign_with_default)]
 mod tests {
     #[cfg(unix)]
     use super::{adjust_ulimit_size, infer_batch_size};
-    use super::{print_opening, Opts};
+    use super::{format_state_counts, print_opening, Opts};
+    use rustscan::scanner::PortState;
+    use std::collections::HashMap;
 
     #[test]
     #[cfg(unix)]
@@ -363,4 +1169,15 @@ mod tests {
         // print opening should not panic
         print_opening(&opts);
     }
+
+    #[test]
+    fn state_counts_are_ordered_and_skip_empty_states() {
+        let mut counts = HashMap::new();
+        counts.insert(PortState::Filtered, 10);
+        counts.insert(PortState::Open, 2);
+        counts.insert(PortState::Unreachable, 0);
+
+        assert_eq!(format_state_counts(&counts), "2 open, 10 filtered");
+        assert_eq!(format_state_counts(&HashMap::new()), "no sockets scanned");
+    }
 }
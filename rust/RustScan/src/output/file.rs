@@ -0,0 +1,122 @@
+//! Atomic report files for `--output`.
+//!
+//! The report is written to a hidden temporary file next to the target and
+//! only renamed over it once it is complete, so a crash or an interrupted
+//! scan never leaves a half written report behind, nor clobbers the report
+//! of a previous run.
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/// A file that only appears at its path once `persist` is called.
+/// Dropping it without persisting removes the temporary file.
+#[derive(Debug)]
+pub struct AtomicFile {
+    writer: Option<BufWriter<File>>,
+    tmp_path: PathBuf,
+    path: PathBuf,
+}
+
+impl AtomicFile {
+    /// Creates the temporary file in the directory of `path`, the rename is
+    /// only atomic within the same file system.
+    pub fn create(path: &Path) -> io::Result<Self> {
+        let file_name = path.file_name().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} is not a file path", path.display()),
+            )
+        })?;
+        let mut tmp_name = std::ffi::OsString::from(".");
+        tmp_name.push(file_name);
+        tmp_name.push(format!(".{}.tmp", std::process::id()));
+        let tmp_path = path.with_file_name(tmp_name);
+
+        let file = File::create(&tmp_path)?;
+        Ok(Self {
+            writer: Some(BufWriter::new(file)),
+            tmp_path,
+            path: path.to_path_buf(),
+        })
+    }
+
+    /// Flushes the contents to disk and moves the file to its final path.
+    pub fn persist(mut self) -> io::Result<()> {
+        if let Some(writer) = self.writer.take() {
+            let file = writer
+                .into_inner()
+                .map_err(io::IntoInnerError::into_error)?;
+            file.sync_all()?;
+        }
+        fs::rename(&self.tmp_path, &self.path)
+    }
+
+    fn writer(&mut self) -> &mut BufWriter<File> {
+        self.writer
+            .as_mut()
+            .expect("the writer is only taken when persisting")
+    }
+}
+
+impl Write for AtomicFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.writer().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer().flush()
+    }
+}
+
+impl Drop for AtomicFile {
+    fn drop(&mut self) {
+        if self.tmp_path.exists() {
+            let _ = fs::remove_file(&self.tmp_path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AtomicFile;
+    use std::fs;
+    use std::io::Write;
+    use std::path::PathBuf;
+
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("rustscan-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn file_appears_only_once_persisted() {
+        let dir = scratch_dir("persist");
+        let path = dir.join("report.json");
+        fs::write(&path, "previous run").unwrap();
+
+        let mut file = AtomicFile::create(&path).unwrap();
+        file.write_all(b"new run").unwrap();
+        file.flush().unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "previous run");
+
+        file.persist().unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new run");
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn dropped_file_leaves_nothing_behind() {
+        let dir = scratch_dir("drop");
+        let path = dir.join("report.xml");
+
+        let mut file = AtomicFile::create(&path).unwrap();
+        file.write_all(b"partial").unwrap();
+        drop(file);
+
+        assert!(!path.exists());
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 0);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
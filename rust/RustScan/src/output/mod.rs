@@ -0,0 +1,824 @@
+//! Machine readable reports of the scan results.
+//!
+//! ## `--output-format`
+//!
+//! ### `text`
+//!
+//! The default, the human friendly terminal output and the greppable
+//! `ip -> [ports]` lines. The terminal output is not written by this module,
+//! only the `ip -> [ports]` lines of the hosts with open ports are, when the
+//! results go to an `--output` file. Each is followed by a line per port
+//! with a banner, TLS or service, e.g.
+//! `127.0.0.1:443 | TLSv1.3, subject CN=localhost`.
+//!
+//! ### `json`
+//!
+//! A single JSON document written once the whole run is over:
+//!
+//! ```json
+//! {
+//!   "schema_version": 1,
+//!   "hosts": [
+//!     {
+//!       "ip": "127.0.0.1",
+//!       "hostnames": ["localhost"],
+//!       "ports": [{ "port": 22, "protocol": "tcp", "state": "open", "banner": "SSH-2.0-OpenSSH_9.6" }],
+//!       "port_states": { "open": 1, "closed": 999 },
+//!       "scripts": [{ "call_format": "nmap -vvv -p {{port}} {{ip}}", "output": "...", "error": null }]
+//!     }
+//!   ],
+//!   "silent_hosts": 253,
+//!   "scan": {
+//!     "protocol": "tcp",
+//!     "ports": "1-1000,8080",
+//!     "seed": 42,
+//!     "shard": { "index": 2, "count": 4 }
+//!   },
+//!   "timings": { "Portscan": 1.02, "Scripts": 3.4, "RustScan": 4.5 },
+//!   "incomplete": false
+//! }
+//! ```
+//!
+//! `hosts` only lists the hosts that answered on at least one port. The
+//! ones every port of which stayed silent, filtered or open|filtered with
+//! `--udp`, are only counted in `silent_hosts`, so huge ranges of hosts
+//! that are down don't make the report grow.
+//!
+//! `banner` is only there with `--banners`, for the ports that sent one.
+//! `service` is only there with `--services`, for the ports whose service
+//! was recognized, e.g.
+//! `{ "name": "ssh", "product": "OpenSSH", "version": "9.6", "info": "protocol 2.0" }`
+//! where only `name` is always there.
+//! `tls` is only there with `--tls`, for the ports that speak TLS:
+//!
+//! ```json
+//! {
+//!   "version": "TLSv1.3",
+//!   "certificate": {
+//!     "subject": "CN=example.com",
+//!     "sans": ["example.com", "www.example.com"],
+//!     "issuer": "C=US, O=Let's Encrypt, CN=R3",
+//!     "not_before": "2024-01-01T00:00:00Z",
+//!     "not_after": "2024-03-31T00:00:00Z"
+//!   }
+//! }
+//! ```
+//!
+//! `certificate` is null when the certificate could not be parsed.
+//!
+//! `scan` tells which sockets were scanned, so the reports of the shards of
+//! a `--shard` scan can be told apart and put back together. `seed` is the
+//! seed of the scan order, null when it is serial, and `shard` is null when
+//! the scan is not sharded.
+//!
+//! `incomplete` is true when the scan was interrupted, with Ctrl-C, before
+//! going through every socket. The hosts are then the ones found so far,
+//! and `silent_hosts` is null as they are not known yet.
+//!
+//! ### `ndjson`
+//!
+//! One JSON object per line, written as soon as the last socket of each
+//! host is done, while the scan goes on. Host lines have the same fields as
+//! the entries of `hosts` above plus `"type": "host"`, their `scripts` are
+//! empty. The scripts only run once the scan is over, each host's results
+//! are then a line of their own:
+//!
+//! ```json
+//! {"type":"scripts","ip":"127.0.0.1","scripts":[{"call_format":"nmap -vvv -p {{port}} {{ip}}","output":"...","error":null}]}
+//! ```
+//!
+//! and the last line is the run summary:
+//!
+//! ```json
+//! {"type":"summary","schema_version":1,"silent_hosts":253,"scan":{"protocol":"tcp","ports":"1-1000,8080","seed":42,"shard":{"index":2,"count":4}},"timings":{"Portscan":1.02,"Scripts":3.4,"RustScan":4.5},"incomplete":false}
+//! ```
+//!
+//! ### `xml`
+//!
+//! The same XML document nmap writes with `-oX`, written once the whole run
+//! is over, so the results can be imported by tools that read nmap scans.
+//! The scan type is `connect`, `syn` or `udp`, as with nmap's `-sT`, `-sS`
+//! and `-sU`, and so are the reasons given for the port states.
+//! An interrupted scan ends with `exit="error"`, as nmap's own do. The
+//! silent hosts are not listed, only counted as down in the run statistics.
+//! The seed and the shard are `seed` and `shard` attributes of `<scaninfo>`,
+//! e.g. `shard="2/4"`, as nmap has no such thing.
+//!
+//! ## `--output`
+//!
+//! Writes the report to a file instead of stdout. The file is replaced
+//! atomically once the run is over, see [`AtomicFile`].
+
+#![allow(clippy::module_name_repetitions)]
+
+use crate::benchmark::Benchmark;
+use crate::input::{OutputFormat, Shard};
+use crate::scanner::{PortDetails, PortState};
+use serde_derive::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write as _;
+use std::io::{self, Write};
+use std::net::{IpAddr, SocketAddr};
+use std::ops::RangeInclusive;
+use std::path::Path;
+use std::time::SystemTime;
+
+mod file;
+mod xml;
+
+pub use file::AtomicFile;
+
+/// Version of the report schema described above. It is bumped whenever a
+/// field is removed or changes meaning, adding fields keeps the version.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// Everything RustScan found out about a single host.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct HostReport {
+    pub ip: IpAddr,
+    pub hostnames: Vec<String>,
+    pub ports: Vec<PortReport>,
+    pub port_states: BTreeMap<PortState, usize>,
+    pub scripts: Vec<ScriptReport>,
+}
+
+/// A single open port of a host.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PortReport {
+    pub port: u16,
+    pub protocol: &'static str,
+    pub state: PortState,
+    #[serde(flatten)]
+    pub details: PortDetails,
+}
+
+/// The outcome of running one script against a host.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ScriptReport {
+    pub call_format: Option<String>,
+    pub output: Option<String>,
+    pub error: Option<String>,
+}
+
+impl HostReport {
+    pub fn new(
+        ip: IpAddr,
+        hostnames: Vec<String>,
+        open_ports: &[u16],
+        protocol: &'static str,
+        states: &HashMap<PortState, usize>,
+    ) -> Self {
+        let mut ports: Vec<PortReport> = open_ports
+            .iter()
+            .map(|&port| PortReport {
+                port,
+                protocol,
+                state: PortState::Open,
+                details: PortDetails::default(),
+            })
+            .collect();
+        ports.sort_by_key(|p| p.port);
+
+        Self {
+            ip,
+            hostnames,
+            ports,
+            port_states: states.iter().map(|(state, n)| (*state, *n)).collect(),
+            scripts: Vec::new(),
+        }
+    }
+
+    /// Attaches what was found out about its open ports, by port.
+    #[must_use]
+    pub fn with_details(mut self, mut details: BTreeMap<u16, PortDetails>) -> Self {
+        for port in &mut self.ports {
+            port.details = details.remove(&port.port).unwrap_or_default();
+        }
+        self
+    }
+
+    /// The `ip -> [ports]` line of the host, and the `ip:port | details`
+    /// line of every port something was found out about.
+    pub fn greppable(&self) -> String {
+        let ports: Vec<String> = self.ports.iter().map(|p| p.port.to_string()).collect();
+        let mut lines = format!("{} -> [{}]", self.ip, ports.join(","));
+        for port in self.ports.iter().filter(|p| !p.details.is_empty()) {
+            let socket = SocketAddr::new(self.ip, port.port);
+            let _ = write!(lines, "\n{socket}{}", port.details);
+        }
+        lines
+    }
+}
+
+/// What was scanned, needed by the formats that describe the run itself.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScanInfo {
+    pub args: Vec<String>,
+    pub start: SystemTime,
+    pub protocol: &'static str,
+    /// The ports scanned on every host, as ascending ranges.
+    pub ports: Vec<RangeInclusive<u16>>,
+    /// The seed of the scan order, when it is not serial.
+    pub seed: Option<u64>,
+    pub shard: Option<Shard>,
+    /// Whether the TCP ports got SYNs instead of connections.
+    pub syn: bool,
+}
+
+impl ScanInfo {
+    /// Describes a run started now with the arguments of this process.
+    pub fn new(protocol: &'static str, ports: Vec<RangeInclusive<u16>>) -> Self {
+        Self {
+            args: std::env::args().collect(),
+            start: SystemTime::now(),
+            protocol,
+            ports,
+            seed: None,
+            shard: None,
+            syn: false,
+        }
+    }
+
+    #[must_use]
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    #[must_use]
+    pub fn with_shard(mut self, shard: Shard) -> Self {
+        self.shard = Some(shard);
+        self
+    }
+
+    #[must_use]
+    pub fn with_syn_scan(mut self) -> Self {
+        self.syn = true;
+        self
+    }
+}
+
+/// The `scan` object of the JSON reports.
+#[derive(Serialize)]
+struct ScanSummary {
+    protocol: &'static str,
+    ports: String,
+    seed: Option<u64>,
+    shard: Option<Shard>,
+}
+
+impl From<&ScanInfo> for ScanSummary {
+    fn from(info: &ScanInfo) -> Self {
+        Self {
+            protocol: info.protocol,
+            ports: xml::services(&info.ports),
+            seed: info.seed,
+            shard: info.shard,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct JsonReport<'a> {
+    schema_version: u32,
+    hosts: &'a [HostReport],
+    silent_hosts: Option<u128>,
+    scan: ScanSummary,
+    timings: BTreeMap<&'static str, f64>,
+    incomplete: bool,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum NdjsonRecord<'a> {
+    Host(&'a HostReport),
+    Scripts {
+        ip: IpAddr,
+        scripts: &'a [ScriptReport],
+    },
+    Summary {
+        schema_version: u32,
+        silent_hosts: Option<u128>,
+        scan: ScanSummary,
+        timings: BTreeMap<&'static str, f64>,
+        incomplete: bool,
+    },
+}
+
+/// Writes the host reports in the selected `OutputFormat`.
+///
+/// NDJSON hosts are written and flushed as soon as they are added, JSON and
+/// XML hosts are kept until `finish` writes the whole document. The text
+/// format writes the `ip -> [ports]` line of each host with open ports.
+pub struct Reporter {
+    format: OutputFormat,
+    writer: Destination,
+    scan_info: ScanInfo,
+    hosts: Vec<HostReport>,
+    silent_hosts: Option<u128>,
+    incomplete: bool,
+}
+
+impl Reporter {
+    pub fn new(format: OutputFormat, writer: Box<dyn Write>, scan_info: ScanInfo) -> Self {
+        Self {
+            format,
+            writer: Destination::Stream(writer),
+            scan_info,
+            hosts: Vec::new(),
+            silent_hosts: None,
+            incomplete: false,
+        }
+    }
+
+    /// Reports into the file at `path`, which is only replaced once
+    /// `finish` succeeds.
+    pub fn to_file(format: OutputFormat, path: &Path, scan_info: ScanInfo) -> io::Result<Self> {
+        Ok(Self {
+            format,
+            writer: Destination::File(AtomicFile::create(path)?),
+            scan_info,
+            hosts: Vec::new(),
+            silent_hosts: None,
+            incomplete: false,
+        })
+    }
+
+    /// Marks the report as the partial results of an interrupted scan.
+    pub fn mark_incomplete(&mut self) {
+        self.incomplete = true;
+    }
+
+    /// Counts the hosts that got no answer on any port, which are not
+    /// added one by one.
+    pub fn silent_hosts(&mut self, count: u128) {
+        self.silent_hosts = Some(count);
+    }
+
+    /// Adds a host whose scan is done, its scripts are added later on by
+    /// `scripts`.
+    pub fn host(&mut self, host: HostReport) -> io::Result<()> {
+        match self.format {
+            OutputFormat::Text if host.ports.is_empty() => Ok(()),
+            OutputFormat::Text => {
+                writeln!(self.writer, "{}", host.greppable())?;
+                self.writer.flush()
+            }
+            OutputFormat::Json | OutputFormat::Xml => {
+                self.hosts.push(host);
+                Ok(())
+            }
+            OutputFormat::Ndjson => self.write_line(&NdjsonRecord::Host(&host)),
+        }
+    }
+
+    /// Adds the results of the scripts run against a host added before.
+    pub fn scripts(&mut self, ip: IpAddr, mut scripts: Vec<ScriptReport>) -> io::Result<()> {
+        match self.format {
+            OutputFormat::Text => Ok(()),
+            OutputFormat::Json | OutputFormat::Xml => {
+                if let Some(host) = self.hosts.iter_mut().find(|host| host.ip == ip) {
+                    host.scripts.append(&mut scripts);
+                }
+                Ok(())
+            }
+            OutputFormat::Ndjson => self.write_line(&NdjsonRecord::Scripts {
+                ip,
+                scripts: &scripts,
+            }),
+        }
+    }
+
+    /// Writes whatever is left once the run is over, the timings included.
+    pub fn finish(mut self, benchmarks: &Benchmark) -> io::Result<()> {
+        let timings: BTreeMap<&'static str, f64> = benchmarks
+            .runtimes()
+            .into_iter()
+            .map(|(name, runtime)| (name, runtime.as_secs_f64()))
+            .collect();
+
+        match self.format {
+            OutputFormat::Text => Ok(()),
+            OutputFormat::Json => {
+                let report = JsonReport {
+                    schema_version: SCHEMA_VERSION,
+                    hosts: &self.hosts,
+                    silent_hosts: self.silent_hosts,
+                    scan: ScanSummary::from(&self.scan_info),
+                    timings,
+                    incomplete: self.incomplete,
+                };
+                serde_json::to_writer_pretty(&mut self.writer, &report)?;
+                writeln!(self.writer)?;
+                self.writer.flush()
+            }
+            OutputFormat::Ndjson => self.write_line(&NdjsonRecord::Summary {
+                schema_version: SCHEMA_VERSION,
+                silent_hosts: self.silent_hosts,
+                scan: ScanSummary::from(&self.scan_info),
+                timings,
+                incomplete: self.incomplete,
+            }),
+            OutputFormat::Xml => xml::write_xml(
+                &mut self.writer,
+                &self.scan_info,
+                &self.hosts,
+                self.silent_hosts.unwrap_or(0),
+                benchmarks,
+                self.incomplete,
+            ),
+        }?;
+        self.writer.close()
+    }
+
+    fn write_line(&mut self, record: &NdjsonRecord) -> io::Result<()> {
+        serde_json::to_writer(&mut self.writer, record)?;
+        writeln!(self.writer)?;
+        self.writer.flush()
+    }
+}
+
+/// Where a `Reporter` writes to.
+enum Destination {
+    Stream(Box<dyn Write>),
+    File(AtomicFile),
+}
+
+impl Destination {
+    fn close(self) -> io::Result<()> {
+        match self {
+            Destination::Stream(mut stream) => stream.flush(),
+            Destination::File(file) => file.persist(),
+        }
+    }
+}
+
+impl Write for Destination {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Destination::Stream(stream) => stream.write(buf),
+            Destination::File(file) => file.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Destination::Stream(stream) => stream.flush(),
+            Destination::File(file) => file.flush(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{HostReport, Reporter, ScanInfo, ScriptReport};
+    use crate::benchmark::{Benchmark, NamedTimer};
+    use crate::input::{OutputFormat, Shard};
+    use crate::scanner::{CertificateInfo, PortDetails, PortState, TlsInfo};
+    use crate::service::Service;
+    use serde_json::Value;
+    use std::collections::HashMap;
+    use std::io::{self, Write};
+    use std::sync::{Arc, Mutex};
+
+    /// A writer the test can still read from after the reporter took ownership.
+    #[derive(Clone, Default)]
+    struct SharedBuf(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuf {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl SharedBuf {
+        fn contents(&self) -> String {
+            String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+        }
+    }
+
+    fn host() -> HostReport {
+        let mut states = HashMap::new();
+        states.insert(PortState::Open, 2);
+        states.insert(PortState::Closed, 98);
+        let mut host = HostReport::new(
+            "127.0.0.1".parse().unwrap(),
+            vec!["localhost".to_owned()],
+            &[443, 80],
+            "tcp",
+            &states,
+        )
+        .with_details(
+            vec![
+                (
+                    80,
+                    PortDetails {
+                        banner: Some("HTTP/1.0 200 OK".to_owned()),
+                        tls: None,
+                        service: Some(service()),
+                    },
+                ),
+                (
+                    443,
+                    PortDetails {
+                        tls: Some(tls()),
+                        ..PortDetails::default()
+                    },
+                ),
+            ]
+            .into_iter()
+            .collect(),
+        );
+        host.scripts.push(ScriptReport {
+            call_format: Some("nmap -vvv -p {{port}} {{ip}}".to_owned()),
+            output: Some("done".to_owned()),
+            error: None,
+        });
+        host
+    }
+
+    fn tls() -> TlsInfo {
+        TlsInfo {
+            version: "TLSv1.3".to_owned(),
+            certificate: Some(CertificateInfo {
+                subject: "CN=localhost".to_owned(),
+                sans: vec!["localhost".to_owned(), "127.0.0.1".to_owned()],
+                issuer: "CN=Test CA".to_owned(),
+                not_before: "2024-01-01T00:00:00Z".to_owned(),
+                not_after: "2034-01-01T00:00:00Z".to_owned(),
+            }),
+        }
+    }
+
+    fn service() -> Service {
+        Service {
+            name: "http".to_owned(),
+            product: Some("nginx".to_owned()),
+            version: Some("1.24.0".to_owned()),
+            info: None,
+        }
+    }
+
+    fn reporter(format: OutputFormat, buf: &SharedBuf) -> Reporter {
+        let scan_info = ScanInfo::new("tcp", vec![1..=3, 80..=80, 443..=443]);
+        Reporter::new(format, Box::new(buf.clone()), scan_info)
+    }
+
+    fn benchmarks() -> Benchmark {
+        let mut benchmarks = Benchmark::init();
+        let mut timer = NamedTimer::start("Portscan");
+        timer.end();
+        benchmarks.push(timer);
+        benchmarks
+    }
+
+    #[test]
+    fn ndjson_writes_hosts_as_they_are_added() {
+        let buf = SharedBuf::default();
+        let mut reporter = reporter(OutputFormat::Ndjson, &buf);
+
+        reporter.host(host()).unwrap();
+        assert_eq!(buf.contents().lines().count(), 1);
+
+        reporter.silent_hosts(253);
+        reporter.finish(&benchmarks()).unwrap();
+        let lines: Vec<Value> = buf
+            .contents()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["type"], "host");
+        assert_eq!(lines[0]["ip"], "127.0.0.1");
+        assert_eq!(lines[0]["hostnames"][0], "localhost");
+        assert_eq!(lines[0]["ports"][0]["port"], 80);
+        assert_eq!(lines[0]["ports"][1]["port"], 443);
+        assert_eq!(lines[0]["ports"][0]["state"], "open");
+        assert_eq!(lines[0]["ports"][0]["banner"], "HTTP/1.0 200 OK");
+        assert!(lines[0]["ports"][1].get("banner").is_none());
+        assert!(lines[0]["ports"][0].get("tls").is_none());
+        assert_eq!(lines[0]["ports"][1]["tls"]["version"], "TLSv1.3");
+        assert_eq!(
+            lines[0]["ports"][1]["tls"]["certificate"]["sans"][1],
+            "127.0.0.1"
+        );
+        assert_eq!(lines[0]["ports"][0]["service"]["product"], "nginx");
+        assert!(lines[0]["ports"][0]["service"].get("info").is_none());
+        assert!(lines[0]["ports"][1].get("service").is_none());
+        assert_eq!(lines[0]["port_states"]["closed"], 98);
+        assert_eq!(lines[0]["scripts"][0]["output"], "done");
+        assert_eq!(lines[1]["type"], "summary");
+        assert_eq!(lines[1]["schema_version"], 1);
+        assert_eq!(lines[1]["silent_hosts"], 253);
+        assert!(lines[1]["timings"]["Portscan"].is_number());
+        assert_eq!(lines[1]["incomplete"], false);
+    }
+
+    #[test]
+    fn script_results_follow_their_host() {
+        let scripts = vec![ScriptReport {
+            call_format: None,
+            output: Some("later".to_owned()),
+            error: None,
+        }];
+
+        let buf = SharedBuf::default();
+        let mut ndjson = reporter(OutputFormat::Ndjson, &buf);
+        ndjson.host(host()).unwrap();
+        ndjson
+            .scripts("127.0.0.1".parse().unwrap(), scripts.clone())
+            .unwrap();
+        let lines: Vec<Value> = buf
+            .contents()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines[1]["type"], "scripts");
+        assert_eq!(lines[1]["ip"], "127.0.0.1");
+        assert_eq!(lines[1]["scripts"][0]["output"], "later");
+
+        let buf = SharedBuf::default();
+        let mut json = reporter(OutputFormat::Json, &buf);
+        json.host(host()).unwrap();
+        json.scripts("127.0.0.1".parse().unwrap(), scripts).unwrap();
+        json.finish(&benchmarks()).unwrap();
+        let report: Value = serde_json::from_str(&buf.contents()).unwrap();
+        assert_eq!(report["hosts"][0]["scripts"][0]["output"], "done");
+        assert_eq!(report["hosts"][0]["scripts"][1]["output"], "later");
+    }
+
+    #[test]
+    fn json_writes_one_document_on_finish() {
+        let buf = SharedBuf::default();
+        let mut reporter = reporter(OutputFormat::Json, &buf);
+
+        reporter.host(host()).unwrap();
+        assert!(buf.contents().is_empty());
+
+        reporter.finish(&benchmarks()).unwrap();
+        let report: Value = serde_json::from_str(&buf.contents()).unwrap();
+
+        assert_eq!(report["schema_version"], 1);
+        assert_eq!(report["hosts"][0]["ip"], "127.0.0.1");
+        assert!(report["silent_hosts"].is_null());
+        assert_eq!(report["hosts"][0]["ports"][0]["protocol"], "tcp");
+        assert!(report["timings"]["Portscan"].is_number());
+        assert_eq!(report["incomplete"], false);
+        assert_eq!(report["scan"]["ports"], "1-3,80,443");
+        assert!(report["scan"]["seed"].is_null());
+        assert!(report["scan"]["shard"].is_null());
+    }
+
+    #[test]
+    fn shards_and_seeds_are_described() {
+        let scan_info = ScanInfo::new("tcp", vec![1..=1000])
+            .with_seed(42)
+            .with_shard(Shard { index: 2, count: 4 });
+
+        let buf = SharedBuf::default();
+        let reporter = Reporter::new(
+            OutputFormat::Ndjson,
+            Box::new(buf.clone()),
+            scan_info.clone(),
+        );
+        reporter.finish(&benchmarks()).unwrap();
+        let summary: Value = serde_json::from_str(&buf.contents()).unwrap();
+        assert_eq!(summary["scan"]["seed"], 42);
+        assert_eq!(summary["scan"]["shard"]["index"], 2);
+        assert_eq!(summary["scan"]["shard"]["count"], 4);
+
+        let buf = SharedBuf::default();
+        let reporter = Reporter::new(OutputFormat::Xml, Box::new(buf.clone()), scan_info);
+        reporter.finish(&benchmarks()).unwrap();
+        assert!(buf
+            .contents()
+            .contains(r#"services="1-1000" seed="42" shard="2/4"/>"#));
+    }
+
+    #[test]
+    fn interrupted_scans_are_marked_incomplete() {
+        let buf = SharedBuf::default();
+        let mut json_reporter = reporter(OutputFormat::Json, &buf);
+        json_reporter.host(host()).unwrap();
+        json_reporter.mark_incomplete();
+        json_reporter.finish(&benchmarks()).unwrap();
+        let report: Value = serde_json::from_str(&buf.contents()).unwrap();
+        assert_eq!(report["incomplete"], true);
+        assert_eq!(report["hosts"][0]["ip"], "127.0.0.1");
+
+        let buf = SharedBuf::default();
+        let mut xml_reporter = reporter(OutputFormat::Xml, &buf);
+        xml_reporter.mark_incomplete();
+        xml_reporter.finish(&benchmarks()).unwrap();
+        let xml = buf.contents();
+        assert!(xml.contains(r#"exit="error" errormsg="Interrupted""#));
+    }
+
+    #[test]
+    fn text_writes_greppable_lines_of_open_hosts() {
+        let buf = SharedBuf::default();
+        let mut reporter = reporter(OutputFormat::Text, &buf);
+
+        reporter.host(host()).unwrap();
+        let mut closed = host();
+        closed.ip = "127.0.0.2".parse().unwrap();
+        closed.ports.clear();
+        reporter.host(closed).unwrap();
+        reporter.finish(&benchmarks()).unwrap();
+        assert_eq!(
+            buf.contents(),
+            "127.0.0.1 -> [80,443]\n\
+             127.0.0.1:80 | http nginx 1.24.0 | HTTP/1.0 200 OK\n\
+             127.0.0.1:443 | TLSv1.3, subject CN=localhost, valid until 2034-01-01T00:00:00Z\n"
+        );
+    }
+
+    #[test]
+    fn greppable_lines_only_list_the_ports_with_details() {
+        let mut host = host();
+        host.ip = "::1".parse().unwrap();
+        host.ports[1].details = PortDetails::default();
+        assert_eq!(
+            host.greppable(),
+            "::1 -> [80,443]\n[::1]:80 | http nginx 1.24.0 | HTTP/1.0 200 OK"
+        );
+
+        host.ports[0].details = PortDetails::default();
+        assert_eq!(host.greppable(), "::1 -> [80,443]");
+    }
+
+    #[test]
+    fn file_is_written_on_finish() {
+        let path =
+            std::env::temp_dir().join(format!("rustscan-report-{}.json", std::process::id()));
+        let scan_info = ScanInfo::new("tcp", vec![80..=80, 443..=443]);
+        let mut reporter = Reporter::to_file(OutputFormat::Json, &path, scan_info).unwrap();
+
+        reporter.host(host()).unwrap();
+        assert!(!path.exists());
+
+        reporter.finish(&benchmarks()).unwrap();
+        let report: Value = serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(report["hosts"][0]["ip"], "127.0.0.1");
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn xml_writes_nmap_document_on_finish() {
+        let buf = SharedBuf::default();
+        let mut reporter = reporter(OutputFormat::Xml, &buf);
+
+        reporter.host(host()).unwrap();
+        assert!(buf.contents().is_empty());
+
+        reporter.silent_hosts(2);
+        reporter.finish(&benchmarks()).unwrap();
+        let xml = buf.contents();
+
+        assert!(xml.starts_with("<?xml version=\"1.0\""));
+        assert!(xml.contains("<!DOCTYPE nmaprun>"));
+        assert!(xml.contains(
+            r#"<scaninfo type="connect" protocol="tcp" numservices="5" services="1-3,80,443"/>"#
+        ));
+        assert!(xml.contains(r#"<status state="up""#));
+        assert!(xml.contains(r#"<address addr="127.0.0.1" addrtype="ipv4"/>"#));
+        assert!(xml.contains(r#"<hostname name="localhost" type="user"/>"#));
+        assert!(xml.contains(r#"<extraports state="closed" count="98">"#));
+        assert!(xml.contains(
+            r#"<port protocol="tcp" portid="80"><state state="open" reason="syn-ack""#
+        ));
+        assert!(xml.contains(
+            r#"<service name="http" product="nginx" version="1.24.0" method="probed" conf="10"/><script id="banner""#
+        ));
+        assert!(xml.contains(r#"<script id="banner" output="HTTP/1.0 200 OK"/></port>"#));
+        assert!(xml.contains(
+            r#"<script id="ssl-cert" output="Subject: CN=localhost&#xa;Subject Alternative Name: localhost, 127.0.0.1&#xa;Issuer: CN=Test CA&#xa;"#
+        ));
+        assert!(xml.contains(r#"output="done""#));
+        assert!(xml.contains(r#"<hosts up="1" down="2" total="3"/>"#));
+        assert!(xml.contains("3 IP addresses (1 host up)"));
+        assert!(xml.trim_end().ends_with("</nmaprun>"));
+    }
+
+    #[test]
+    fn xml_tells_a_syn_scan_from_a_connect_scan() {
+        let buf = SharedBuf::default();
+        let scan_info = ScanInfo::new("tcp", vec![1..=3, 80..=80, 443..=443]).with_syn_scan();
+        let mut reporter = Reporter::new(OutputFormat::Xml, Box::new(buf.clone()), scan_info);
+
+        reporter.host(host()).unwrap();
+        reporter.finish(&benchmarks()).unwrap();
+        let xml = buf.contents();
+
+        assert!(xml.contains(r#"<scaninfo type="syn" protocol="tcp""#));
+        assert!(xml.contains(
+            r#"<extraports state="closed" count="98"><extrareasons reason="reset" count="98"/>"#
+        ));
+        assert!(xml.contains(r#"<state state="open" reason="syn-ack""#));
+        assert!(!xml.contains("conn-refused"));
+    }
+}
@@ -0,0 +1,401 @@
+//! Writes the scan results in the XML schema of nmap's `-oX` output,
+//! so tools that import nmap scans can read RustScan's results directly.
+//!
+//! Only the elements RustScan has data for are written: the run and scan
+//! info, one `<host>` per target that answered with its status, address,
+//! host names, open ports and the other states folded into `<extraports>`,
+//! the services recognized on them as `<service>`, the banners and
+//! certificates as the `banner` and `ssl-cert` scripts of their port, the
+//! script results as `<hostscript>`, and the run statistics.
+use super::{HostReport, ScanInfo};
+use crate::benchmark::Benchmark;
+use crate::scanner::{PortState, TlsInfo};
+use crate::service::Service;
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+use std::net::IpAddr;
+use std::ops::RangeInclusive;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The version of nmap's XML output this writer follows.
+const XML_OUTPUT_VERSION: &str = "1.05";
+
+pub(super) fn write_xml<W: Write>(
+    w: &mut W,
+    info: &ScanInfo,
+    hosts: &[HostReport],
+    silent_hosts: u128,
+    benchmarks: &Benchmark,
+    incomplete: bool,
+) -> io::Result<()> {
+    let start = unix_secs(info.start);
+    let scan_type = if info.protocol == "udp" {
+        "udp"
+    } else if info.syn {
+        "syn"
+    } else {
+        "connect"
+    };
+
+    writeln!(w, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(w, "<!DOCTYPE nmaprun>")?;
+    writeln!(
+        w,
+        r#"<nmaprun scanner="rustscan" args="{}" start="{}" startstr="{}" version="{}" xmloutputversion="{}">"#,
+        escape(&info.args.join(" ")),
+        start,
+        timestr(start),
+        env!("CARGO_PKG_VERSION"),
+        XML_OUTPUT_VERSION
+    )?;
+    let mut extra = String::new();
+    if let Some(seed) = info.seed {
+        extra.push_str(&format!(r#" seed="{seed}""#));
+    }
+    if let Some(shard) = info.shard {
+        extra.push_str(&format!(r#" shard="{}/{}""#, shard.index, shard.count));
+    }
+    writeln!(
+        w,
+        r#"<scaninfo type="{}" protocol="{}" numservices="{}" services="{}"{}/>"#,
+        scan_type,
+        info.protocol,
+        info.ports.iter().map(|range| range.len()).sum::<usize>(),
+        services(&info.ports),
+        extra
+    )?;
+
+    let mut hosts_up: u128 = 0;
+    for host in hosts {
+        if write_host(w, host, info)? {
+            hosts_up += 1;
+        }
+    }
+
+    let end = unix_secs(SystemTime::now());
+    let elapsed = benchmarks
+        .runtimes()
+        .into_iter()
+        .find(|(name, _)| *name == "RustScan")
+        .map_or_else(
+            || end.saturating_sub(start) as f64,
+            |(_, d)| d.as_secs_f64(),
+        );
+    // The silent hosts are not listed but still count as scanned, and down.
+    let total = hosts.len() as u128 + silent_hosts;
+    // nmap reports the scans it didn't finish the same way.
+    let exit = if incomplete {
+        r#"exit="error" errormsg="Interrupted""#
+    } else {
+        r#"exit="success""#
+    };
+    writeln!(w, "<runstats>")?;
+    writeln!(
+        w,
+        r#"<finished time="{}" timestr="{}" elapsed="{:.2}" summary="RustScan done at {}; {} IP address{} ({} host{} up) scanned in {:.2} seconds" {}/>"#,
+        end,
+        timestr(end),
+        elapsed,
+        timestr(end),
+        total,
+        if total == 1 { "" } else { "es" },
+        hosts_up,
+        if hosts_up == 1 { "" } else { "s" },
+        elapsed,
+        exit
+    )?;
+    writeln!(
+        w,
+        r#"<hosts up="{}" down="{}" total="{}"/>"#,
+        hosts_up,
+        total - hosts_up,
+        total
+    )?;
+    writeln!(w, "</runstats>")?;
+    writeln!(w, "</nmaprun>")?;
+    w.flush()
+}
+
+/// Writes a single `<host>` element and returns whether the host is up.
+///
+/// Hosts are up when at least one port answered, open or closed. The hosts
+/// that only answered as unreachable are listed as down, without their
+/// ports, like `nmap -v` does.
+fn write_host<W: Write>(w: &mut W, host: &HostReport, info: &ScanInfo) -> io::Result<bool> {
+    let count = |state| host.port_states.get(&state).copied().unwrap_or(0);
+    let up = count(PortState::Open) + count(PortState::Closed) > 0;
+    let reason = if up {
+        "user-set"
+    } else if host
+        .port_states
+        .keys()
+        .all(|s| *s == PortState::Unreachable)
+    {
+        "host-unreach"
+    } else {
+        "no-response"
+    };
+
+    writeln!(w, "<host>")?;
+    writeln!(
+        w,
+        r#"<status state="{}" reason="{}" reason_ttl="0"/>"#,
+        if up { "up" } else { "down" },
+        reason
+    )?;
+    writeln!(
+        w,
+        r#"<address addr="{}" addrtype="{}"/>"#,
+        host.ip,
+        match host.ip {
+            IpAddr::V4(_) => "ipv4",
+            IpAddr::V6(_) => "ipv6",
+        }
+    )?;
+    if host.hostnames.is_empty() {
+        writeln!(w, "<hostnames/>")?;
+    } else {
+        writeln!(w, "<hostnames>")?;
+        for name in &host.hostnames {
+            writeln!(w, r#"<hostname name="{}" type="user"/>"#, escape(name))?;
+        }
+        writeln!(w, "</hostnames>")?;
+    }
+
+    if up {
+        writeln!(w, "<ports>")?;
+        for (state, count) in extraports(host) {
+            writeln!(
+                w,
+                r#"<extraports state="{}" count="{}"><extrareasons reason="{}" count="{}"/></extraports>"#,
+                state,
+                count,
+                reason_for(state, info.protocol, info.syn),
+                count
+            )?;
+        }
+        for port in &host.ports {
+            write!(
+                w,
+                r#"<port protocol="{}" portid="{}"><state state="{}" reason="{}" reason_ttl="0"/>"#,
+                port.protocol,
+                port.port,
+                port.state,
+                reason_for(port.state, port.protocol, info.syn)
+            )?;
+            if let Some(service) = &port.details.service {
+                write!(w, "{}", service_element(service))?;
+            }
+            // Where nmap's own banner script puts it.
+            if let Some(banner) = &port.details.banner {
+                write!(w, r#"<script id="banner" output="{}"/>"#, escape(banner))?;
+            }
+            if let Some(tls) = &port.details.tls {
+                write!(
+                    w,
+                    r#"<script id="ssl-cert" output="{}"/>"#,
+                    escape(&ssl_cert(tls))
+                )?;
+            }
+            writeln!(w, "</port>")?;
+        }
+        writeln!(w, "</ports>")?;
+    }
+
+    if !host.scripts.is_empty() {
+        writeln!(w, "<hostscript>")?;
+        for script in &host.scripts {
+            let output = script
+                .output
+                .as_deref()
+                .or(script.error.as_deref())
+                .unwrap_or_default();
+            writeln!(
+                w,
+                r#"<script id="{}" output="{}"/>"#,
+                escape(script.call_format.as_deref().unwrap_or("rustscan")),
+                escape(output)
+            )?;
+        }
+        writeln!(w, "</hostscript>")?;
+    }
+    writeln!(w, "</host>")?;
+
+    Ok(up)
+}
+
+/// The `<service>` element of nmap's version detection for `service`.
+fn service_element(service: &Service) -> String {
+    let mut element = format!(r#"<service name="{}""#, escape(&service.name));
+    let details = [
+        ("product", &service.product),
+        ("version", &service.version),
+        ("extrainfo", &service.info),
+    ];
+    for (attribute, value) in details {
+        if let Some(value) = value {
+            element.push_str(&format!(r#" {attribute}="{}""#, escape(value)));
+        }
+    }
+    element.push_str(r#" method="probed" conf="10"/>"#);
+    element
+}
+
+/// The output of nmap's `ssl-cert` script for `tls`, plus the protocol
+/// version.
+fn ssl_cert(tls: &TlsInfo) -> String {
+    let mut output = String::new();
+    if let Some(certificate) = &tls.certificate {
+        output.push_str(&format!("Subject: {}\n", certificate.subject));
+        if !certificate.sans.is_empty() {
+            output.push_str(&format!(
+                "Subject Alternative Name: {}\n",
+                certificate.sans.join(", ")
+            ));
+        }
+        output.push_str(&format!("Issuer: {}\n", certificate.issuer));
+        output.push_str(&format!("Not valid before: {}\n", certificate.not_before));
+        output.push_str(&format!("Not valid after:  {}\n", certificate.not_after));
+    }
+    output.push_str(&format!("Protocol: {}", tls.version));
+    output
+}
+
+/// Folds the counts of the states that are not listed port by port.
+/// nmap has no unreachable port state, those are reported as filtered.
+fn extraports(host: &HostReport) -> BTreeMap<PortState, usize> {
+    let mut extra = BTreeMap::new();
+    for (state, count) in &host.port_states {
+        let state = match state {
+            PortState::Open => continue,
+            PortState::Unreachable => PortState::Filtered,
+            other => *other,
+        };
+        *extra.entry(state).or_insert(0) += count;
+    }
+    extra.retain(|_, count| *count > 0);
+    extra
+}
+
+/// The nmap reason that matches how a connect, SYN (`syn`) or UDP scan saw
+/// a state.
+fn reason_for(state: PortState, protocol: &str, syn: bool) -> &'static str {
+    match state {
+        PortState::Open if protocol == "udp" => "udp-response",
+        PortState::Open => "syn-ack",
+        PortState::Closed if protocol == "udp" => "port-unreach",
+        PortState::Closed if syn => "reset",
+        PortState::Closed => "conn-refused",
+        PortState::Filtered | PortState::OpenFiltered => "no-response",
+        PortState::Unreachable => "host-unreach",
+    }
+}
+
+/// Writes port ranges in nmap's services syntax, e.g. `1-3,80`.
+pub(super) fn services(ports: &[RangeInclusive<u16>]) -> String {
+    let ranges: Vec<String> = ports
+        .iter()
+        .map(|range| {
+            if range.start() == range.end() {
+                range.start().to_string()
+            } else {
+                format!("{}-{}", range.start(), range.end())
+            }
+        })
+        .collect();
+    ranges.join(",")
+}
+
+/// Escapes the characters that are not allowed in XML attribute values.
+fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            '\n' => escaped.push_str("&#xa;"),
+            '\r' => escaped.push_str("&#xd;"),
+            '\t' => escaped.push_str("&#x9;"),
+            // Other control characters can't be represented in XML 1.0 at all.
+            c if c.is_control() => {}
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// Formats a unix timestamp like nmap's `timestr`, e.g.
+/// `Thu Jan  1 00:00:00 1970`, always in UTC.
+fn timestr(secs: u64) -> String {
+    const WEEKDAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+
+    let days = secs / 86_400;
+    let rem = secs % 86_400;
+    let (year, month, day) = civil_from_days(days);
+
+    format!(
+        "{} {} {:>2} {:02}:{:02}:{:02} {}",
+        WEEKDAYS[(days % 7) as usize],
+        MONTHS[(month - 1) as usize],
+        day,
+        rem / 3600,
+        (rem % 3600) / 60,
+        rem % 60,
+        year
+    )
+}
+
+/// Converts days since the unix epoch to a (year, month, day) date,
+/// following Howard Hinnant's `civil_from_days` algorithm.
+fn civil_from_days(days: u64) -> (u64, u64, u64) {
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + u64::from(month <= 2);
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{escape, services, timestr};
+
+    #[test]
+    fn services_are_written_as_ranges() {
+        assert_eq!(services(&[1..=3, 80..=80, 443..=444]), "1-3,80,443-444");
+        assert_eq!(services(&[65_534..=65_535]), "65534-65535");
+        assert_eq!(services(&[]), "");
+    }
+
+    #[test]
+    fn attribute_values_are_escaped() {
+        assert_eq!(
+            escape("<a href=\"x\">'&'</a>\n"),
+            "&lt;a href=&quot;x&quot;&gt;&apos;&amp;&apos;&lt;/a&gt;&#xa;"
+        );
+        assert_eq!(escape("bell\u{7}"), "bell");
+    }
+
+    #[test]
+    fn timestr_matches_nmap_format() {
+        assert_eq!(timestr(0), "Thu Jan  1 00:00:00 1970");
+        assert_eq!(timestr(1_700_000_000), "Tue Nov 14 22:13:20 2023");
+        assert_eq!(timestr(951_782_400), "Tue Feb 29 00:00:00 2000");
+    }
+}
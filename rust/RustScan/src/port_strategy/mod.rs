@@ -1,9 +1,16 @@
 //! Provides a means to hold configuration options specifically for port scanning.
 mod range_iterator;
+mod services;
+mod spec;
+mod top_ports;
 use crate::input::{PortRange, ScanOrder};
 use rand::seq::SliceRandom;
-use rand::thread_rng;
+use rand::{thread_rng, Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
 use range_iterator::RangeIterator;
+pub use spec::PortSpec;
+use std::ops::RangeInclusive;
+pub use top_ports::top_ports;
 
 /// Represents options of port scanning.
 ///
@@ -17,9 +24,24 @@ pub enum PortStrategy {
 }
 
 impl PortStrategy {
+    /// The interleaved order is up to the scanner, which shuffles the ports
+    /// together with the IPs, the ports themselves are kept in order.
     pub fn pick(range: &Option<PortRange>, ports: Option<Vec<u16>>, order: ScanOrder) -> Self {
+        Self::pick_with_seed(range, ports, order, thread_rng().gen())
+    }
+
+    /// Same as `pick`, with the random order derived from `seed`. The same
+    /// seed always gives the same order, across releases and platforms too:
+    /// ChaCha8 is a fixed algorithm, unlike `StdRng`.
+    pub fn pick_with_seed(
+        range: &Option<PortRange>,
+        ports: Option<Vec<u16>>,
+        order: ScanOrder,
+        seed: u64,
+    ) -> Self {
+        let mut rng = ChaCha8Rng::seed_from_u64(seed);
         match order {
-            ScanOrder::Serial if ports.is_none() => {
+            ScanOrder::Serial | ScanOrder::Interleaved if ports.is_none() => {
                 let range = range.as_ref().unwrap();
                 PortStrategy::Serial(SerialRange {
                     start: range.start,
@@ -29,13 +51,11 @@ impl PortStrategy {
             ScanOrder::Random if ports.is_none() => {
                 let range = range.as_ref().unwrap();
                 PortStrategy::Random(RandomRange {
-                    start: range.start,
-                    end: range.end,
+                    range: RangeIterator::new(range.start.into(), range.end.into(), &mut rng),
                 })
             }
-            ScanOrder::Serial => PortStrategy::Manual(ports.unwrap()),
+            ScanOrder::Serial | ScanOrder::Interleaved => PortStrategy::Manual(ports.unwrap()),
             ScanOrder::Random => {
-                let mut rng = thread_rng();
                 let mut ports = ports.unwrap();
                 ports.shuffle(&mut rng);
                 PortStrategy::Manual(ports)
@@ -43,22 +63,90 @@ impl PortStrategy {
         }
     }
 
+    /// Drops the `excluded` ports, the others keep their order.
+    #[must_use]
+    pub fn exclude(self, excluded: &[u16]) -> Self {
+        if excluded.is_empty() {
+            return self;
+        }
+        let ports = self
+            .iter()
+            .filter(|port| !excluded.contains(port))
+            .collect();
+        PortStrategy::Manual(ports)
+    }
+
     pub fn order(&self) -> Vec<u16> {
+        self.iter().collect()
+    }
+
+    /// The ports as ascending ranges of consecutive ports, without walking
+    /// the ranges.
+    pub fn ranges(&self) -> Vec<RangeInclusive<u16>> {
+        match self {
+            PortStrategy::Manual(ports) => {
+                let mut sorted = ports.clone();
+                sorted.sort_unstable();
+                sorted.dedup();
+                let mut ranges: Vec<RangeInclusive<u16>> = Vec::new();
+                for port in sorted {
+                    match ranges.last_mut() {
+                        Some(range) if range.end().checked_add(1) == Some(port) => {
+                            *range = *range.start()..=port;
+                        }
+                        _ => ranges.push(port..=port),
+                    }
+                }
+                ranges
+            }
+            PortStrategy::Serial(range) => vec![range.start..=range.end],
+            PortStrategy::Random(range) => vec![range.range.range()],
+        }
+    }
+
+    /// Walks the ports in scan order without collecting them. The order is
+    /// the same on every call.
+    pub fn iter(&self) -> Box<dyn Iterator<Item = u16> + '_> {
         match self {
-            PortStrategy::Manual(ports) => ports.clone(),
-            PortStrategy::Serial(range) => range.generate(),
-            PortStrategy::Random(range) => range.generate(),
+            PortStrategy::Manual(ports) => Box::new(ports.iter().copied()),
+            PortStrategy::Serial(range) => range.iter(),
+            PortStrategy::Random(range) => range.iter(),
+        }
+    }
+
+    /// The number of ports, without generating them.
+    pub fn len(&self) -> usize {
+        match self {
+            PortStrategy::Manual(ports) => ports.len(),
+            PortStrategy::Serial(range) => range.len(),
+            PortStrategy::Random(range) => range.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The port at position `index` of the scan order.
+    pub fn get(&self, index: usize) -> Option<u16> {
+        match self {
+            PortStrategy::Manual(ports) => ports.get(index).copied(),
+            PortStrategy::Serial(range) => range.get(index),
+            PortStrategy::Random(range) => range.get(index),
         }
     }
 }
 
 /// Trait associated with a port strategy. Each PortStrategy must be able
-/// to generate an order for future port scanning.
+/// to walk its order for future port scanning, and to tell the port at any
+/// position of it, without storing the ports.
 trait RangeOrder {
-    fn generate(&self) -> Vec<u16>;
+    fn iter(&self) -> Box<dyn Iterator<Item = u16> + '_>;
+    fn len(&self) -> usize;
+    fn get(&self, index: usize) -> Option<u16>;
 }
 
-/// As the name implies SerialRange will always generate a vector in
+/// As the name implies SerialRange will always generate the ports in
 /// ascending order.
 #[derive(Debug)]
 pub struct SerialRange {
@@ -67,31 +155,49 @@ pub struct SerialRange {
 }
 
 impl RangeOrder for SerialRange {
-    fn generate(&self) -> Vec<u16> {
-        (self.start..=self.end).collect()
+    fn iter(&self) -> Box<dyn Iterator<Item = u16> + '_> {
+        Box::new(self.start..=self.end)
+    }
+
+    fn len(&self) -> usize {
+        usize::from(self.end - self.start) + 1
+    }
+
+    fn get(&self, index: usize) -> Option<u16> {
+        if index < self.len() {
+            Some(self.start + index as u16)
+        } else {
+            None
+        }
     }
 }
 
-/// As the name implies RandomRange will always generate a vector with
-/// a random order. This vector is built following the LCG algorithm.
+/// As the name implies RandomRange will always generate the ports in
+/// a random order. The order follows the LCG algorithm.
 #[derive(Debug)]
 pub struct RandomRange {
-    start: u16,
-    end: u16,
+    range: RangeIterator,
 }
 
 impl RangeOrder for RandomRange {
-    // Right now using RangeIterator and generating a range + shuffling the
-    // vector is pretty much the same. The advantages of it will come once
-    // we have to generate different ranges for different IPs without storing
-    // actual vectors.
+    // RangeIterator never stores the ports, it computes each of them from
+    // its position, so the same random order can be walked again for every
+    // IP without keeping a shuffled vector around.
     //
     // Another benefit of RangeIterator is that it always generate a range with
     // a certain distance between the items in the Array. The chances of having
     // port numbers close to each other are pretty slim due to the way the
     // algorithm works.
-    fn generate(&self) -> Vec<u16> {
-        RangeIterator::new(self.start.into(), self.end.into()).collect()
+    fn iter(&self) -> Box<dyn Iterator<Item = u16> + '_> {
+        Box::new(self.range.clone())
+    }
+
+    fn len(&self) -> usize {
+        self.range.len()
+    }
+
+    fn get(&self, index: usize) -> Option<u16> {
+        self.range.get(index)
     }
 }
 
@@ -105,7 +211,7 @@ mod tests {
         let range = PortRange { start: 1, end: 100 };
         let strategy = PortStrategy::pick(&Some(range), None, ScanOrder::Serial);
         let result = strategy.order();
-        let expected_range = (1..=100).into_iter().collect::<Vec<u16>>();
+        let expected_range = (1..=100).collect::<Vec<u16>>();
         assert_eq!(expected_range, result);
     }
     #[test]
@@ -113,13 +219,90 @@ mod tests {
         let range = PortRange { start: 1, end: 100 };
         let strategy = PortStrategy::pick(&Some(range), None, ScanOrder::Random);
         let mut result = strategy.order();
-        let expected_range = (1..=100).into_iter().collect::<Vec<u16>>();
+        let expected_range = (1..=100).collect::<Vec<u16>>();
         assert_ne!(expected_range, result);
 
         result.sort_unstable();
         assert_eq!(expected_range, result);
     }
 
+    #[test]
+    fn strategies_get_ports_by_position() {
+        let range = PortRange { start: 1, end: 100 };
+        for order in [ScanOrder::Serial, ScanOrder::Random] {
+            let strategy = PortStrategy::pick(&Some(range.clone()), None, order);
+            let ports = strategy.order();
+
+            assert_eq!(strategy.len(), 100);
+            assert_eq!(strategy.order(), ports);
+            for (index, port) in ports.iter().enumerate() {
+                assert_eq!(strategy.get(index), Some(*port));
+            }
+            assert_eq!(strategy.get(100), None);
+        }
+    }
+
+    #[test]
+    fn random_strategy_is_reproducible_with_a_seed() {
+        let range = PortRange {
+            start: 1,
+            end: 1000,
+        };
+        let order = |seed| {
+            PortStrategy::pick_with_seed(&Some(range.clone()), None, ScanOrder::Random, seed)
+                .order()
+        };
+        assert_eq!(order(3), order(3));
+        assert_ne!(order(3), order(4));
+
+        let ports = |seed| {
+            PortStrategy::pick_with_seed(&None, Some((1..100).collect()), ScanOrder::Random, seed)
+                .order()
+        };
+        assert_eq!(ports(3), ports(3));
+        assert_ne!(ports(3), ports(4));
+    }
+
+    // A seed has to give the same order with every build, or the shards and
+    // checkpoints of a scan would no longer line up.
+    #[test]
+    fn seeded_order_is_pinned() {
+        let range = PortRange {
+            start: 1,
+            end: 1000,
+        };
+        let walk = PortStrategy::pick_with_seed(&Some(range), None, ScanOrder::Random, 3).order();
+        let shuffled =
+            PortStrategy::pick_with_seed(&None, Some((1..100).collect()), ScanOrder::Random, 3)
+                .order();
+        assert_eq!(walk[..6], [639, 200, 761, 322, 883, 444]);
+        assert_eq!(shuffled[..6], [64, 52, 45, 73, 71, 7]);
+    }
+
+    #[test]
+    fn excluded_ports_are_dropped_in_order() {
+        let range = PortRange { start: 1, end: 10 };
+        let strategy = PortStrategy::pick(&Some(range), None, ScanOrder::Serial).exclude(&[3, 7]);
+        assert_eq!(strategy.order(), vec![1, 2, 4, 5, 6, 8, 9, 10]);
+        assert_eq!(strategy.len(), 8);
+    }
+
+    #[test]
+    fn ranges_are_merged_from_the_ports() {
+        let range = PortRange {
+            start: 1,
+            end: 1000,
+        };
+        for order in [ScanOrder::Serial, ScanOrder::Random] {
+            let strategy = PortStrategy::pick(&Some(range.clone()), None, order);
+            assert_eq!(strategy.ranges(), vec![1..=1000]);
+        }
+        let strategy = PortStrategy::pick(&None, Some(vec![443, 1, 3, 2, 80]), ScanOrder::Random);
+        assert_eq!(strategy.ranges(), vec![1..=3, 80..=80, 443..=443]);
+        let strategy = strategy.exclude(&[2]);
+        assert_eq!(strategy.ranges(), vec![1..=1, 3..=3, 80..=80, 443..=443]);
+    }
+
     #[test]
     fn serial_strategy_with_ports() {
         let strategy = PortStrategy::pick(&None, Some(vec![80, 443]), ScanOrder::Serial);
@@ -131,7 +314,7 @@ mod tests {
     fn random_strategy_with_ports() {
         let strategy = PortStrategy::pick(&None, Some((1..10).collect()), ScanOrder::Random);
         let mut result = strategy.order();
-        let expected_range = (1..10).into_iter().collect::<Vec<u16>>();
+        let expected_range = (1..10).collect::<Vec<u16>>();
         assert_ne!(expected_range, result);
 
         result.sort_unstable();
@@ -1,7 +1,9 @@
 use gcd::Gcd;
 use rand::Rng;
 use std::convert::TryInto;
+use std::ops::RangeInclusive;
 
+#[derive(Debug, Clone)]
 pub struct RangeIterator {
     active: bool,
     normalized_end: u32,
@@ -21,13 +23,15 @@ impl RangeIterator {
     ///
     /// For example, the range `1000-2500` will be normalized to `0-1500`
     /// before going through the algorithm.
-    pub fn new(start: u32, end: u32) -> Self {
+    ///
+    /// The randomness comes from `rng`, so a seeded generator always
+    /// gives the same order.
+    pub fn new<R: Rng>(start: u32, end: u32, rng: &mut R) -> Self {
         let normalized_end = end - start + 1;
-        let step = pick_random_coprime(normalized_end);
+        let step = pick_random_coprime(normalized_end, rng);
 
         // Randomly choose a number within the range to be the first
         // and assign it as a pick.
-        let mut rng = rand::thread_rng();
         let normalized_first_pick = rng.gen_range(0..normalized_end);
 
         Self {
@@ -39,6 +43,29 @@ impl RangeIterator {
             actual_start: start,
         }
     }
+
+    /// The number of ports in the range.
+    pub fn len(&self) -> usize {
+        self.normalized_end as usize
+    }
+
+    /// The ports the walk goes through, in ascending order.
+    pub fn range(&self) -> RangeInclusive<u16> {
+        let start = self.actual_start;
+        let end = start + self.normalized_end - 1;
+        start.try_into().unwrap_or(u16::MAX)..=end.try_into().unwrap_or(u16::MAX)
+    }
+
+    /// The port at position `index` of the walk, without walking there.
+    pub fn get(&self, index: usize) -> Option<u16> {
+        if index >= self.len() {
+            return None;
+        }
+        let end = u64::from(self.normalized_end);
+        let offset = (u64::from(self.step) * index as u64) % end;
+        let pick = (u64::from(self.normalized_first_pick) + offset) % end;
+        (u64::from(self.actual_start) + pick).try_into().ok()
+    }
 }
 
 impl Iterator for RangeIterator {
@@ -79,11 +106,10 @@ impl Iterator for RangeIterator {
 /// the boundaries, which in these case are the "start" and "end" arguments
 /// would also provide non-ideal randomization as discussed on the paragraph
 /// above.
-fn pick_random_coprime(end: u32) -> u32 {
+fn pick_random_coprime<R: Rng>(end: u32, rng: &mut R) -> u32 {
     let range_boundary = end / 4;
     let lower_range = range_boundary;
     let upper_range = end - range_boundary;
-    let mut rng = rand::thread_rng();
     let mut candidate = rng.gen_range(lower_range..upper_range);
 
     for _ in 0..10 {
@@ -99,33 +125,56 @@ fn pick_random_coprime(end: u32) -> u32 {
 #[cfg(test)]
 mod tests {
     use super::RangeIterator;
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha8Rng;
 
     #[test]
     fn range_iterator_iterates_through_the_entire_range() {
         let result = generate_sorted_range(1, 10);
-        let expected_range = (1..=10).into_iter().collect::<Vec<u16>>();
+        let expected_range = (1..=10).collect::<Vec<u16>>();
         assert_eq!(expected_range, result);
 
         let result = generate_sorted_range(1, 100);
-        let expected_range = (1..=100).into_iter().collect::<Vec<u16>>();
+        let expected_range = (1..=100).collect::<Vec<u16>>();
         assert_eq!(expected_range, result);
 
         let result = generate_sorted_range(1, 1000);
-        let expected_range = (1..=1000).into_iter().collect::<Vec<u16>>();
+        let expected_range = (1..=1000).collect::<Vec<u16>>();
         assert_eq!(expected_range, result);
 
         let result = generate_sorted_range(1, 65_535);
-        let expected_range = (1..=65_535).into_iter().collect::<Vec<u16>>();
+        let expected_range = (1..=65_535).collect::<Vec<u16>>();
         assert_eq!(expected_range, result);
 
         let result = generate_sorted_range(1000, 2000);
-        let expected_range = (1000..=2000).into_iter().collect::<Vec<u16>>();
+        let expected_range = (1000..=2000).collect::<Vec<u16>>();
         assert_eq!(expected_range, result);
     }
 
+    #[test]
+    fn range_iterator_gets_any_position_of_the_walk() {
+        let range = RangeIterator::new(1000, 2000, &mut rand::thread_rng());
+        let walked: Vec<u16> = range.clone().collect();
+        let indexed: Vec<u16> = (0..range.len()).filter_map(|i| range.get(i)).collect();
+
+        assert_eq!(range.len(), 1001);
+        assert_eq!(walked, indexed);
+        assert_eq!(range.get(range.len()), None);
+    }
+
+    #[test]
+    fn range_iterator_is_reproducible_with_a_seeded_rng() {
+        let walk = |seed| -> Vec<u16> {
+            RangeIterator::new(1, 1000, &mut ChaCha8Rng::seed_from_u64(seed)).collect()
+        };
+
+        assert_eq!(walk(1), walk(1));
+        assert_ne!(walk(1), walk(2));
+    }
+
     fn generate_sorted_range(start: u32, end: u32) -> Vec<u16> {
-        let range = RangeIterator::new(start, end);
-        let mut result = range.into_iter().collect::<Vec<u16>>();
+        let range = RangeIterator::new(start, end, &mut rand::thread_rng());
+        let mut result = range.collect::<Vec<u16>>();
         result.sort_unstable();
 
         result
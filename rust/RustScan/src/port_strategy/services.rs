@@ -0,0 +1,65 @@
+//! The well known services and their ports, built in from `services`.
+
+const SERVICES: &str = include_str!("services");
+
+/// A service known to listen on a port.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct KnownService {
+    pub name: &'static str,
+    pub port: u16,
+    pub udp: bool,
+}
+
+/// Every known service, once per name and alias. Only the TCP and UDP
+/// ones are listed.
+pub(crate) fn known_services() -> impl Iterator<Item = KnownService> {
+    SERVICES.lines().flat_map(|line| {
+        let mut fields = line.split_whitespace();
+        let name = fields.next().filter(|name| !name.starts_with('#'));
+        let port = fields.next().and_then(|port| port.split_once('/'));
+        let service = match (name, port) {
+            (Some(name), Some((port, protocol @ ("tcp" | "udp")))) => {
+                port.parse().ok().map(|port| KnownService {
+                    name,
+                    port,
+                    udp: protocol == "udp",
+                })
+            }
+            _ => None,
+        };
+        service.into_iter().flat_map(move |service| {
+            std::iter::once(service.name)
+                .chain(fields.clone())
+                .map(move |name| KnownService { name, ..service })
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{known_services, KnownService};
+
+    #[test]
+    fn services_are_known_by_name_and_alias() {
+        let services: Vec<KnownService> = known_services().collect();
+        let ssh = KnownService {
+            name: "ssh",
+            port: 22,
+            udp: false,
+        };
+        assert!(services.contains(&ssh));
+        assert!(services.contains(&KnownService {
+            name: "www",
+            port: 80,
+            udp: false,
+        }));
+        assert!(services.contains(&KnownService {
+            name: "domain",
+            port: 53,
+            udp: true,
+        }));
+        assert!(services
+            .iter()
+            .all(|service| !service.name.starts_with('#')));
+    }
+}
@@ -0,0 +1,253 @@
+//! Port specifications in the syntax of nmap's `-p`.
+//!
+//! A specification is a comma separated list of:
+//!
+//! | Item        | Ports                                            |
+//! |-------------|--------------------------------------------------|
+//! | `80`        | the port                                         |
+//! | `8000-8100` | the range, `-1024` starts at 1, `1024-` ends at 65535 |
+//! | `-`         | every port                                       |
+//! | `http`      | the ports of the service, `http*` of every service matching |
+//! | `[1-1024]`  | the ports of the known services in the range     |
+//!
+//! `T:` and `U:` make the item and the ones after it TCP or UDP only, the
+//! items before any prefix are both. The service names are the ones of
+//! `services`, with their protocols.
+use super::services::{known_services, KnownService};
+use serde::de::{self, Deserialize, Deserializer, SeqAccess, Visitor};
+use std::fmt;
+use std::ops::RangeInclusive;
+
+/// The ports of a specification, for each protocol.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct PortSpec {
+    /// Sorted, without duplicates.
+    tcp: Vec<u16>,
+    udp: Vec<u16>,
+}
+
+/// The protocols an item of the specification is for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Protocols {
+    Both,
+    Tcp,
+    Udp,
+}
+
+impl PortSpec {
+    /// Reads a specification, e.g. `22,80,8000-8100,U:53,T:443`.
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let mut ports = Self::default();
+        let mut protocols = Protocols::Both;
+        for item in spec.split(',').map(str::trim) {
+            let item = if let Some(item) = item.strip_prefix("T:") {
+                protocols = Protocols::Tcp;
+                item
+            } else if let Some(item) = item.strip_prefix("U:") {
+                protocols = Protocols::Udp;
+                item
+            } else {
+                item
+            };
+            ports.add(item.trim(), protocols)?;
+        }
+
+        ports.tcp.sort_unstable();
+        ports.tcp.dedup();
+        ports.udp.sort_unstable();
+        ports.udp.dedup();
+        if ports.tcp.is_empty() && ports.udp.is_empty() {
+            return Err(format!("no ports in '{spec}'"));
+        }
+        Ok(ports)
+    }
+
+    /// The ports scanned over TCP, or over UDP with `udp`, in ascending
+    /// order.
+    pub fn ports(&self, udp: bool) -> &[u16] {
+        if udp {
+            &self.udp
+        } else {
+            &self.tcp
+        }
+    }
+
+    fn add(&mut self, item: &str, protocols: Protocols) -> Result<(), String> {
+        let error = || format!("'{item}' is not a port, a port range or a service name");
+        if item.is_empty() {
+            return Err(String::from("empty item"));
+        }
+
+        if let Some(range) = item.strip_prefix('[').and_then(|r| r.strip_suffix(']')) {
+            let range = port_range(range).ok_or_else(error)?;
+            self.add_services(protocols, |service| range.contains(&service.port));
+        } else if item.starts_with(|c: char| c.is_ascii_digit() || c == '-') {
+            let range = port_range(item).ok_or_else(error)?;
+            if protocols != Protocols::Udp {
+                self.tcp.extend(range.clone());
+            }
+            if protocols != Protocols::Tcp {
+                self.udp.extend(range);
+            }
+        } else if !self.add_services(protocols, |service| glob_match(item, service.name)) {
+            return Err(format!("unknown service '{item}'"));
+        }
+        Ok(())
+    }
+
+    /// Adds the ports of the known services that `matches` takes, and
+    /// tells whether there was any.
+    fn add_services(
+        &mut self,
+        protocols: Protocols,
+        matches: impl Fn(&KnownService) -> bool,
+    ) -> bool {
+        let mut any = false;
+        for service in known_services().filter(|service| matches(service)) {
+            any = true;
+            match (service.udp, protocols) {
+                (false, Protocols::Both | Protocols::Tcp) => self.tcp.push(service.port),
+                (true, Protocols::Both | Protocols::Udp) => self.udp.push(service.port),
+                _ => {}
+            }
+        }
+        any
+    }
+}
+
+/// The same ports over both protocols.
+impl From<Vec<u16>> for PortSpec {
+    fn from(mut ports: Vec<u16>) -> Self {
+        ports.sort_unstable();
+        ports.dedup();
+        Self {
+            tcp: ports.clone(),
+            udp: ports,
+        }
+    }
+}
+
+/// `80`, `1-1024`, `-1024`, `1024-` or `-`.
+fn port_range(item: &str) -> Option<RangeInclusive<u16>> {
+    let (start, end) = match item.split_once('-') {
+        Some((start, end)) => (start.trim(), end.trim()),
+        None => (item, item),
+    };
+    let start = if start.is_empty() {
+        1
+    } else {
+        start.parse().ok()?
+    };
+    let end = if end.is_empty() {
+        u16::MAX
+    } else {
+        end.parse().ok()?
+    };
+    (start <= end).then_some(start..=end)
+}
+
+/// Whether `name` matches `pattern`, where `*` stands for any characters
+/// and `?` for any single one.
+fn glob_match(pattern: &str, name: &str) -> bool {
+    match pattern.split_once('*') {
+        None => {
+            pattern.len() == name.len()
+                && pattern
+                    .chars()
+                    .zip(name.chars())
+                    .all(|(p, n)| p == '?' || p == n)
+        }
+        Some((head, tail)) => {
+            name.len() >= head.len()
+                && name.is_char_boundary(head.len())
+                && glob_match(head, &name[..head.len()])
+                && (head.len()..=name.len())
+                    .filter(|&at| name.is_char_boundary(at))
+                    .any(|at| glob_match(tail, &name[at..]))
+        }
+    }
+}
+
+/// A specification in the configuration file, either a string like on
+/// the command line or a list of ports.
+impl<'de> Deserialize<'de> for PortSpec {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct SpecVisitor;
+
+        impl<'de> Visitor<'de> for SpecVisitor {
+            type Value = PortSpec;
+
+            fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str("a port specification or a list of ports")
+            }
+
+            fn visit_str<E: de::Error>(self, spec: &str) -> Result<PortSpec, E> {
+                PortSpec::parse(spec).map_err(E::custom)
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<PortSpec, A::Error> {
+                let mut ports = Vec::new();
+                while let Some(port) = seq.next_element()? {
+                    ports.push(port);
+                }
+                Ok(PortSpec::from(ports))
+            }
+        }
+
+        deserializer.deserialize_any(SpecVisitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{glob_match, PortSpec};
+
+    #[test]
+    fn ports_and_ranges_are_merged() {
+        let spec = PortSpec::parse("8000-8003,22, 80,22,-3,65534-").unwrap();
+        let ports = [1, 2, 3, 22, 80, 8000, 8001, 8002, 8003, 65534, 65535];
+        assert_eq!(spec.ports(false), ports);
+        assert_eq!(spec.ports(true), ports);
+        assert_eq!(PortSpec::parse("-").unwrap().ports(false).len(), 65535);
+    }
+
+    #[test]
+    fn prefixes_pick_the_protocol() {
+        let spec = PortSpec::parse("22,U:53,161,T:443").unwrap();
+        assert_eq!(spec.ports(false), [22, 443]);
+        assert_eq!(spec.ports(true), [22, 53, 161]);
+    }
+
+    #[test]
+    fn services_are_looked_up_by_name() {
+        let spec = PortSpec::parse("ssh,domain").unwrap();
+        assert_eq!(spec.ports(false), [22, 53]);
+        assert_eq!(spec.ports(true), [53]);
+
+        let spec = PortSpec::parse("T:https*").unwrap();
+        assert_eq!(spec.ports(false), [443]);
+        assert!(spec.ports(true).is_empty());
+
+        let spec = PortSpec::parse("[20-25]").unwrap();
+        assert_eq!(spec.ports(false), [20, 21, 22, 23, 25]);
+    }
+
+    #[test]
+    fn invalid_items_are_rejected() {
+        assert!(PortSpec::parse("80,no-such-service").is_err());
+        assert!(PortSpec::parse("100-1").is_err());
+        assert!(PortSpec::parse("70000").is_err());
+        assert!(PortSpec::parse("80,,443").is_err());
+        assert!(PortSpec::parse("[1-2-3]").is_err());
+    }
+
+    #[test]
+    fn globs() {
+        assert!(glob_match("http*", "http-alt"));
+        assert!(glob_match("http*", "http"));
+        assert!(glob_match("*sql", "mysql"));
+        assert!(glob_match("im?p", "imap"));
+        assert!(!glob_match("http", "https"));
+        assert!(!glob_match("*sql", "mysql-proxy"));
+    }
+}
@@ -0,0 +1,72 @@
+//! The ports most often found open, built in from `top-ports`.
+use super::services::known_services;
+
+const TOP_PORTS: &str = include_str!("top-ports");
+
+/// The `count` ports most likely to be open over TCP, or over UDP with
+/// `udp`, the most likely first.
+pub fn top_ports(count: usize, udp: bool) -> Vec<u16> {
+    let ranked = ranked(if udp { "udp" } else { "tcp" });
+    let services = known_services()
+        .filter(|service| service.udp == udp)
+        .map(|service| service.port);
+    let mut known: Vec<u16> = services.collect();
+    known.sort_unstable();
+
+    let mut seen = vec![false; usize::from(u16::MAX) + 1];
+    ranked
+        .into_iter()
+        .chain(known)
+        .chain(1..=u16::MAX)
+        .filter(|&port| !std::mem::replace(&mut seen[usize::from(port)], true))
+        .take(count)
+        .collect()
+}
+
+/// The ports of the `protocol` list, in the order of the file.
+fn ranked(protocol: &str) -> Vec<u16> {
+    TOP_PORTS
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .skip_while(|line| line.trim() != protocol)
+        .skip(1)
+        .take_while(|line| line.starts_with(|c: char| c.is_ascii_digit()))
+        .flat_map(str::split_whitespace)
+        .map(|port| port.parse().expect("the built-in top ports are valid"))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{known_services, ranked, top_ports};
+
+    #[test]
+    fn the_most_frequent_ports_come_first() {
+        assert_eq!(top_ports(5, false), [80, 23, 443, 21, 22]);
+        assert_eq!(top_ports(3, true), [631, 161, 137]);
+        assert_eq!(ranked("tcp").len(), 1000);
+        assert_eq!(ranked("udp").len(), 100);
+    }
+
+    #[test]
+    fn the_rest_of_the_list_follows_the_most_frequent() {
+        assert_eq!(top_ports(105, false)[100..], [1, 3, 4, 6, 17]);
+        assert_eq!(top_ports(1000, false)[999], 65389);
+        assert_eq!(top_ports(25, true)[20..], [7, 9, 17, 19, 49]);
+        assert_eq!(top_ports(100, true)[99], 65024);
+    }
+
+    #[test]
+    fn every_port_is_ranked_once() {
+        for udp in [false, true] {
+            let mut ports = top_ports(70_000, udp);
+            assert_eq!(ports.len(), 65535);
+            ports.sort_unstable();
+            ports.dedup();
+            assert_eq!(ports.len(), 65535);
+        }
+        // The known services come right after the list.
+        let next = top_ports(1001, false)[1000];
+        assert!(known_services().any(|service| !service.udp && service.port == next));
+    }
+}
@@ -0,0 +1,151 @@
+//! Banner grabbing on open TCP ports, see `--banners`.
+//!
+//! Many services introduce themselves as soon as the connection is up, SSH,
+//! SMTP or FTP servers among them. The others only talk once asked, so when
+//! nothing came within the wait, a generic HTTP request goes out, which
+//! most of them answer with something, if only an error.
+use async_std::io;
+use async_std::net::TcpStream;
+use async_std::prelude::*;
+use std::fmt::Write;
+use std::time::{Duration, Instant};
+
+/// The most bytes of a banner that are kept.
+const MAX_BANNER_LEN: usize = 1024;
+
+/// Sent to the services that wait for the client to talk first.
+const GENERIC_PROBE: &[u8] = b"HEAD / HTTP/1.0\r\n\r\n";
+
+/// Reads what the service on the other end of `stream` sends first, waiting
+/// up to `wait` in all for it: half of it for the service to talk on its
+/// own, the rest for the answer to the generic probe. `None` when it says
+/// nothing at all.
+pub(super) async fn grab(stream: &mut TcpStream, wait: Duration) -> Option<String> {
+    let deadline = Instant::now() + wait;
+    let mut buf = vec![0; MAX_BANNER_LEN];
+    let mut len = read_some(stream, &mut buf, wait / 2).await;
+    if len == 0 {
+        stream.write_all(GENERIC_PROBE).await.ok()?;
+        let left = deadline.saturating_duration_since(Instant::now());
+        len = read_some(stream, &mut buf, left).await;
+    }
+
+    let banner = sanitize(&buf[..len]);
+    if banner.is_empty() {
+        None
+    } else {
+        Some(banner)
+    }
+}
+
+/// The number of bytes read within `wait`, 0 when the service says
+/// nothing, closes the connection or fails.
+async fn read_some(stream: &mut TcpStream, buf: &mut [u8], wait: Duration) -> usize {
+    io::timeout(wait, stream.read(buf)).await.unwrap_or(0)
+}
+
+/// Makes a single printable line of `bytes`. Printable ASCII is kept, the
+/// rest is escaped the way nmap shows banners, e.g. `\r\n` or `\x00`.
+fn sanitize(bytes: &[u8]) -> String {
+    let end = bytes
+        .iter()
+        .rposition(|b| !b.is_ascii_whitespace())
+        .map_or(0, |last| last + 1);
+
+    let mut banner = String::with_capacity(end);
+    for &byte in &bytes[..end] {
+        match byte {
+            b'\r' => banner.push_str("\\r"),
+            b'\n' => banner.push_str("\\n"),
+            b'\t' => banner.push_str("\\t"),
+            b'\\' => banner.push_str("\\\\"),
+            b' '..=b'~' => banner.push(char::from(byte)),
+            _ => {
+                let _ = write!(banner, "\\x{byte:02x}");
+            }
+        }
+    }
+    banner
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{grab, sanitize};
+    use async_std::net::TcpStream;
+    use async_std::task::block_on;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn banners_are_a_single_printable_line() {
+        assert_eq!(sanitize(b"SSH-2.0-OpenSSH_9.6\r\n"), "SSH-2.0-OpenSSH_9.6");
+        assert_eq!(
+            sanitize(b"220 ready\r\n220 still\x00\xff\\"),
+            "220 ready\\r\\n220 still\\x00\\xff\\\\"
+        );
+        assert_eq!(sanitize(b" \r\n"), "");
+    }
+
+    /// Grabs the banner of a server that answers with `greeting`, either
+    /// right away or to the generic probe.
+    fn grab_from(greeting: &'static [u8], talks_first: bool) -> Option<String> {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            if !talks_first {
+                let mut request = [0; 64];
+                let _ = stream.read(&mut request).unwrap();
+                assert!(request.starts_with(b"HEAD / HTTP/1.0"));
+            }
+            stream.write_all(greeting).unwrap();
+        });
+
+        let banner = block_on(async {
+            let mut stream = TcpStream::connect(addr).await.unwrap();
+            grab(&mut stream, Duration::from_millis(300)).await
+        });
+        server.join().unwrap();
+        banner
+    }
+
+    #[test]
+    fn banners_are_read_or_asked_for() {
+        assert_eq!(
+            grab_from(b"SSH-2.0-test\r\n", true).as_deref(),
+            Some("SSH-2.0-test")
+        );
+        assert_eq!(
+            grab_from(b"HTTP/1.0 200 OK\r\nServer: test\r\n\r\n", false).as_deref(),
+            Some("HTTP/1.0 200 OK\\r\\nServer: test")
+        );
+        assert_eq!(grab_from(b"", false), None);
+    }
+
+    #[test]
+    fn silent_services_take_a_single_wait() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            // Holds the connection open without a word, until it is closed.
+            let mut request = Vec::new();
+            let _ = stream.read_to_end(&mut request);
+        });
+
+        let wait = Duration::from_millis(300);
+        let started = Instant::now();
+        let banner = block_on(async {
+            let mut stream = TcpStream::connect(addr).await.unwrap();
+            grab(&mut stream, wait).await
+        });
+        let took = started.elapsed();
+        server.join().unwrap();
+
+        assert_eq!(banner, None);
+        assert!(took >= wait, "{:?}", took);
+        assert!(took < wait * 3 / 2, "{:?}", took);
+    }
+}
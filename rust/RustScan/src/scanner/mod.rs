@@ -1,48 +1,281 @@
 //! Core functionality for actual scanning behaviour.
+use crate::address::Targets;
+use crate::input::Shard;
 use crate::port_strategy::PortStrategy;
+use crate::service::{Service, ServiceProbes};
 use log::debug;
 
 mod socket_iterator;
-use socket_iterator::SocketIterator;
+use socket_iterator::{ports_in_shard, ShardLayout, SocketIterator};
+
+mod udp_payloads;
+use udp_payloads::payload_for_port;
+
+mod rate_limit;
+pub use rate_limit::RateLimit;
+use rate_limit::Throttle;
+
+mod rtt;
+use rtt::RttTable;
+
+mod permutation;
+use permutation::Permutation;
+
+mod progress;
+use progress::{Progress, Watchlist};
+
+mod proxy;
+pub use proxy::{Proxy, ProxyKind};
+
+mod source;
+pub use source::Source;
+
+mod banner;
+
+mod tls;
+use tls::TlsProbe;
+pub use tls::{CertificateInfo, TlsInfo};
+
+#[cfg(feature = "syn")]
+mod syn;
+#[cfg(feature = "syn")]
+pub use syn::SynScan;
 
 use async_std::io;
 use async_std::net::TcpStream;
 use async_std::prelude::*;
 use colored::Colorize;
-use futures::stream::FuturesUnordered;
+use futures::stream::{self, FuturesUnordered, Stream};
+use serde_derive::{Deserialize, Serialize};
 use std::{
-    collections::HashSet,
+    collections::HashMap,
+    convert::TryFrom,
+    fmt,
     net::{IpAddr, Shutdown, SocketAddr},
     num::NonZeroU8,
-    time::Duration,
+    ops::RangeInclusive,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
 };
 
+/// The state a scanned socket was found in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum PortState {
+    /// The connection was accepted, or the UDP probe got an answer.
+    Open,
+    /// The host answered but refused the connection.
+    Closed,
+    /// Nothing answered before the timeout, usually a firewall dropping packets.
+    Filtered,
+    /// A UDP probe got no answer, which can't tell open and filtered apart.
+    OpenFiltered,
+    /// The host or its network could not be reached at all.
+    Unreachable,
+}
+
+impl PortState {
+    /// Maps the error of a failed connection attempt to a port state.
+    pub fn from_error(error: &io::Error) -> Self {
+        match error.kind() {
+            io::ErrorKind::ConnectionRefused | io::ErrorKind::ConnectionReset => PortState::Closed,
+            io::ErrorKind::HostUnreachable
+            | io::ErrorKind::NetworkUnreachable
+            | io::ErrorKind::NetworkDown
+            | io::ErrorKind::AddrNotAvailable => PortState::Unreachable,
+            // Timeouts and anything we can't classify mean no answer came back.
+            _ => PortState::Filtered,
+        }
+    }
+}
+
+impl fmt::Display for PortState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = match self {
+            PortState::Open => "open",
+            PortState::Closed => "closed",
+            PortState::Filtered => "filtered",
+            PortState::OpenFiltered => "open|filtered",
+            PortState::Unreachable => "unreachable",
+        };
+        f.write_str(state)
+    }
+}
+
+impl serde::Serialize for PortState {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for PortState {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        const STATES: [PortState; 5] = [
+            PortState::Open,
+            PortState::Closed,
+            PortState::Filtered,
+            PortState::OpenFiltered,
+            PortState::Unreachable,
+        ];
+        let name = <String as serde::Deserialize>::deserialize(deserializer)?;
+        STATES
+            .iter()
+            .find(|state| state.to_string() == name)
+            .copied()
+            .ok_or_else(|| {
+                serde::de::Error::unknown_variant(
+                    &name,
+                    &["open", "closed", "filtered", "open|filtered", "unreachable"],
+                )
+            })
+    }
+}
+
+/// The outcome of scanning a single socket.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScanResult {
+    pub socket: SocketAddr,
+    pub state: PortState,
+    /// What else was found out about it, when it is open.
+    pub details: PortDetails,
+}
+
+impl ScanResult {
+    pub fn new(socket: SocketAddr, state: PortState) -> Self {
+        Self {
+            socket,
+            state,
+            details: PortDetails::default(),
+        }
+    }
+}
+
+/// What was found out about an open port besides its state.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PortDetails {
+    /// What the service sent first, sanitized, see `with_banners`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub banner: Option<String>,
+    /// What the TLS handshake told, for the ports speaking TLS, see
+    /// `with_tls_detection`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tls: Option<TlsInfo>,
+    /// The service the probes recognized, see `with_service_detection`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub service: Option<Service>,
+}
+
+impl PortDetails {
+    pub fn is_empty(&self) -> bool {
+        self.banner.is_none() && self.tls.is_none() && self.service.is_none()
+    }
+}
+
+/// Written after the port, as ` | service | banner | TLS`, with only the
+/// parts that are known.
+impl fmt::Display for PortDetails {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(service) = &self.service {
+            write!(f, " | {service}")?;
+        }
+        if let Some(banner) = &self.banner {
+            write!(f, " | {banner}")?;
+        }
+        if let Some(tls) = &self.tls {
+            write!(f, " | {tls}")?;
+        }
+        Ok(())
+    }
+}
+
+/// What became of a socket handed to `scan_socket`.
+// Nearly every socket ends up a result, boxing them would cost an
+// allocation each to save space on the rare `OutOfFiles`.
+#[allow(clippy::large_enum_variant)]
+enum Outcome {
+    Scanned(ScanResult),
+    /// The process ran out of file descriptors before the socket could be
+    /// scanned, it has to be scanned again later.
+    OutOfFiles(SocketAddr),
+}
+
+/// Whether `error` means the process or the whole system ran out of file
+/// descriptors, EMFILE and ENFILE.
+fn is_out_of_files(error: &io::Error) -> bool {
+    #[cfg(unix)]
+    const OUT_OF_FILES: &[i32] = &[libc::ENFILE, libc::EMFILE];
+    // Winsock has its own code for it, which libc doesn't define.
+    #[cfg(windows)]
+    const WSAEMFILE: i32 = 10024;
+    #[cfg(windows)]
+    const OUT_OF_FILES: &[i32] = &[WSAEMFILE];
+    #[cfg(not(any(unix, windows)))]
+    const OUT_OF_FILES: &[i32] = &[];
+
+    error
+        .raw_os_error()
+        .is_some_and(|code| OUT_OF_FILES.contains(&code))
+}
+
 /// The class for the scanner
-/// IP is data type IpAddr and is the IP address
+/// targets are the IP addresses, kept as ranges and only expanded while scanning
 /// start & end is where the port scan starts and ends
 /// batch_size is how many ports at a time should be scanned
 /// Timeout is the time RustScan should wait before declaring a port closed. As datatype Duration.
 /// greppable is whether or not RustScan should print things, or wait until the end to print only the ip and open ports.
 /// Added by wasuaje - 01/26/2024:
 ///     exclude_ports  is an exclusion port list
+/// udp probes the ports with UDP datagrams instead of TCP connects, see `with_udp`.
+/// rate_limit bounds how many connections are started per second, see `with_rate_limit`.
+/// rtt replaces the fixed timeout with one per host, see `with_adaptive_timeout`.
+/// shuffle_seed shuffles the hosts and ports together, see `with_interleaved_order`.
+/// shard restricts the scan to one part of the sockets, see `with_shard`.
+/// resume_from skips the sockets of an earlier run, see `with_resume`.
+/// progress tracks which sockets are done, see `checkpoint`.
+/// watched are the hosts waiting for their last socket, by its position, see `watch`.
+/// stop ends the scan early once it is set, see `with_stop_signal`.
+/// proxy is the SOCKS5 or HTTP proxy the TCP connections go through, see `with_proxy`.
+/// source is the local address, interface and port the connections leave from, see `with_source`.
+/// syn sends SYNs through raw sockets instead of connecting, see `with_syn_scan`.
+/// banner_wait is how long to wait for the banner of an open port, see `with_banners`.
+/// tls runs a TLS handshake on every open port, see `with_tls_detection`.
+/// services are the probes telling the service on every open port, see `with_service_detection`.
+/// lowest_batch_size is how far the batch size shrank after running out of file descriptors.
 #[cfg(not(tarpaulin_include))]
 #[derive(Debug)]
 pub struct Scanner {
-    ips: Vec<IpAddr>,
+    targets: Targets,
     batch_size: u16,
     timeout: Duration,
     tries: NonZeroU8,
     greppable: bool,
     port_strategy: PortStrategy,
     accessible: bool,
-    exclude_ports: Vec<u16>,
+    udp: bool,
+    rate_limit: RateLimit,
+    rtt: Option<RttTable>,
+    shuffle_seed: Option<u64>,
+    shard: Option<Shard>,
+    live_hosts: Option<Targets>,
+    resume_from: (u128, Vec<u128>),
+    progress: Mutex<Progress>,
+    watched: Mutex<Watchlist>,
+    stop: Arc<AtomicBool>,
+    proxy: Option<Proxy>,
+    source: Source,
+    #[cfg(feature = "syn")]
+    syn: Option<SynScan>,
+    banner_wait: Option<Duration>,
+    tls: Option<(TlsProbe, Duration)>,
+    services: Option<(ServiceProbes, Duration)>,
+    lowest_batch_size: AtomicUsize,
 }
 
 // Allowing too many arguments for clippy.
 #[allow(clippy::too_many_arguments)]
 impl Scanner {
     pub fn new(
-        ips: &[IpAddr],
+        targets: impl Into<Targets>,
         batch_size: u16,
         timeout: Duration,
         tries: u8,
@@ -56,71 +289,466 @@ impl Scanner {
             timeout,
             tries: NonZeroU8::new(std::cmp::max(tries, 1)).unwrap(),
             greppable,
-            port_strategy,
-            ips: ips.iter().map(ToOwned::to_owned).collect(),
+            port_strategy: port_strategy.exclude(&exclude_ports),
+            targets: targets.into(),
             accessible,
-            exclude_ports,
+            udp: false,
+            rate_limit: RateLimit::default(),
+            rtt: None,
+            shuffle_seed: None,
+            shard: None,
+            live_hosts: None,
+            resume_from: (0, Vec::new()),
+            progress: Mutex::new(Progress::new(0)),
+            watched: Mutex::new(Watchlist::default()),
+            stop: Arc::new(AtomicBool::new(false)),
+            proxy: None,
+            source: Source::default(),
+            #[cfg(feature = "syn")]
+            syn: None,
+            banner_wait: None,
+            tls: None,
+            services: None,
+            lowest_batch_size: AtomicUsize::new(usize::from(batch_size)),
+        }
+    }
+
+    /// Sends protocol probes over UDP to every socket instead of connecting
+    /// over TCP, with `udp`.
+    #[must_use]
+    pub fn with_udp(mut self, udp: bool) -> Self {
+        self.udp = udp;
+        self
+    }
+
+    /// Limits how many connections are started per second, on top of the
+    /// `batch_size` limit on how many are in flight.
+    #[must_use]
+    pub fn with_rate_limit(mut self, rate_limit: RateLimit) -> Self {
+        self.rate_limit = rate_limit;
+        self
+    }
+
+    /// Derives the timeout of every host from the round-trip times of its
+    /// answered connections, bounded by `min` and `max`. The fixed timeout
+    /// is used for a host until it answers.
+    #[must_use]
+    pub fn with_adaptive_timeout(mut self, min: Duration, max: Duration) -> Self {
+        self.rtt = Some(RttTable::new(min, max, self.timeout));
+        self
+    }
+
+    /// Scans every IP and port combination in a pseudo-random order keyed
+    /// by `seed`, instead of holding a port while going through all the IPs.
+    /// Consecutive connections then go to unrelated hosts and ports.
+    #[must_use]
+    pub fn with_interleaved_order(mut self, seed: u64) -> Self {
+        self.shuffle_seed = Some(seed);
+        self
+    }
+
+    /// Only scans the sockets of `shard`. Runs with the same targets, ports,
+    /// order and seed, and each of the shards, scan every socket once.
+    #[must_use]
+    pub fn with_shard(mut self, shard: Shard) -> Self {
+        self.shard = Some(shard);
+        self
+    }
+
+    /// Only scans the targets in `live`, the ones host discovery found up.
+    /// Only their sockets are walked, the hosts that are down cost nothing,
+    /// and the positions of those sockets are held in memory. They keep
+    /// their positions among all the targets, so shards and checkpoints
+    /// still line up with runs that found other hosts up.
+    #[must_use]
+    pub fn with_live_hosts(mut self, live: Targets) -> Self {
+        self.live_hosts = Some(live);
+        self
+    }
+
+    /// Continues the scan of an earlier run from a `checkpoint` of it: the
+    /// sockets before `position` and the `done` ones after it are skipped.
+    /// Only the same targets, ports, order, seed and shard give the same
+    /// positions.
+    #[must_use]
+    pub fn with_resume(mut self, position: u128, done: Vec<u128>) -> Self {
+        self.progress = Mutex::new(Progress::new(position));
+        self.resume_from = (position, done);
+        self
+    }
+
+    /// Stops the scan once `stop` is set, e.g. from a signal handler. No
+    /// socket is started after that, the ones in flight are still waited
+    /// for and yielded, then the stream ends.
+    #[must_use]
+    pub fn with_stop_signal(mut self, stop: Arc<AtomicBool>) -> Self {
+        self.stop = stop;
+        self
+    }
+
+    /// Makes every TCP connection through `proxy`, which tells whether the
+    /// target accepted it. UDP scans can't go through a proxy.
+    #[must_use]
+    pub fn with_proxy(mut self, proxy: Proxy) -> Self {
+        self.proxy = Some(proxy);
+        self
+    }
+
+    /// Binds every connection, and every UDP probe, to `source` instead of
+    /// letting the OS pick the local end. Connections through a proxy are
+    /// bound on the way to the proxy.
+    #[must_use]
+    pub fn with_source(mut self, source: Source) -> Self {
+        self.source = source;
+        self
+    }
+
+    /// Sends a SYN to every TCP socket through `syn` instead of connecting
+    /// to it, and tells the state from the answer. Takes no file descriptor
+    /// per socket, so the batch size can go past the file limit.
+    #[cfg(feature = "syn")]
+    #[must_use]
+    pub fn with_syn_scan(mut self, syn: SynScan) -> Self {
+        self.syn = Some(syn);
+        self
+    }
+
+    /// Reads the banner of every open TCP port before closing the
+    /// connection, waiting up to `wait` for the service to talk or answer
+    /// a generic probe. Only connect scans have a connection to read from.
+    #[must_use]
+    pub fn with_banners(mut self, wait: Duration) -> Self {
+        self.banner_wait = Some(wait);
+        self
+    }
+
+    /// Tries a TLS handshake on a new connection to every open TCP port,
+    /// waiting up to `wait` for it, and describes the certificate of the
+    /// ports that speak TLS.
+    #[must_use]
+    pub fn with_tls_detection(mut self, wait: Duration) -> Self {
+        self.tls = Some((TlsProbe::new(), wait));
+        self
+    }
+
+    /// Sends the `probes` to every open TCP port, each over a new
+    /// connection, waiting up to `wait` for every answer, until one of
+    /// them tells the service.
+    #[must_use]
+    pub fn with_service_detection(mut self, probes: ServiceProbes, wait: Duration) -> Self {
+        self.services = Some((probes, wait));
+        self
+    }
+
+    /// Whether the scan was stopped before going through every socket.
+    pub fn stopped(&self) -> bool {
+        self.stop.load(Ordering::Relaxed)
+    }
+
+    /// How far the scan got: every socket before the returned position is
+    /// done, and so are the listed positions after it. Hand it to
+    /// `with_resume` to continue from there.
+    pub fn checkpoint(&self) -> (u128, Vec<u128>) {
+        self.progress.lock().unwrap().checkpoint()
+    }
+
+    /// The batch size the scan had to shrink to after running out of file
+    /// descriptors, or `None` when it never had to.
+    pub fn reduced_batch_size(&self) -> Option<u16> {
+        let lowest = self.lowest_batch_size.load(Ordering::Relaxed);
+        u16::try_from(lowest)
+            .ok()
+            .filter(|lowest| *lowest < self.batch_size)
+    }
+
+    /// The timeout of the next connection to `ip`.
+    fn timeout_for(&self, ip: IpAddr) -> Duration {
+        self.rtt
+            .as_ref()
+            .map_or(self.timeout, |rtt| rtt.timeout(ip))
+    }
+
+    /// Feeds the time a connection to `ip` took to the adaptive timeout, if
+    /// it is on and the connection got an answer. A refused connection took
+    /// a round trip just as well as an accepted one. The estimate of a host
+    /// is kept until its last socket is done, see `stream`.
+    fn record_rtt<T>(&self, ip: IpAddr, started: Instant, outcome: &io::Result<T>) {
+        let answered = match outcome {
+            Ok(_) => true,
+            Err(e) => e.kind() == io::ErrorKind::ConnectionRefused,
+        };
+        if let (Some(rtt), true) = (&self.rtt, answered) {
+            if rtt.record(ip, started.elapsed()) {
+                if let Some(last) = self.last_position_of(ip) {
+                    rtt.forget_after(last, ip);
+                }
+            }
         }
     }
 
     /// Runs scan_range with chunk sizes
     /// If you want to run RustScan normally, this is the entry point used
-    /// Returns all open ports as `Vec<u16>`
+    /// Returns a `ScanResult` with the `PortState` of every scanned socket
     /// Added by wasuaje - 01/26/2024:
     ///    Filtering port against exclude port list
-    pub async fn run(&self) -> Vec<SocketAddr> {
-        let ports: Vec<u16> = self
-            .port_strategy
-            .order()
-            .iter()
-            .filter(|&port| !self.exclude_ports.contains(port))
-            .copied()
-            .collect(); // *生成port列表, 过滤掉exclude_ports中的端口
-        let mut socket_iterator: SocketIterator = SocketIterator::new(&self.ips, &ports);   // *生成socket迭代器
-        let mut open_sockets: Vec<SocketAddr> = Vec::new(); // *存放打开的socket
-        let mut ftrs = FuturesUnordered::new(); // *存放future
-        let mut errors: HashSet<String> = HashSet::new();   // *存放错误信息
-
-        for _ in 0..self.batch_size {   // *批量扫描,batch_size为批量大小
-            if let Some(socket) = socket_iterator.next() {  // *获取下一个socket
-                ftrs.push(self.scan_socket(socket));    // *将socket加入future
-            } else {
-                break;
-            }
+    pub async fn run(&self) -> Vec<ScanResult> {
+        let mut results: Vec<ScanResult> = Vec::new(); // *存放每个socket的扫描结果
+        let mut state_counts: HashMap<PortState, usize> = HashMap::new();
+        let mut scan = Box::pin(self.stream());
+
+        while let Some(result) = scan.next().await {
+            *state_counts.entry(result.state).or_insert(0) += 1;
+            results.push(result);
         }
 
+        debug!("Port states found: {:?}", state_counts);
+        debug!(
+            "Open Sockets found: {:?}",
+            results
+                .iter()
+                .filter(|r| r.state == PortState::Open)
+                .map(|r| r.socket)
+                .collect::<Vec<SocketAddr>>()
+        );
+        results
+    }
+
+    /// Scans the same sockets as `run`, but yields every `ScanResult` as
+    /// soon as its socket is done instead of waiting for the whole scan.
+    ///
+    /// Results come in completion order, so consumers can start working on
+    /// open ports while the rest of the sockets are still being scanned.
+    ///
+    /// ```rust
+    /// # use async_std::{prelude::*, task::block_on};
+    /// # use std::{net::IpAddr, time::Duration};
+    /// # use rustscan::input::ScanOrder;
+    /// # use rustscan::port_strategy::PortStrategy;
+    /// # use rustscan::scanner::{PortState, Scanner};
+    /// let addrs = vec!["127.0.0.1".parse::<IpAddr>().unwrap()];
+    /// let strategy = PortStrategy::pick(&None, Some(vec![22, 80, 443]), ScanOrder::Serial);
+    /// let scanner = Scanner::new(
+    ///     &addrs,
+    ///     10,
+    ///     Duration::from_millis(100),
+    ///     1,
+    ///     true,
+    ///     strategy,
+    ///     true,
+    ///     vec![],
+    /// );
+    ///
+    /// block_on(async {
+    ///     let mut results = Box::pin(scanner.stream());
+    ///     while let Some(result) = results.next().await {
+    ///         if result.state == PortState::Open {
+    ///             println!("{} is open", result.socket);
+    ///         }
+    ///     }
+    /// });
+    /// ```
+    pub fn stream(&self) -> impl Stream<Item = ScanResult> + '_ {
+        self.stream_with(move |socket| self.scan_socket(socket))
+    }
+
+    /// `stream` with `scan` telling the outcome of every socket.
+    fn stream_with<'a, F, Fut>(&'a self, scan: F) -> impl Stream<Item = ScanResult> + 'a
+    where
+        F: Fn(SocketAddr) -> Fut + Copy + 'a,
+        Fut: Future<Output = Outcome> + 'a,
+    {
+        let port_count = self.ports().len();
+
         debug!("Start scanning sockets. \nBatch size {}\nNumber of ip-s {}\nNumber of ports {}\nTargets all together {} ",
             self.batch_size,
-            self.ips.len(),
-            &ports.len(),
-            (self.ips.len() * ports.len()));    // *打印扫描信息
+            self.targets.len(),
+            port_count,
+            (self.targets.len().saturating_mul(port_count as u128)));    // *打印扫描信息
 
-        while let Some(result) = ftrs.next().await {
-            if let Some(socket) = socket_iterator.next() {
-                ftrs.push(self.scan_socket(socket));
-            }
+        let mut socket_iterator = SocketIterator::new(&self.targets, &self.port_strategy);   // *生成socket迭代器
+        if let Some(seed) = self.shuffle_seed {
+            socket_iterator = socket_iterator.shuffled(seed);
+        }
+        if let Some(shard) = self.shard {
+            socket_iterator = socket_iterator.sharded(shard);
+        }
+        if let Some(live) = &self.live_hosts {
+            socket_iterator = socket_iterator.live(live);
+        }
+        let (position, done) = &self.resume_from;
+        let socket_iterator = socket_iterator
+            .resumed(*position, done)
+            .tracked(&self.progress);
+        let batch_size = usize::from(self.batch_size);  // *批量扫描,batch_size为批量大小
+        let throttle = Throttle::new(socket_iterator, self.rate_limit, batch_size, Instant::now());
+        let ftrs = FuturesUnordered::new(); // *存放future
 
-            match result {
-                Ok(socket) => open_sockets.push(socket),    // *添加打开的socket
-                Err(e) => {
-                    let error_string = e.to_string();
-                    if errors.len() < self.ips.len() * 1000 {
-                        errors.insert(error_string);
+        // Every finished socket makes room for the next one, which keeps
+        // batch_size futures in flight until the iterator is exhausted. With
+        // a rate limit, the throttle also tells when to wake up for the next
+        // token even though no socket finished. Once stopped, only the
+        // sockets in flight are left to finish.
+        stream::unfold(
+            (throttle, ftrs, batch_size),
+            move |(mut throttle, mut ftrs, mut batch_size)| async move {
+                loop {
+                    let now = Instant::now();
+                    let stopped = self.stopped();
+                    if !stopped {
+                        while let Some(socket) = throttle.next(ftrs.len() < batch_size, now) {
+                            ftrs.push(scan(socket));    // *将socket加入future
+                        }
+                    }
+
+                    let wake_at = if stopped { None } else { throttle.wake_at(now) };
+                    let outcome = match wake_at {
+                        None => ftrs.next().await?,
+                        Some(wake_at) => {
+                            let wait = wake_at.saturating_duration_since(now);
+                            if ftrs.is_empty() {
+                                async_std::task::sleep(wait).await;
+                                continue;
+                            }
+                            match async_std::future::timeout(wait, ftrs.next()).await {
+                                Ok(outcome) => outcome?,
+                                Err(_) => continue,
+                            }
+                        }
+                    };
+
+                    match outcome {
+                        Outcome::Scanned(result) => {
+                            throttle.recovered();
+                            let mut progress = self.progress.lock().unwrap();
+                            progress.finished(result.socket);
+                            if let Some(rtt) = &self.rtt {
+                                rtt.forget_done(progress.resume_at());
+                            }
+                            drop(progress);
+                            return Some((result, (throttle, ftrs, batch_size)));
+                        }
+                        Outcome::OutOfFiles(socket) => {
+                            // The sockets still in flight are about as many as
+                            // the file descriptor limit allows, so the batch
+                            // shrinks to them and the socket is tried again.
+                            batch_size = ftrs.len().clamp(1, batch_size);
+                            self.lowest_batch_size
+                                .fetch_min(batch_size, Ordering::Relaxed);
+                            debug!(
+                                "Out of file descriptors, batch size reduced to {}",
+                                batch_size
+                            );
+                            throttle.retry(socket, Instant::now());
+                        }
                     }
                 }
+            },
+        )
+    }
+
+    /// Has `completed_hosts` return `ip` once all of its sockets are done.
+    /// Only meant for the hosts that answered, it takes a pass over the
+    /// ports of the host with a shuffled scan order.
+    pub fn watch(&self, ip: IpAddr) {
+        if let Some(last) = self.last_position_of(ip) {
+            self.watched.lock().unwrap().watch(last, ip);
+        }
+    }
+
+    /// The watched hosts whose sockets all got done since the last call.
+    pub fn completed_hosts(&self) -> Vec<IpAddr> {
+        let done = self.progress.lock().unwrap().resume_at();
+        self.watched.lock().unwrap().done(done)
+    }
+
+    /// The watched hosts still waiting for some of their sockets, which are
+    /// no longer watched, once an interrupted scan is over.
+    pub fn unfinished_hosts(&self) -> Vec<IpAddr> {
+        self.watched.lock().unwrap().take()
+    }
+
+    /// The position of the last socket of `ip` in the scan, or None when
+    /// none of its sockets are scanned.
+    fn last_position_of(&self, ip: IpAddr) -> Option<u128> {
+        if self.port_count_on(ip) == 0 {
+            return None;
+        }
+        let host = self.targets.position(ip)?;
+        let layout = ShardLayout::new(self.targets.len(), self.port_strategy.len(), self.shard);
+        let mut positions =
+            (0..self.port_strategy.len() as u128).filter_map(|port| layout.position(port, host));
+        match self.shuffle_seed {
+            Some(seed) => {
+                let order = Permutation::new(layout.len(), seed);
+                positions.map(|position| order.position(position)).max()
             }
+            // The positions go a port at a time.
+            None => positions.next_back(),
+        }
+    }
+
+    /// Returns the ports scanned on every IP in scan order, with the
+    /// excluded ports already filtered out.
+    pub fn ports(&self) -> Vec<u16> {
+        self.port_strategy.order()
+    }
+
+    /// The ports scanned on every IP, as ascending ranges.
+    pub fn port_ranges(&self) -> Vec<RangeInclusive<u16>> {
+        self.port_strategy.ranges()
+    }
+
+    /// The number of hosts with at least one port scanned, the live ones
+    /// only after discovery, counted without walking the targets.
+    pub fn host_count(&self) -> u128 {
+        if let Some(live) = &self.live_hosts {
+            return live.iter().filter(|ip| self.port_count_on(*ip) > 0).count() as u128;
+        }
+        let hosts = self.targets.len();
+        let ports = self.port_strategy.len();
+        if ports == 0 {
+            return 0;
+        }
+        match self.shard {
+            // Every `shard.count` hosts in a row get the same share of the
+            // ports, some of them none when there are fewer ports.
+            Some(shard) => {
+                let count = u128::from(shard.count);
+                let per_cycle = (ports as u128).min(count);
+                let rest = (hosts - hosts % count..hosts)
+                    .filter(|&host| ports_in_shard(shard, ports, host) > 0)
+                    .count() as u128;
+                hosts / count * per_cycle + rest
+            }
+            None => hosts,
+        }
+    }
+
+    /// The number of ports scanned on `ip`, which is only a part of the
+    /// ports with a shard, or 0 when `ip` is not a target or is down.
+    pub fn port_count_on(&self, ip: IpAddr) -> usize {
+        let Some(host) = self.targets.position(ip) else {
+            return 0;
+        };
+        if self
+            .live_hosts
+            .as_ref()
+            .is_some_and(|live| !live.contains(ip))
+        {
+            return 0;
+        }
+        match self.shard {
+            Some(shard) => ports_in_shard(shard, self.port_strategy.len(), host),
+            None => self.port_strategy.len(),
         }
-        debug!("Typical socket connection errors {:?}", errors);
-        debug!("Open Sockets found: {:?}", &open_sockets);
-        open_sockets
     }
 
     /// Given a socket, scan it self.tries times.
     /// Turns the address into a SocketAddr
     /// Deals with the `<result>` type
-    /// If it experiences error ErrorKind::Other then too many files are open and it Panics!
-    /// Else any other error, it returns the error in Result as a string
-    /// If no errors occur, it returns the port number in Result to signify the port is open.
+    /// If it runs out of file descriptors, the socket is handed back to be scanned again later.
+    /// Else the error of the last try is turned into the matching `PortState`.
+    /// If no errors occur, the socket is reported as `PortState::Open`.
     /// This function mainly deals with the logic of Results handling.
     /// # Example
     ///
@@ -129,12 +757,24 @@ impl Scanner {
     /// ```
     ///
     /// Note: `self` must contain `self.ip`.
-    async fn scan_socket(&self, socket: SocketAddr) -> io::Result<SocketAddr> {
+    async fn scan_socket(&self, socket: SocketAddr) -> Outcome {
         let tries = self.tries.get();   // *获取尝试次数
 
+        if self.udp {
+            return self.udp_scan(socket).await;
+        }
+        #[cfg(feature = "syn")]
+        if let Some(syn) = &self.syn {
+            return self.syn_scan(syn, socket).await;
+        }
+
         for nr_try in 1..=tries {   // *尝试连接,nr_try为尝试次数
             match self.connect(socket).await {  // *连接socket
-                Ok(x) => {  // *连接成功
+                Ok(mut x) => {  // *连接成功
+                    let banner = match self.banner_wait {
+                        Some(wait) => banner::grab(&mut x, wait).await,
+                        None => None,
+                    };
                     debug!(
                         "Connection was successful, shutting down stream {}",
                         &socket
@@ -142,26 +782,27 @@ impl Scanner {
                     if let Err(e) = x.shutdown(Shutdown::Both) {    // *关闭stream
                         debug!("Shutdown stream error {}", &e);
                     }
-                    if !self.greppable {    // *打印扫描结果
-                        if self.accessible {
-                            println!("Open {socket}");
-                        } else {
-                            println!("Open {}", socket.to_string().purple());
-                        }
-                    }
 
                     debug!("Return Ok after {} tries", nr_try);
-                    return Ok(socket);
+                    let mut result = ScanResult::new(socket, PortState::Open);
+                    result.details = PortDetails {
+                        banner,
+                        tls: self.detect_tls(socket).await,
+                        service: self.detect_service(socket).await,
+                    };
+                    self.print_open(&result);    // *打印扫描结果
+                    return Outcome::Scanned(result);
                 }
                 Err(e) => { // *连接失败
-                    let mut error_string = e.to_string();   // *获取错误信息
-
-                    assert!(!error_string.to_lowercase().contains("too many open files"), "Too many open files. Please reduce batch size. The default is 5000. Try -b 2500.");
+                    if is_out_of_files(&e) {
+                        return Outcome::OutOfFiles(socket);
+                    }
 
                     if nr_try == tries {    // *尝试次数用完
-                        error_string.push(' ');
-                        error_string.push_str(&socket.ip().to_string());
-                        return Err(io::Error::new(io::ErrorKind::Other, error_string));
+                        return Outcome::Scanned(ScanResult::new(
+                            socket,
+                            PortState::from_error(&e),
+                        ));
                     }
                 }
             };
@@ -169,6 +810,36 @@ impl Scanner {
         unreachable!();   // *unreachable!() 是一个宏，当执行到这个宏的时候，它会导致程序立即崩溃，并给出一个 panic 信息。这个宏通常用在你确定某段代码永远不会被执行到的地方，如果这段代码被执行到了，那么说明你的程序存在逻辑错误。
     }
 
+    /// The TLS handshake on the open TCP `socket`, with TLS detection on.
+    async fn detect_tls(&self, socket: SocketAddr) -> Option<TlsInfo> {
+        let (tls, wait) = self.tls.as_ref()?;
+        let stream = self.connect(socket).await.ok()?;
+        tls.probe(stream, socket, *wait).await
+    }
+
+    /// The service on the open TCP `socket`, with service detection on.
+    async fn detect_service(&self, socket: SocketAddr) -> Option<Service> {
+        let (probes, wait) = self.services.as_ref()?;
+        probes
+            .detect(socket.port(), *wait, || self.connect(socket))
+            .await
+    }
+
+    /// Prints an open TCP port as soon as it is found, with what is known
+    /// about its service.
+    fn print_open(&self, result: &ScanResult) {
+        if self.greppable {
+            return;
+        }
+        let socket = result.socket;
+        let details = &result.details;
+        if self.accessible {
+            println!("Open {socket}{details}");
+        } else {
+            println!("Open {}{details}", socket.to_string().purple());
+        }
+    }
+
     /// Performs the connection to the socket with timeout
     /// # Example
     ///
@@ -180,16 +851,121 @@ impl Scanner {
     /// let socket = SocketAddr::new(ip, port);
     /// scanner.connect(socket);
     /// // returns Result which is either Ok(stream) for port is open, or Er for port is closed.
-    /// // Timeout occurs after self.timeout seconds
+    /// // Timeout occurs after self.timeout seconds, or the adaptive timeout of the host
     /// ```
     ///
     async fn connect(&self, socket: SocketAddr) -> io::Result<TcpStream> {  // *连接socket
-        let stream: TcpStream = io::timeout(
-            self.timeout,
-            async move { TcpStream::connect(socket).await },
-        )
-        .await?;
-        Ok(stream)
+        let started = Instant::now();
+        let stream = io::timeout(self.timeout_for(socket.ip()), async move {
+            match &self.proxy {
+                Some(proxy) => proxy.connect(socket, &self.source).await,
+                None => self.source.connect(socket).await,
+            }
+        })
+        .await;
+        self.record_rtt(socket.ip(), started, &stream);
+        stream
+    }
+
+    /// Probes a TCP socket self.tries times with a SYN.
+    ///
+    /// The answer tells the state:
+    ///   - a SYN/ACK means the port is `PortState::Open`.
+    ///   - a RST means the port is `PortState::Closed`.
+    ///   - silence until the timeout means the port is `PortState::Filtered`.
+    ///
+    /// Only the silent case is retried.
+    #[cfg(feature = "syn")]
+    async fn syn_scan(&self, syn: &SynScan, socket: SocketAddr) -> Outcome {
+        let tries = self.tries.get();
+
+        for nr_try in 1..=tries {
+            let started = Instant::now();
+            let answer = syn.probe(socket, self.timeout_for(socket.ip())).await;
+            self.record_rtt(socket.ip(), started, &answer);
+            match answer {
+                Ok(state) => {
+                    let mut result = ScanResult::new(socket, state);
+                    if state == PortState::Open {
+                        result.details.tls = self.detect_tls(socket).await;
+                        result.details.service = self.detect_service(socket).await;
+                        self.print_open(&result);
+                    }
+
+                    debug!("Return {} after {} tries", state, nr_try);
+                    return Outcome::Scanned(result);
+                }
+                Err(e) if e.kind() == io::ErrorKind::TimedOut && nr_try < tries => {
+                    debug!("No SYN answer from {}, retrying", &socket);
+                }
+                Err(e) => {
+                    return Outcome::Scanned(ScanResult::new(socket, PortState::from_error(&e)))
+                }
+            }
+        }
+        unreachable!();
+    }
+
+    /// Probes a UDP socket self.tries times with the payload registered for its port.
+    ///
+    /// UDP has no handshake, so the state is inferred from what comes back:
+    ///   - any datagram means the port is `PortState::Open`.
+    ///   - an ICMP port unreachable surfaces as `ConnectionRefused`, the port is `PortState::Closed`.
+    ///   - silence until the timeout means the port is `PortState::OpenFiltered`.
+    ///
+    /// Only the silent case is retried, since a lost probe or reply is the
+    /// only thing another try can fix.
+    async fn udp_scan(&self, socket: SocketAddr) -> Outcome {
+        let payload = payload_for_port(socket.port());
+        let tries = self.tries.get();
+
+        for nr_try in 1..=tries {
+            match self.udp_probe(socket, payload).await {
+                Ok(len) => {
+                    debug!("Received {} bytes from {}", len, &socket);
+                    if !self.greppable {
+                        if self.accessible {
+                            println!("Open {socket}/udp");
+                        } else {
+                            println!("Open {}", format!("{socket}/udp").purple());
+                        }
+                    }
+
+                    debug!("Return Ok after {} tries", nr_try);
+                    return Outcome::Scanned(ScanResult::new(socket, PortState::Open));
+                }
+                Err(e) if e.kind() == io::ErrorKind::TimedOut => {
+                    if nr_try == tries {
+                        return Outcome::Scanned(ScanResult::new(socket, PortState::OpenFiltered));
+                    }
+                    debug!("No UDP answer from {}, retrying", &socket);
+                }
+                Err(e) if is_out_of_files(&e) => return Outcome::OutOfFiles(socket),
+                Err(e) => {
+                    return Outcome::Scanned(ScanResult::new(socket, PortState::from_error(&e)))
+                }
+            }
+        }
+        unreachable!();
+    }
+
+    /// Sends a single UDP probe and waits the host timeout for the first answer.
+    /// Returns the number of bytes received.
+    async fn udp_probe(&self, socket: SocketAddr, payload: &[u8]) -> io::Result<usize> {
+        let udp_socket = self.source.udp_socket(socket).await?;
+        // Connecting the socket makes the kernel report ICMP errors for it
+        // and drops datagrams coming from any other peer.
+        udp_socket.connect(socket).await?;
+        let started = Instant::now();
+        udp_socket.send(payload).await?;
+
+        let mut buf = [0u8; 1024];
+        let received = io::timeout(self.timeout_for(socket.ip()), async {
+            udp_socket.recv(&mut buf).await
+        })
+        .await;
+        self.record_rtt(socket.ip(), started, &received);
+        received
     }
 }
 
@@ -197,6 +973,7 @@ impl Scanner {
 mod tests {
     use super::*;
     use crate::input::{PortRange, ScanOrder};
+    use crate::test_utils::closed_port;
     use async_std::task::block_on;
     use std::{net::IpAddr, time::Duration};
 
@@ -312,4 +1089,390 @@ mod tests {
         block_on(scanner.run());
         assert_eq!(1, 1);
     }
+
+    #[test]
+    fn udp_scan_finds_responding_port() {
+        let server = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let port = server.local_addr().unwrap().port();
+        std::thread::spawn(move || {
+            let mut buf = [0u8; 512];
+            if let Ok((_, peer)) = server.recv_from(&mut buf) {
+                server.send_to(b"pong", peer).unwrap();
+            }
+        });
+
+        let addrs = vec!["127.0.0.1".parse::<IpAddr>().unwrap()];
+        let strategy = PortStrategy::pick(&None, Some(vec![port]), ScanOrder::Serial);
+        let scanner = Scanner::new(
+            &addrs,
+            10,
+            Duration::from_millis(500),
+            1,
+            true,
+            strategy,
+            true,
+            vec![],
+        )
+        .with_udp(true);
+        let results = block_on(scanner.run());
+        assert_eq!(
+            results,
+            vec![ScanResult::new(
+                SocketAddr::new(addrs[0], port),
+                PortState::Open
+            )]
+        );
+    }
+
+    #[test]
+    fn udp_scan_reports_closed_port() {
+        // Grab a free port and release it so nothing is listening there.
+        let port = std::net::UdpSocket::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+
+        let addrs = vec!["127.0.0.1".parse::<IpAddr>().unwrap()];
+        let strategy = PortStrategy::pick(&None, Some(vec![port]), ScanOrder::Serial);
+        let scanner = Scanner::new(
+            &addrs,
+            10,
+            Duration::from_millis(200),
+            1,
+            true,
+            strategy,
+            true,
+            vec![],
+        )
+        .with_udp(true);
+        let results = block_on(scanner.run());
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].state, PortState::Closed);
+    }
+
+    #[test]
+    fn tcp_scan_reports_open_and_closed_ports() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let open_port = listener.local_addr().unwrap().port();
+        let closed_port = closed_port();
+
+        let addrs = vec!["127.0.0.1".parse::<IpAddr>().unwrap()];
+        let strategy =
+            PortStrategy::pick(&None, Some(vec![open_port, closed_port]), ScanOrder::Serial);
+        let scanner = Scanner::new(
+            &addrs,
+            10,
+            Duration::from_millis(500),
+            1,
+            true,
+            strategy,
+            true,
+            vec![],
+        );
+        let mut results = block_on(scanner.run());
+        results.sort_by_key(|r| r.state);
+        assert_eq!(
+            results,
+            vec![
+                ScanResult::new(SocketAddr::new(addrs[0], open_port), PortState::Open),
+                ScanResult::new(SocketAddr::new(addrs[0], closed_port), PortState::Closed),
+            ]
+        );
+    }
+
+    #[test]
+    fn port_state_from_error_kind() {
+        let state = |kind| PortState::from_error(&io::Error::from(kind));
+        assert_eq!(state(io::ErrorKind::ConnectionRefused), PortState::Closed);
+        assert_eq!(state(io::ErrorKind::TimedOut), PortState::Filtered);
+        assert_eq!(
+            state(io::ErrorKind::HostUnreachable),
+            PortState::Unreachable
+        );
+        assert_eq!(
+            state(io::ErrorKind::NetworkUnreachable),
+            PortState::Unreachable
+        );
+    }
+
+    #[test]
+    fn stream_yields_every_socket() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let open_port = listener.local_addr().unwrap().port();
+
+        let addrs = vec!["127.0.0.1".parse::<IpAddr>().unwrap()];
+        let mut ports: Vec<u16> = (40_000..40_050).collect();
+        ports.push(open_port);
+        let strategy = PortStrategy::pick(&None, Some(ports), ScanOrder::Serial);
+        let scanner = Scanner::new(
+            &addrs,
+            10,
+            Duration::from_millis(200),
+            1,
+            true,
+            strategy,
+            true,
+            vec![],
+        );
+
+        let results: Vec<ScanResult> = block_on(futures::StreamExt::collect(scanner.stream()));
+        assert_eq!(results.len(), 51);
+        assert!(results.contains(&ScanResult::new(
+            SocketAddr::new(addrs[0], open_port),
+            PortState::Open
+        )));
+    }
+
+    #[test]
+    fn adaptive_timeout_learns_from_answered_connections() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let open_port = listener.local_addr().unwrap().port();
+
+        let addrs = vec!["127.0.0.1".parse::<IpAddr>().unwrap()];
+        let strategy = PortStrategy::pick(&None, Some(vec![open_port, 40_000]), ScanOrder::Serial);
+        // One socket at a time, so the second one is still to come once
+        // the first one is yielded.
+        let scanner = Scanner::new(
+            &addrs,
+            1,
+            Duration::from_millis(1_000),
+            1,
+            true,
+            strategy,
+            true,
+            vec![],
+        )
+        .with_adaptive_timeout(Duration::from_millis(50), Duration::from_millis(2_000));
+
+        assert_eq!(scanner.timeout_for(addrs[0]), Duration::from_millis(1_000));
+        block_on(async {
+            let mut scan = Box::pin(scanner.stream());
+            assert!(scan.next().await.is_some());
+            // Loopback answers in microseconds, so the minimum kicks in.
+            assert_eq!(scanner.timeout_for(addrs[0]), Duration::from_millis(50));
+            assert!(scan.next().await.is_some());
+            assert!(scan.next().await.is_none());
+        });
+        // The host is done, so its estimate is gone.
+        assert_eq!(scanner.timeout_for(addrs[0]), Duration::from_millis(1_000));
+    }
+
+    #[test]
+    fn out_of_files_shrinks_the_batch_and_retries_the_socket() {
+        let addrs = vec!["127.0.0.1".parse::<IpAddr>().unwrap()];
+        let range = PortRange { start: 1, end: 20 };
+        let scanner = Scanner::new(
+            &addrs,
+            5,
+            Duration::from_millis(100),
+            1,
+            true,
+            PortStrategy::pick(&Some(range), None, ScanOrder::Serial),
+            true,
+            vec![],
+        );
+        assert_eq!(scanner.reduced_batch_size(), None);
+
+        // Port 3 runs out of files the first time, while the sockets
+        // before it are still in flight.
+        let failed = AtomicBool::new(false);
+        let failed = &failed;
+        let scan = scanner.stream_with(move |socket| async move {
+            if socket.port() == 3 && !failed.swap(true, Ordering::Relaxed) {
+                return Outcome::OutOfFiles(socket);
+            }
+            async_std::task::sleep(Duration::from_millis(10)).await;
+            Outcome::Scanned(ScanResult::new(socket, PortState::Closed))
+        });
+        let results: Vec<ScanResult> = block_on(futures::StreamExt::collect(scan));
+
+        let mut ports: Vec<u16> = results.iter().map(|r| r.socket.port()).collect();
+        ports.sort_unstable();
+        assert_eq!(ports, (1..=20).collect::<Vec<u16>>());
+        assert!(failed.load(Ordering::Relaxed));
+        assert!(scanner.reduced_batch_size().is_some_and(|size| size < 5));
+        assert_eq!(scanner.checkpoint(), (20, vec![]));
+    }
+
+    #[test]
+    fn max_rate_slows_the_scan_down() {
+        let addrs = vec!["127.0.0.1".parse::<IpAddr>().unwrap()];
+        let strategy =
+            PortStrategy::pick(&None, Some((40_000..40_020).collect()), ScanOrder::Serial);
+        let scanner = Scanner::new(
+            &addrs,
+            100,
+            Duration::from_millis(200),
+            1,
+            true,
+            strategy,
+            true,
+            vec![],
+        )
+        .with_rate_limit(RateLimit {
+            max_rate: Some(100),
+            ..RateLimit::default()
+        });
+
+        let start = Instant::now();
+        let results = block_on(scanner.run());
+        // 2 connections of burst, then one every 10ms.
+        assert_eq!(results.len(), 20);
+        assert!(start.elapsed() >= Duration::from_millis(150));
+    }
+
+    #[test]
+    fn stop_signal_drains_sockets_in_flight() {
+        let addrs = vec!["127.0.0.1".parse::<IpAddr>().unwrap()];
+        let strategy =
+            PortStrategy::pick(&None, Some((40_000..40_100).collect()), ScanOrder::Serial);
+        let stop = Arc::new(AtomicBool::new(false));
+        let scanner = Scanner::new(
+            &addrs,
+            10,
+            Duration::from_millis(200),
+            1,
+            true,
+            strategy,
+            true,
+            vec![],
+        )
+        .with_stop_signal(Arc::clone(&stop));
+
+        let results: Vec<ScanResult> = block_on(async {
+            let mut scan = Box::pin(scanner.stream());
+            let mut results = vec![scan.next().await.unwrap()];
+            stop.store(true, Ordering::Relaxed);
+            while let Some(result) = scan.next().await {
+                results.push(result);
+            }
+            results
+        });
+
+        assert!(scanner.stopped());
+        // The rest of the first batch was in flight, no socket was started
+        // after the stop.
+        assert_eq!(results.len(), 10);
+        let (position, done) = scanner.checkpoint();
+        assert_eq!(position, 10);
+        assert!(done.is_empty());
+    }
+
+    #[test]
+    fn host_count_matches_the_hosts_with_ports() {
+        let addrs: Vec<IpAddr> = (1..=10)
+            .map(|n| format!("10.0.0.{n}").parse().unwrap())
+            .collect();
+        let scanner = |ports: u16| {
+            let range = PortRange {
+                start: 1,
+                end: ports,
+            };
+            Scanner::new(
+                &addrs,
+                10,
+                Duration::from_millis(100),
+                1,
+                true,
+                PortStrategy::pick(&Some(range), None, ScanOrder::Serial),
+                true,
+                vec![],
+            )
+        };
+        let counted = |scanner: &Scanner| {
+            addrs
+                .iter()
+                .filter(|ip| scanner.port_count_on(**ip) > 0)
+                .count() as u128
+        };
+
+        assert_eq!(scanner(3).host_count(), 10);
+        for ports in [1, 3, 4, 5] {
+            for index in 1..=4 {
+                let sharded = scanner(ports).with_shard(Shard { index, count: 4 });
+                assert_eq!(sharded.host_count(), counted(&sharded));
+            }
+        }
+
+        let live: Targets = addrs[..3].iter().copied().collect();
+        let discovered = scanner(3).with_live_hosts(live);
+        assert_eq!(discovered.host_count(), 3);
+    }
+
+    #[test]
+    fn last_positions_are_where_the_walk_leaves_the_hosts() {
+        let addrs: Vec<IpAddr> = (1..=7)
+            .map(|n| format!("10.0.0.{n}").parse().unwrap())
+            .collect();
+        let targets = Targets::from(&addrs);
+        let strategy = || PortStrategy::pick(&None, Some((1..=10).collect()), ScanOrder::Serial);
+
+        for seed in [None, Some(3)] {
+            for shard in [None, Some(Shard { index: 2, count: 3 })] {
+                let mut scanner = Scanner::new(
+                    &addrs,
+                    10,
+                    Duration::from_millis(100),
+                    1,
+                    true,
+                    strategy(),
+                    true,
+                    vec![],
+                );
+                let ports = strategy();
+                let mut walk = SocketIterator::new(&targets, &ports);
+                if let Some(seed) = seed {
+                    scanner = scanner.with_interleaved_order(seed);
+                    walk = walk.shuffled(seed);
+                }
+                if let Some(shard) = shard {
+                    scanner = scanner.with_shard(shard);
+                    walk = walk.sharded(shard);
+                }
+
+                let sockets: Vec<SocketAddr> = walk.collect();
+                for ip in &addrs {
+                    let last = sockets.iter().rposition(|socket| socket.ip() == *ip);
+                    assert_eq!(
+                        scanner.last_position_of(*ip),
+                        last.map(|position| position as u128),
+                        "{ip}, seed {seed:?}, {shard:?}"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn watched_hosts_complete_once_their_sockets_are_done() {
+        let ip: IpAddr = "127.0.0.1".parse().unwrap();
+        let range = PortRange { start: 1, end: 20 };
+        let scanner = Scanner::new(
+            &vec![ip],
+            10,
+            Duration::from_millis(100),
+            1,
+            true,
+            PortStrategy::pick(&Some(range), None, ScanOrder::Serial),
+            true,
+            vec![],
+        );
+        scanner.watch(ip);
+        assert!(scanner.completed_hosts().is_empty());
+        assert_eq!(scanner.unfinished_hosts(), vec![ip]);
+        scanner.watch(ip);
+
+        let scanned = block_on(async {
+            let mut scan = Box::pin(scanner.stream());
+            let mut scanned = 0;
+            while scan.next().await.is_some() {
+                scanned += 1;
+            }
+            scanned
+        });
+        assert_eq!(scanned, 20);
+        assert_eq!(scanner.completed_hosts(), vec![ip]);
+        assert!(scanner.completed_hosts().is_empty());
+    }
 }
@@ -0,0 +1,139 @@
+//! A keyed pseudo-random permutation of the indexes `0..len`.
+//!
+//! It lets the scanner visit every IP and port combination exactly once, in
+//! an order that looks random, while only storing a handful of keys: the
+//! index of the n-th socket is computed on demand. It is a small Feistel
+//! network over the smallest power of two with an even number of bits that
+//! holds `len`, and indexes that land outside of `0..len` are encrypted
+//! again until they fall inside ("cycle walking"), which keeps it a
+//! permutation of `0..len` itself.
+const ROUNDS: usize = 4;
+
+#[derive(Debug, Clone)]
+pub(super) struct Permutation {
+    len: u128,
+    half_bits: u32,
+    keys: [u64; ROUNDS],
+}
+
+impl Permutation {
+    /// The same `len` and `seed` always give the same permutation.
+    pub fn new(len: u128, seed: u64) -> Self {
+        let bits = (128 - len.saturating_sub(1).leading_zeros()).max(2);
+        let mut state = seed;
+        let mut keys = [0; ROUNDS];
+        for key in &mut keys {
+            *key = splitmix64(&mut state);
+        }
+
+        Self {
+            len,
+            half_bits: bits.div_ceil(2),
+            keys,
+        }
+    }
+
+    /// The index at `position` of the permutation, `position` must be below
+    /// `len`.
+    pub fn get(&self, position: u128) -> u128 {
+        // The domain holds less than four times len, so this takes less
+        // than four rounds on average.
+        let mut index = self.encrypt(position);
+        while index >= self.len {
+            index = self.encrypt(index);
+        }
+        index
+    }
+
+    /// The position of `index` in the permutation, the reverse of `get`.
+    pub fn position(&self, index: u128) -> u128 {
+        let mut position = self.decrypt(index);
+        while position >= self.len {
+            position = self.decrypt(position);
+        }
+        position
+    }
+
+    fn encrypt(&self, value: u128) -> u128 {
+        let mask = (1u128 << self.half_bits) - 1;
+        let mut left = value >> self.half_bits;
+        let mut right = value & mask;
+        for key in &self.keys {
+            // Halves are at most 64 bits wide.
+            let mut state = (right as u64) ^ key;
+            let mixed = u128::from(splitmix64(&mut state)) & mask;
+            let next = left ^ mixed;
+            left = right;
+            right = next;
+        }
+        (left << self.half_bits) | right
+    }
+
+    fn decrypt(&self, value: u128) -> u128 {
+        let mask = (1u128 << self.half_bits) - 1;
+        let mut left = value >> self.half_bits;
+        let mut right = value & mask;
+        for key in self.keys.iter().rev() {
+            let mut state = (left as u64) ^ key;
+            let mixed = u128::from(splitmix64(&mut state)) & mask;
+            let previous = right ^ mixed;
+            right = left;
+            left = previous;
+        }
+        (left << self.half_bits) | right
+    }
+}
+
+/// The SplitMix64 generator, a cheap way to get well mixed 64 bit values.
+fn splitmix64(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Permutation;
+
+    #[test]
+    fn visits_every_index_once() {
+        for len in [1, 2, 3, 10, 255, 256, 1000, 4097] {
+            let permutation = Permutation::new(len, 42);
+            let mut indexes: Vec<u128> = (0..len).map(|p| permutation.get(p)).collect();
+            indexes.sort_unstable();
+            assert_eq!(indexes, (0..len).collect::<Vec<u128>>(), "len {len}");
+        }
+    }
+
+    #[test]
+    fn position_is_the_reverse_of_get() {
+        for len in [1, 2, 3, 10, 1000, 4097] {
+            let permutation = Permutation::new(len, 7);
+            for position in 0..len {
+                assert_eq!(permutation.position(permutation.get(position)), position);
+            }
+        }
+    }
+
+    #[test]
+    fn order_depends_only_on_the_seed() {
+        let indexes = |seed| -> Vec<u128> {
+            let permutation = Permutation::new(1000, seed);
+            (0..1000).map(|p| permutation.get(p)).collect()
+        };
+
+        assert_eq!(indexes(7), indexes(7));
+        assert_ne!(indexes(7), indexes(8));
+        assert_ne!(indexes(7), (0..1000).collect::<Vec<u128>>());
+    }
+
+    #[test]
+    fn handles_huge_products() {
+        let permutation = Permutation::new(u128::MAX, 1);
+        let index = permutation.get(u128::MAX - 1);
+        assert!(index < u128::MAX);
+        assert_ne!(permutation.get(0), permutation.get(1));
+    }
+}
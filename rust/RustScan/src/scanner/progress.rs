@@ -0,0 +1,136 @@
+//! Tracks how far a scan got, to resume it later.
+//!
+//! Sockets are started in the order of their position in the scan, but
+//! finish in any order, and the throttle can hold some back for a while.
+//! The point a scan can be resumed from is the position of the oldest
+//! socket still pending, plus the few sockets after it that are already
+//! done, which the resumed scan skips.
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::net::{IpAddr, SocketAddr};
+
+#[derive(Debug, Default)]
+pub(super) struct Progress {
+    /// The position after the last socket taken from the iterator.
+    next: u128,
+    /// The position of every started socket that is not done yet.
+    pending: HashMap<SocketAddr, u128>,
+    in_flight: BTreeSet<u128>,
+    /// The positions of the sockets done after the oldest pending one.
+    done: BTreeSet<u128>,
+}
+
+impl Progress {
+    /// A scan resumed at `position` has nothing pending yet.
+    pub fn new(position: u128) -> Self {
+        Self {
+            next: position,
+            ..Self::default()
+        }
+    }
+
+    /// Records that the socket at `position` was taken from the iterator.
+    pub fn started(&mut self, position: u128, socket: SocketAddr) {
+        self.next = position + 1;
+        self.pending.insert(socket, position);
+        self.in_flight.insert(position);
+    }
+
+    /// Records that `socket` has its result.
+    pub fn finished(&mut self, socket: SocketAddr) {
+        let Some(position) = self.pending.remove(&socket) else {
+            return;
+        };
+        self.in_flight.remove(&position);
+        self.done.insert(position);
+
+        let resume_at = self.resume_at();
+        self.done = self.done.split_off(&resume_at);
+    }
+
+    /// Every socket before this position is done.
+    pub fn resume_at(&self) -> u128 {
+        self.in_flight.iter().next().copied().unwrap_or(self.next)
+    }
+
+    /// The position to resume from, and the positions after it that are
+    /// already done.
+    pub fn checkpoint(&self) -> (u128, Vec<u128>) {
+        (self.resume_at(), self.done.iter().copied().collect())
+    }
+}
+
+/// Hosts waiting for their last socket to be done, by its position.
+#[derive(Debug, Default)]
+pub(super) struct Watchlist {
+    hosts: BTreeMap<u128, Vec<IpAddr>>,
+}
+
+impl Watchlist {
+    /// Has `ip` leave the list once the socket at `last` is done.
+    pub fn watch(&mut self, last: u128, ip: IpAddr) {
+        self.hosts.entry(last).or_default().push(ip);
+    }
+
+    /// Takes the hosts out whose last socket is before `resume_at`, the
+    /// position every socket before is done.
+    pub fn done(&mut self, resume_at: u128) -> Vec<IpAddr> {
+        let pending = self.hosts.split_off(&resume_at);
+        std::mem::replace(&mut self.hosts, pending)
+            .into_values()
+            .flatten()
+            .collect()
+    }
+
+    /// Takes every host out, done or not.
+    pub fn take(&mut self) -> Vec<IpAddr> {
+        std::mem::take(&mut self.hosts)
+            .into_values()
+            .flatten()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Progress, Watchlist};
+    use std::net::{IpAddr, SocketAddr};
+
+    fn socket(port: u16) -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 1], port))
+    }
+
+    #[test]
+    fn checkpoint_waits_for_the_oldest_pending_socket() {
+        let mut progress = Progress::new(10);
+        assert_eq!(progress.checkpoint(), (10, vec![]));
+
+        for position in 10..15 {
+            progress.started(position, socket(position as u16));
+        }
+        progress.finished(socket(11));
+        progress.finished(socket(13));
+        assert_eq!(progress.checkpoint(), (10, vec![11, 13]));
+
+        progress.finished(socket(10));
+        assert_eq!(progress.checkpoint(), (12, vec![13]));
+
+        progress.finished(socket(12));
+        progress.finished(socket(14));
+        assert_eq!(progress.checkpoint(), (15, vec![]));
+    }
+
+    #[test]
+    fn hosts_leave_the_watchlist_after_their_last_socket() {
+        let first: IpAddr = "10.0.0.1".parse().unwrap();
+        let second: IpAddr = "10.0.0.2".parse().unwrap();
+        let mut watchlist = Watchlist::default();
+        watchlist.watch(4, first);
+        watchlist.watch(9, second);
+
+        assert!(watchlist.done(4).is_empty());
+        assert_eq!(watchlist.done(5), vec![first]);
+        assert!(watchlist.done(5).is_empty());
+        assert_eq!(watchlist.take(), vec![second]);
+        assert!(watchlist.take().is_empty());
+    }
+}
@@ -0,0 +1,417 @@
+//! Connection rate limiting for the scanner.
+//!
+//! `batch_size` only bounds how many sockets are in flight, a fast network
+//! still sees thousands of new connections per second. The `Throttle` sits
+//! between the socket iterator and the futures of the scan and hands out
+//! the next socket only when the rate limits allow it:
+//!
+//! - `max_rate` is a token bucket shared by every connection.
+//! - `max_host_rate` gives every host its own bucket. Sockets of a host that
+//!   is out of tokens are put aside and the sockets of other hosts go first,
+//!   so a single target isn't hammered while the others idle.
+//! - `min_rate` lets sockets through even when `batch_size` sockets are
+//!   already in flight, whenever the scan falls behind that rate.
+//!
+//! Sockets that could not be opened because the process ran out of file
+//! descriptors are handed back with `retry`, they are scanned again first
+//! after a short pause that doubles while sockets keep failing.
+use std::collections::{HashMap, VecDeque};
+use std::net::{IpAddr, SocketAddr};
+use std::time::{Duration, Instant};
+
+/// The first pause after running out of file descriptors.
+const MIN_BACKOFF: Duration = Duration::from_millis(10);
+/// The longest pause, however many times in a row sockets failed.
+const MAX_BACKOFF: Duration = Duration::from_secs(1);
+
+/// The rate limits of a scan, in new connections per second.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RateLimit {
+    /// The most connections started per second, over all hosts.
+    pub max_rate: Option<u32>,
+    /// The fewest connections started per second, even above `batch_size`.
+    pub min_rate: Option<u32>,
+    /// The most connections started per second to a single host.
+    pub max_host_rate: Option<u32>,
+}
+
+/// A token bucket refilled at `rate` tokens per second.
+///
+/// The bucket holds up to 20ms worth of tokens, so high rates don't need a
+/// timer wake up for every single connection.
+#[derive(Debug, Clone)]
+struct TokenBucket {
+    rate: f64,
+    capacity: f64,
+    tokens: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    fn new(rate: u32, now: Instant) -> Self {
+        let rate = f64::from(rate);
+        let capacity = (rate / 50.0).max(1.0);
+        Self {
+            rate,
+            capacity,
+            tokens: capacity,
+            updated: now,
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.capacity);
+        self.updated = now;
+    }
+
+    fn ready(&mut self, now: Instant) -> bool {
+        self.refill(now);
+        self.tokens >= 1.0
+    }
+
+    fn take(&mut self) {
+        self.tokens -= 1.0;
+    }
+
+    fn is_full(&mut self, now: Instant) -> bool {
+        self.refill(now);
+        self.tokens >= self.capacity
+    }
+
+    /// When the next token is available, as of the last refill.
+    fn ready_at(&self) -> Instant {
+        if self.tokens >= 1.0 {
+            self.updated
+        } else {
+            self.updated + Duration::from_secs_f64((1.0 - self.tokens) / self.rate)
+        }
+    }
+}
+
+/// Hands out sockets from `sockets` as fast as the `RateLimit` allows.
+pub(super) struct Throttle<I> {
+    sockets: I,
+    exhausted: bool,
+    /// Sockets whose host was out of tokens when they came up.
+    deferred: VecDeque<SocketAddr>,
+    max_deferred: usize,
+    global: Option<TokenBucket>,
+    host_rate: Option<u32>,
+    hosts: HashMap<IpAddr, TokenBucket>,
+    min_rate: Option<u32>,
+    started: Instant,
+    started_sockets: u64,
+    /// Sockets that ran out of file descriptors, they go before any other.
+    retry: VecDeque<SocketAddr>,
+    backoff: Duration,
+    paused_until: Option<Instant>,
+}
+
+impl<I: Iterator<Item = SocketAddr>> Throttle<I> {
+    /// At most `max_deferred` sockets are put aside waiting for their host,
+    /// which keeps the memory bounded when most sockets are on one host.
+    pub fn new(sockets: I, limit: RateLimit, max_deferred: usize, now: Instant) -> Self {
+        Self {
+            sockets,
+            exhausted: false,
+            deferred: VecDeque::new(),
+            max_deferred: max_deferred.max(1),
+            global: limit.max_rate.map(|rate| TokenBucket::new(rate, now)),
+            host_rate: limit.max_host_rate,
+            hosts: HashMap::new(),
+            min_rate: limit.min_rate,
+            started: now,
+            started_sockets: 0,
+            retry: VecDeque::new(),
+            backoff: MIN_BACKOFF,
+            paused_until: None,
+        }
+    }
+
+    /// Returns the next socket to scan, if the limits allow starting one
+    /// now. `has_room` tells whether fewer than `batch_size` sockets are in
+    /// flight.
+    pub fn next(&mut self, has_room: bool, now: Instant) -> Option<SocketAddr> {
+        if self.paused_until.is_some_and(|until| now < until) {
+            return None;
+        }
+        if !has_room && !self.below_min_rate(now) {
+            return None;
+        }
+        if let Some(global) = &mut self.global {
+            if !global.ready(now) {
+                return None;
+            }
+        }
+
+        let socket = match self.retry.pop_front() {
+            Some(socket) => socket,
+            None => self.next_ready_socket(now)?,
+        };
+        if let Some(global) = &mut self.global {
+            global.take();
+        }
+        if let Some(bucket) = self.hosts.get_mut(&socket.ip()) {
+            bucket.take();
+        }
+        self.started_sockets += 1;
+        Some(socket)
+    }
+
+    /// Hands back a socket that failed because no file descriptor was left,
+    /// and pauses before starting any other socket.
+    pub fn retry(&mut self, socket: SocketAddr, now: Instant) {
+        self.retry.push_back(socket);
+        self.paused_until = Some(now + self.backoff);
+        self.backoff = (self.backoff * 2).min(MAX_BACKOFF);
+    }
+
+    /// Resets the pause once a socket went through again.
+    pub fn recovered(&mut self) {
+        self.backoff = MIN_BACKOFF;
+    }
+
+    /// Returns when `next` may return a socket again without any other
+    /// socket finishing first, or `None` when only a finishing socket can
+    /// unblock it.
+    pub fn wake_at(&self, now: Instant) -> Option<Instant> {
+        if self.exhausted && self.deferred.is_empty() && self.retry.is_empty() {
+            return None;
+        }
+
+        let global = self.global.as_ref().map(TokenBucket::ready_at);
+        let min_rate = self.min_rate.map(|rate| {
+            self.started + Duration::from_secs_f64(self.started_sockets as f64 / f64::from(rate))
+        });
+        let hosts = self
+            .deferred
+            .iter()
+            .filter_map(|socket| self.hosts.get(&socket.ip()))
+            .map(TokenBucket::ready_at)
+            .min();
+
+        [global, min_rate, hosts, self.paused_until]
+            .iter()
+            .flatten()
+            .copied()
+            .filter(|at| *at > now)
+            .min()
+    }
+
+    fn below_min_rate(&self, now: Instant) -> bool {
+        self.min_rate.is_some_and(|rate| {
+            let elapsed = now.saturating_duration_since(self.started).as_secs_f64();
+            (self.started_sockets as f64) < elapsed * f64::from(rate)
+        })
+    }
+
+    /// Takes a socket whose host has a token left, first from the deferred
+    /// sockets and then from the iterator, putting aside the sockets of
+    /// hosts that are out of tokens.
+    fn next_ready_socket(&mut self, now: Instant) -> Option<SocketAddr> {
+        let Some(host_rate) = self.host_rate else {
+            return self.next_from_iterator();
+        };
+
+        self.prune_hosts(now);
+        let hosts = &mut self.hosts;
+        if let Some(index) = self
+            .deferred
+            .iter()
+            .position(|socket| host_ready(hosts, socket.ip(), host_rate, now))
+        {
+            return self.deferred.remove(index);
+        }
+        while self.deferred.len() < self.max_deferred {
+            let socket = self.next_from_iterator()?;
+            if host_ready(&mut self.hosts, socket.ip(), host_rate, now) {
+                return Some(socket);
+            }
+            self.deferred.push_back(socket);
+        }
+        None
+    }
+
+    fn next_from_iterator(&mut self) -> Option<SocketAddr> {
+        let socket = self.sockets.next();
+        self.exhausted = socket.is_none();
+        socket
+    }
+
+    /// A full bucket behaves like a new one, so they are dropped once
+    /// there are many, to keep the memory bounded on large host ranges.
+    fn prune_hosts(&mut self, now: Instant) {
+        if self.hosts.len() > 2 * self.max_deferred {
+            self.hosts.retain(|_, bucket| !bucket.is_full(now));
+        }
+    }
+}
+
+/// Whether `ip` has a token left, giving it a full bucket on first sight.
+fn host_ready(
+    hosts: &mut HashMap<IpAddr, TokenBucket>,
+    ip: IpAddr,
+    rate: u32,
+    now: Instant,
+) -> bool {
+    hosts
+        .entry(ip)
+        .or_insert_with(|| TokenBucket::new(rate, now))
+        .ready(now)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RateLimit, Throttle};
+    use std::net::SocketAddr;
+    use std::time::{Duration, Instant};
+
+    fn sockets(addrs: &[&str]) -> std::vec::IntoIter<SocketAddr> {
+        addrs
+            .iter()
+            .map(|addr| addr.parse().unwrap())
+            .collect::<Vec<SocketAddr>>()
+            .into_iter()
+    }
+
+    #[test]
+    fn unlimited_throttle_only_waits_for_room() {
+        let now = Instant::now();
+        let mut throttle = Throttle::new(
+            sockets(&["10.0.0.1:1", "10.0.0.1:2"]),
+            RateLimit::default(),
+            10,
+            now,
+        );
+
+        assert_eq!(throttle.next(false, now), None);
+        assert_eq!(throttle.wake_at(now), None);
+        assert_eq!(
+            throttle.next(true, now),
+            Some("10.0.0.1:1".parse().unwrap())
+        );
+        assert_eq!(
+            throttle.next(true, now),
+            Some("10.0.0.1:2".parse().unwrap())
+        );
+        assert_eq!(throttle.next(true, now), None);
+    }
+
+    #[test]
+    fn max_rate_spaces_out_connections() {
+        let now = Instant::now();
+        let limit = RateLimit {
+            max_rate: Some(10),
+            ..RateLimit::default()
+        };
+        let mut throttle = Throttle::new(sockets(&["10.0.0.1:1", "10.0.0.1:2"]), limit, 10, now);
+
+        assert!(throttle.next(true, now).is_some());
+        assert_eq!(throttle.next(true, now), None);
+        assert_eq!(
+            throttle.wake_at(now),
+            Some(now + Duration::from_millis(100))
+        );
+
+        let later = now + Duration::from_millis(100);
+        assert_eq!(
+            throttle.next(true, later),
+            Some("10.0.0.1:2".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn max_host_rate_lets_other_hosts_go_first() {
+        let now = Instant::now();
+        let limit = RateLimit {
+            max_host_rate: Some(1),
+            ..RateLimit::default()
+        };
+        let mut throttle = Throttle::new(
+            sockets(&["10.0.0.1:1", "10.0.0.1:2", "10.0.0.2:1"]),
+            limit,
+            10,
+            now,
+        );
+
+        assert_eq!(
+            throttle.next(true, now),
+            Some("10.0.0.1:1".parse().unwrap())
+        );
+        assert_eq!(
+            throttle.next(true, now),
+            Some("10.0.0.2:1".parse().unwrap())
+        );
+        assert_eq!(throttle.next(true, now), None);
+        assert_eq!(throttle.wake_at(now), Some(now + Duration::from_secs(1)));
+
+        let later = now + Duration::from_secs(1);
+        assert_eq!(
+            throttle.next(true, later),
+            Some("10.0.0.1:2".parse().unwrap())
+        );
+        assert_eq!(throttle.wake_at(later), None);
+    }
+
+    #[test]
+    fn retried_sockets_go_first_after_a_pause() {
+        let now = Instant::now();
+        let mut throttle = Throttle::new(
+            sockets(&["10.0.0.1:1", "10.0.0.1:2"]),
+            RateLimit::default(),
+            10,
+            now,
+        );
+
+        let first = throttle.next(true, now).unwrap();
+        throttle.retry(first, now);
+        assert_eq!(throttle.next(true, now), None);
+        assert_eq!(throttle.wake_at(now), Some(now + Duration::from_millis(10)));
+
+        // The pause doubles while sockets keep failing.
+        let later = now + Duration::from_millis(10);
+        assert_eq!(throttle.next(true, later), Some(first));
+        throttle.retry(first, later);
+        assert_eq!(
+            throttle.wake_at(later),
+            Some(later + Duration::from_millis(20))
+        );
+
+        let later = later + Duration::from_millis(20);
+        assert_eq!(throttle.next(true, later), Some(first));
+        throttle.recovered();
+        assert_eq!(
+            throttle.next(true, later),
+            Some("10.0.0.1:2".parse().unwrap())
+        );
+        throttle.retry(first, later);
+        assert_eq!(
+            throttle.wake_at(later),
+            Some(later + Duration::from_millis(10))
+        );
+    }
+
+    #[test]
+    fn min_rate_goes_past_the_batch_size() {
+        let now = Instant::now();
+        let limit = RateLimit {
+            min_rate: Some(10),
+            ..RateLimit::default()
+        };
+        let mut throttle = Throttle::new(sockets(&["10.0.0.1:1", "10.0.0.1:2"]), limit, 10, now);
+
+        assert!(throttle.next(true, now).is_some());
+        assert_eq!(throttle.next(false, now), None);
+        assert_eq!(
+            throttle.wake_at(now),
+            Some(now + Duration::from_millis(100))
+        );
+
+        let later = now + Duration::from_millis(150);
+        assert_eq!(
+            throttle.next(false, later),
+            Some("10.0.0.1:2".parse().unwrap())
+        );
+    }
+}
@@ -0,0 +1,182 @@
+//! Adaptive per host timeouts, derived from measured round-trip times.
+//!
+//! Every connection that gets an answer, accepted or refused, is a sample
+//! of the round-trip time to its host. The samples are smoothed the way TCP
+//! does it (RFC 6298, also what nmap's `srtt`/`rttvar` are), and the timeout
+//! of the next connections to that host becomes `srtt + 4 * rttvar`, bounded
+//! by the user's minimum and maximum. Hosts without samples yet use the
+//! initial timeout. A host's estimate is dropped once all of its sockets are
+//! done, so the table only holds the hosts still being scanned.
+use super::progress::Watchlist;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// The smoothed round-trip time of a single host, in seconds.
+#[derive(Debug, Clone, Copy)]
+struct RttEstimate {
+    srtt: f64,
+    rttvar: f64,
+}
+
+impl RttEstimate {
+    fn new(sample: f64) -> Self {
+        Self {
+            srtt: sample,
+            rttvar: sample / 2.0,
+        }
+    }
+
+    fn update(&mut self, sample: f64) {
+        let delta = sample - self.srtt;
+        self.rttvar += (delta.abs() - self.rttvar) / 4.0;
+        self.srtt += delta / 8.0;
+    }
+
+    fn timeout(&self) -> f64 {
+        self.srtt + 4.0 * self.rttvar
+    }
+}
+
+/// The round-trip time estimates of the hosts that answered, until their
+/// last socket is done.
+#[derive(Debug)]
+pub(super) struct RttTable {
+    min: Duration,
+    max: Duration,
+    initial: Duration,
+    hosts: Mutex<HashMap<IpAddr, RttEstimate>>,
+    expiring: Mutex<Watchlist>,
+}
+
+impl RttTable {
+    /// `initial` is used until a host answers, and is bounded too.
+    pub fn new(min: Duration, max: Duration, initial: Duration) -> Self {
+        let max = max.max(min);
+        Self {
+            min,
+            max,
+            initial: initial.clamp(min, max),
+            hosts: Mutex::new(HashMap::new()),
+            expiring: Mutex::new(Watchlist::default()),
+        }
+    }
+
+    /// The timeout to use for the next connection to `ip`.
+    pub fn timeout(&self, ip: IpAddr) -> Duration {
+        let hosts = self.hosts.lock().unwrap();
+        hosts.get(&ip).map_or(self.initial, |estimate| {
+            Duration::from_secs_f64(estimate.timeout()).clamp(self.min, self.max)
+        })
+    }
+
+    /// Adds the round-trip time of a connection to `ip` that got an answer.
+    /// Returns whether it is the first one of `ip`, which should then be
+    /// handed to `forget_after`.
+    pub fn record(&self, ip: IpAddr, rtt: Duration) -> bool {
+        let sample = rtt.as_secs_f64();
+        let mut hosts = self.hosts.lock().unwrap();
+        match hosts.get_mut(&ip) {
+            Some(estimate) => {
+                estimate.update(sample);
+                false
+            }
+            None => {
+                hosts.insert(ip, RttEstimate::new(sample));
+                true
+            }
+        }
+    }
+
+    /// Drops the estimate of `ip` once the socket at position `last`, its
+    /// last one, is done.
+    pub fn forget_after(&self, last: u128, ip: IpAddr) {
+        self.expiring.lock().unwrap().watch(last, ip);
+    }
+
+    /// Drops the estimates of the hosts whose last socket is before
+    /// `resume_at`, the position every socket before is done.
+    pub fn forget_done(&self, resume_at: u128) {
+        let done = self.expiring.lock().unwrap().done(resume_at);
+        if done.is_empty() {
+            return;
+        }
+        let mut hosts = self.hosts.lock().unwrap();
+        for ip in done {
+            hosts.remove(&ip);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RttTable;
+    use std::net::IpAddr;
+    use std::time::Duration;
+
+    fn ms(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    #[test]
+    fn unseen_hosts_use_the_initial_timeout() {
+        let table = RttTable::new(ms(100), ms(5_000), ms(1_500));
+        let ip: IpAddr = "10.0.0.1".parse().unwrap();
+        assert_eq!(table.timeout(ip), ms(1_500));
+
+        let table = RttTable::new(ms(100), ms(1_000), ms(1_500));
+        assert_eq!(table.timeout(ip), ms(1_000));
+    }
+
+    #[test]
+    fn timeout_follows_the_measured_rtt() {
+        let table = RttTable::new(ms(10), ms(5_000), ms(1_500));
+        let lan: IpAddr = "10.0.0.1".parse().unwrap();
+        let far: IpAddr = "10.0.0.2".parse().unwrap();
+
+        // srtt 20ms and rttvar 10ms give 60ms.
+        table.record(lan, ms(20));
+        assert_eq!(table.timeout(lan), ms(60));
+
+        for _ in 0..20 {
+            table.record(far, ms(800));
+        }
+        assert!(table.timeout(far) >= ms(800));
+        assert!(table.timeout(far) < ms(1_500));
+        assert_eq!(table.timeout(lan), ms(60));
+    }
+
+    #[test]
+    fn timeout_stays_within_bounds() {
+        let table = RttTable::new(ms(100), ms(500), ms(1_500));
+        let fast: IpAddr = "10.0.0.1".parse().unwrap();
+        let slow: IpAddr = "10.0.0.2".parse().unwrap();
+
+        table.record(fast, Duration::from_micros(200));
+        table.record(slow, ms(2_000));
+        assert_eq!(table.timeout(fast), ms(100));
+        assert_eq!(table.timeout(slow), ms(500));
+    }
+
+    #[test]
+    fn estimates_are_dropped_once_their_host_is_done() {
+        let table = RttTable::new(ms(10), ms(5_000), ms(1_500));
+        let first: IpAddr = "10.0.0.1".parse().unwrap();
+        let second: IpAddr = "10.0.0.2".parse().unwrap();
+
+        assert!(table.record(first, ms(20)));
+        assert!(!table.record(first, ms(20)));
+        table.forget_after(3, first);
+        assert!(table.record(second, ms(20)));
+        table.forget_after(7, second);
+
+        table.forget_done(4);
+        assert_eq!(table.timeout(first), ms(1_500));
+        assert_eq!(table.timeout(second), ms(60));
+        assert_eq!(table.hosts.lock().unwrap().len(), 1);
+
+        table.forget_done(8);
+        assert!(table.hosts.lock().unwrap().is_empty());
+    }
+}
@@ -1,32 +1,243 @@
-use itertools::{iproduct, Product};
-use std::net::{IpAddr, SocketAddr};
+use super::permutation::Permutation;
+use super::progress::Progress;
+use crate::address::Targets;
+use crate::input::Shard;
+use crate::port_strategy::PortStrategy;
+use std::collections::HashSet;
+use std::net::SocketAddr;
+use std::sync::Mutex;
 
 pub struct SocketIterator<'s> {
-    // product_it is a cartesian product iterator over
-    // the slices of ports and IP addresses.
+    // position walks the cartesian product of the ports and IP addresses,
+    // which are only computed from it when needed, so the iterator takes
+    // the same memory for a /8 as for a single host and can be moved
+    // around freely, e.g. into the state of a Stream.
     //
     // The IP/port order is intentionally reversed here since we want
-    // the itertools::iproduct! macro below to generate the pairs with
-    // all the IPs for one port before moving on to the next one
-    // ("hold the port, go through all the IPs, then advance the port...").
+    // the index to generate the pairs with all the IPs for one port
+    // before moving on to the next one ("hold the port, go through all
+    // the IPs, then advance the port...").
     // See also the comments in the iterator implementation for an example.
-    product_it:
-        Product<Box<std::slice::Iter<'s, u16>>, Box<std::slice::Iter<'s, std::net::IpAddr>>>,
+    //
+    // With a shard, the positions only go through the combinations of
+    // the shard, see ShardLayout, the others are never visited.
+    // With an order, the position is first mapped to a pseudo-random
+    // index of those, which shuffles the hosts and ports together.
+    // A resumed scan starts at a later position, and skips the positions
+    // that were already done after it.
+    //
+    // With live hosts, only their combinations are walked: their positions
+    // are mapped back from the ports and hosts, through the shard and the
+    // order, and gone through in order. The hosts that are down cost
+    // nothing, yet every socket keeps its position among all the targets.
+    targets: &'s Targets,
+    ports: &'s PortStrategy,
+    layout: ShardLayout,
+    seed: Option<u64>,
+    order: Option<Permutation>,
+    live: Option<&'s Targets>,
+    live_positions: Option<Vec<u128>>,
+    progress: Option<&'s Mutex<Progress>>,
+    skip: HashSet<u128>,
+    len: u128,
+    position: u128,
 }
 
-/// An iterator that receives a slice of IPs and ports and returns a Socket
+/// An iterator that receives the targets and the ports and returns a Socket
 /// for each IP and port pair until all of these combinations are exhausted.
 /// The goal of this iterator is to go over every IP and port combination
 /// without generating a big memory footprint. The alternative would be
 /// generating a vector containing all these combinations.
 impl<'s> SocketIterator<'s> {
-    pub fn new(ips: &'s [IpAddr], ports: &'s [u16]) -> Self {
-        let ports_it = Box::new(ports.iter());
-        let ips_it = Box::new(ips.iter());
+    pub fn new(targets: &'s Targets, ports: &'s PortStrategy) -> Self {
+        let layout = ShardLayout::new(targets.len(), ports.len(), None);
         Self {
-            product_it: iproduct!(ports_it, ips_it),
+            targets,
+            ports,
+            layout,
+            seed: None,
+            order: None,
+            live: None,
+            live_positions: None,
+            progress: None,
+            skip: HashSet::new(),
+            len: layout.len(),
+            position: 0,
         }
     }
+
+    /// Walks the combinations in the pseudo-random order given by `seed`
+    /// instead of port by port.
+    pub fn shuffled(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self.order = Some(Permutation::new(self.len, seed));
+        self
+    }
+
+    /// Only walks the combinations that are part of `shard`.
+    pub fn sharded(mut self, shard: Shard) -> Self {
+        self.layout = ShardLayout::new(self.targets.len(), self.ports.len(), Some(shard));
+        self.len = self.layout.len();
+        self.order = self.seed.map(|seed| Permutation::new(self.len, seed));
+        self
+    }
+
+    /// Only returns the sockets of the hosts in `live`.
+    pub fn live(mut self, live: &'s Targets) -> Self {
+        self.live = Some(live);
+        self
+    }
+
+    /// Starts at `position` instead of the beginning, and skips the `done`
+    /// positions.
+    pub fn resumed(mut self, position: u128, done: &[u128]) -> Self {
+        self.position = position;
+        self.skip = done.iter().copied().collect();
+        self
+    }
+
+    /// Records the position of every socket it returns in `progress`.
+    pub fn tracked(mut self, progress: &'s Mutex<Progress>) -> Self {
+        self.progress = Some(progress);
+        self
+    }
+
+    /// The next position to walk, the next one of a live host with `live`.
+    fn next_position(&mut self) -> Option<u128> {
+        let position = match self.live {
+            None => self.position,
+            Some(live) => {
+                if self.live_positions.is_none() {
+                    self.live_positions = Some(self.positions_of(live));
+                }
+                let positions = self.live_positions.as_deref().unwrap_or_default();
+                let next = positions.partition_point(|&p| p < self.position);
+                *positions.get(next)?
+            }
+        };
+        if position >= self.len {
+            return None;
+        }
+        self.position = position + 1;
+        Some(position)
+    }
+
+    /// The positions of the combinations of the hosts in `live`, sorted.
+    /// Only the ports of the shard are taken for each of them.
+    fn positions_of(&self, live: &Targets) -> Vec<u128> {
+        let ports = self.ports.len() as u128;
+        let mut positions: Vec<u128> = live
+            .iter()
+            .filter_map(|ip| self.targets.position(ip))
+            .flat_map(|host| (0..ports).filter_map(move |port| self.layout.position(port, host)))
+            .map(|index| {
+                self.order
+                    .as_ref()
+                    .map_or(index, |order| order.position(index))
+            })
+            .collect();
+        positions.sort_unstable();
+        positions
+    }
+}
+
+/// Where the combinations of the `port`-th port and the `host`-th IP that
+/// are part of a shard are in its positions.
+///
+/// The shards cut the product diagonally, the combinations whose port and
+/// host add up to the shard's index modulo the shard count, so every shard
+/// gets about the same share of the ports of every host, whatever the
+/// number of hosts and ports. Every run of `count` ports then holds each
+/// host exactly once, and the positions go through the runs a port at a
+/// time, the hosts of that port in order, as in the whole product. Both
+/// ways are computed from a few numbers, in `O(log count)`.
+///
+/// Without a shard, it is the single shard of the whole product.
+#[derive(Debug, Clone, Copy)]
+pub(super) struct ShardLayout {
+    hosts: u128,
+    ports: u128,
+    count: u128,
+    index: u128,
+}
+
+impl ShardLayout {
+    pub fn new(hosts: u128, ports: usize, shard: Option<Shard>) -> Self {
+        let shard = shard.unwrap_or(Shard { index: 1, count: 1 });
+        Self {
+            hosts,
+            ports: ports as u128,
+            count: u128::from(shard.count),
+            index: u128::from(shard.index - 1),
+        }
+    }
+
+    /// The number of combinations in the shard.
+    pub fn len(&self) -> u128 {
+        (self.ports / self.count)
+            .saturating_mul(self.hosts)
+            .saturating_add(self.before(self.ports % self.count))
+    }
+
+    /// The port and host indexes of the combination at `position`, which
+    /// must be below `len`.
+    pub fn get(&self, position: u128) -> (u128, u128) {
+        let (run, offset) = (position / self.hosts, position % self.hosts);
+        // The last port of the run whose combinations start at or before
+        // the offset, the ports that have no host in the shard start where
+        // the next one does.
+        let (mut low, mut high) = (0, self.count - 1);
+        while low < high {
+            let middle = low + (high - low).div_ceil(2);
+            if self.before(middle) <= offset {
+                low = middle;
+            } else {
+                high = middle - 1;
+            }
+        }
+        let residue = (self.index + self.count - low) % self.count;
+        let host = residue + (offset - self.before(low)) * self.count;
+        (run * self.count + low, host)
+    }
+
+    /// The position of the combination of the `port`-th port and the
+    /// `host`-th IP, or None when it is not part of the shard.
+    pub fn position(&self, port: u128, host: u128) -> Option<u128> {
+        let first = port % self.count;
+        if (first + host % self.count) % self.count != self.index {
+            return None;
+        }
+        Some(port / self.count * self.hosts + self.before(first) + host / self.count)
+    }
+
+    /// The number of combinations of the first `ports` ports of a run, at
+    /// most `count`. They go with the hosts whose index is one of the
+    /// `ports` residues up to `index` modulo `count`, and the residues
+    /// below `hosts % count` have one more host than the others.
+    fn before(&self, ports: u128) -> u128 {
+        let extra = self.hosts % self.count;
+        let below = |residue: u128| residue.min(extra);
+        let up_to_index = below(self.index + 1);
+        let longer = if ports <= self.index + 1 {
+            up_to_index - below(self.index + 1 - ports)
+        } else {
+            up_to_index + extra - below(self.count + self.index + 1 - ports)
+        };
+        ports * (self.hosts / self.count) + longer
+    }
+}
+
+/// The number of ports of the `host`-th IP that are part of `shard`, out of
+/// `ports` ports.
+pub(super) fn ports_in_shard(shard: Shard, ports: usize, host: u128) -> usize {
+    let count = u128::from(shard.count);
+    let first = (u128::from(shard.index - 1) + count - host % count) % count;
+    let ports = ports as u128;
+    if first >= ports {
+        0
+    } else {
+        ((ports - 1 - first) / count + 1) as usize
+    }
 }
 
 #[allow(clippy::doc_link_with_quotes)]
@@ -37,22 +248,42 @@ impl<'s> Iterator for SocketIterator<'s> {
     /// IPs and ports or None when these combinations are exhausted. Every
     /// IP will have the same port until a port is incremented.
     ///
-    /// let it = SocketIterator::new(&["127.0.0.1", "192.168.0.1"], &[80, 443]);
+    /// let it = SocketIterator::new(&["127.0.0.1", "192.168.0.1"], vec![80, 443]);
     /// it.next(); // 127.0.0.1:80
     /// it.next(); // 192.168.0.1:80
     /// it.next(); // 127.0.0.1:443
     /// it.next(); // 192.168.0.1:443
     /// it.next(); // None
     fn next(&mut self) -> Option<Self::Item> {
-        self.product_it
-            .next()
-            .map(|(port, ip)| SocketAddr::new(*ip, *port))
+        while let Some(position) = self.next_position() {
+            if self.skip.remove(&position) {
+                continue;
+            }
+            let index = self
+                .order
+                .as_ref()
+                .map_or(position, |order| order.get(position));
+            let (port, host) = self.layout.get(index);
+            let port = self.ports.get(port as usize)?;
+            let ip = self.targets.get(host)?;
+
+            let socket = SocketAddr::new(ip, port);
+            if let Some(progress) = self.progress {
+                progress.lock().unwrap().started(position, socket);
+            }
+            return Some(socket);
+        }
+
+        None
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::SocketIterator;
+    use super::{ports_in_shard, ShardLayout, SocketIterator};
+    use crate::address::Targets;
+    use crate::input::{ScanOrder, Shard};
+    use crate::port_strategy::PortStrategy;
     use std::net::{IpAddr, SocketAddr};
 
     #[test]
@@ -61,8 +292,10 @@ mod tests {
             "127.0.0.1".parse::<IpAddr>().unwrap(),
             "192.168.0.1".parse::<IpAddr>().unwrap(),
         ];
+        let targets = Targets::from(&addrs);
         let ports: Vec<u16> = vec![22, 80, 443];
-        let mut it = SocketIterator::new(&addrs, &ports);
+        let strategy = PortStrategy::pick(&None, Some(ports.clone()), ScanOrder::Serial);
+        let mut it = SocketIterator::new(&targets, &strategy);
 
         assert_eq!(Some(SocketAddr::new(addrs[0], ports[0])), it.next());
         assert_eq!(Some(SocketAddr::new(addrs[1], ports[0])), it.next());
@@ -72,4 +305,163 @@ mod tests {
         assert_eq!(Some(SocketAddr::new(addrs[1], ports[2])), it.next());
         assert_eq!(None, it.next());
     }
+
+    #[test]
+    fn shuffled_order_interleaves_hosts_and_ports() {
+        let targets: Targets = (1..=20)
+            .map(|i| format!("10.0.0.{i}").parse::<IpAddr>().unwrap())
+            .collect();
+        let strategy = PortStrategy::pick(&None, Some((1..=50).collect()), ScanOrder::Serial);
+
+        let serial: Vec<SocketAddr> = SocketIterator::new(&targets, &strategy).collect();
+        let shuffled: Vec<SocketAddr> = SocketIterator::new(&targets, &strategy)
+            .shuffled(7)
+            .collect();
+
+        assert_eq!(serial.len(), 20 * 50);
+        assert_ne!(serial, shuffled);
+        let mut sorted = shuffled.clone();
+        sorted.sort_unstable();
+        let mut expected = serial.clone();
+        expected.sort_unstable();
+        assert_eq!(sorted, expected);
+
+        // Neither the hosts nor the ports come in runs anymore.
+        let same_port = shuffled.windows(2).filter(|w| w[0].port() == w[1].port());
+        let same_host = shuffled.windows(2).filter(|w| w[0].ip() == w[1].ip());
+        assert!(same_port.count() < 100);
+        assert!(same_host.count() < 200);
+    }
+
+    #[test]
+    fn resumed_iterator_skips_what_was_done() {
+        let targets: Targets = (1..=3)
+            .map(|i| format!("10.0.0.{i}").parse::<IpAddr>().unwrap())
+            .collect();
+        let strategy = PortStrategy::pick(&None, Some((1..=4).collect()), ScanOrder::Serial);
+        let all: Vec<SocketAddr> = SocketIterator::new(&targets, &strategy)
+            .shuffled(1)
+            .collect();
+
+        let resumed: Vec<SocketAddr> = SocketIterator::new(&targets, &strategy)
+            .shuffled(1)
+            .resumed(5, &[6, 9])
+            .collect();
+        let expected: Vec<SocketAddr> = [5, 7, 8, 10, 11].iter().map(|&i| all[i]).collect();
+        assert_eq!(resumed, expected);
+    }
+
+    #[test]
+    fn hosts_that_are_down_keep_their_positions() {
+        let targets: Targets = (1..=4)
+            .map(|i| format!("10.0.0.{i}").parse::<IpAddr>().unwrap())
+            .collect();
+        let live: Targets = vec!["10.0.0.2".parse::<IpAddr>().unwrap()]
+            .into_iter()
+            .collect();
+        let strategy = PortStrategy::pick(&None, Some(vec![22, 80]), ScanOrder::Serial);
+        let all: Vec<SocketAddr> = SocketIterator::new(&targets, &strategy).collect();
+
+        let sockets: Vec<SocketAddr> = SocketIterator::new(&targets, &strategy)
+            .live(&live)
+            .resumed(2, &[])
+            .collect();
+        assert_eq!(sockets, vec![all[5]]);
+    }
+
+    #[test]
+    fn only_the_positions_of_live_hosts_are_walked() {
+        let targets: Targets = (1..=10)
+            .map(|i| format!("10.0.0.{i}").parse::<IpAddr>().unwrap())
+            .collect();
+        let live: Targets = [2, 3, 7]
+            .iter()
+            .map(|i| format!("10.0.0.{i}").parse::<IpAddr>().unwrap())
+            .collect();
+        let strategy = PortStrategy::pick(&None, Some((1..=6).collect()), ScanOrder::Serial);
+        let shard = Shard { index: 2, count: 3 };
+
+        for shuffle in [None, Some(5)] {
+            let walk = || {
+                let mut it = SocketIterator::new(&targets, &strategy);
+                if let Some(seed) = shuffle {
+                    it = it.shuffled(seed);
+                }
+                it.sharded(shard)
+            };
+            let all: Vec<SocketAddr> = walk().collect();
+            let expected: Vec<SocketAddr> = all
+                .iter()
+                .enumerate()
+                .filter(|&(position, socket)| {
+                    position >= 2 && position != 4 && live.contains(socket.ip())
+                })
+                .map(|(_, socket)| *socket)
+                .collect();
+
+            let sockets: Vec<SocketAddr> = walk().live(&live).resumed(2, &[4]).collect();
+            assert!(!sockets.is_empty());
+            assert_eq!(sockets, expected);
+        }
+    }
+
+    #[test]
+    fn shards_cover_every_combination_once() {
+        let targets: Targets = (1..=7)
+            .map(|i| format!("10.0.0.{i}").parse::<IpAddr>().unwrap())
+            .collect();
+        let strategy = PortStrategy::pick(&None, Some((1..=10).collect()), ScanOrder::Serial);
+        let all: Vec<SocketAddr> = SocketIterator::new(&targets, &strategy).collect();
+
+        for shuffle in [None, Some(3)] {
+            let mut merged: Vec<SocketAddr> = Vec::new();
+            for index in 1..=3 {
+                let shard = Shard { index, count: 3 };
+                let mut it = SocketIterator::new(&targets, &strategy).sharded(shard);
+                if let Some(seed) = shuffle {
+                    it = it.shuffled(seed);
+                }
+                let sockets: Vec<SocketAddr> = it.collect();
+
+                for (host, ip) in targets.iter().enumerate() {
+                    let on_host = sockets.iter().filter(|s| s.ip() == ip).count();
+                    assert_eq!(on_host, ports_in_shard(shard, 10, host as u128));
+                }
+                merged.extend(sockets);
+            }
+
+            merged.sort_unstable();
+            let mut expected = all.clone();
+            expected.sort_unstable();
+            assert_eq!(merged, expected);
+        }
+    }
+
+    #[test]
+    fn shard_positions_only_go_through_its_combinations() {
+        for (hosts, ports) in [(1, 1), (7, 10), (10, 7), (2, 9), (9, 2), (5, 5)] {
+            for count in [1, 2, 3, 4, 11] {
+                for index in 1..=count {
+                    let shard = Shard { index, count };
+                    let layout = ShardLayout::new(hosts, ports, Some(shard));
+
+                    // The product a port at a time, as the whole scan goes.
+                    let expected: Vec<(u128, u128)> = (0..ports as u128)
+                        .flat_map(|port| (0..hosts).map(move |host| (port, host)))
+                        .filter(|(port, host)| {
+                            let count = u128::from(count);
+                            (port + host) % count == u128::from(index - 1)
+                        })
+                        .collect();
+                    let walked: Vec<(u128, u128)> =
+                        (0..layout.len()).map(|p| layout.get(p)).collect();
+                    assert_eq!(walked, expected, "{hosts} hosts, {ports} ports, {shard:?}");
+
+                    for (position, &(port, host)) in walked.iter().enumerate() {
+                        assert_eq!(layout.position(port, host), Some(position as u128));
+                    }
+                }
+            }
+        }
+    }
 }
@@ -0,0 +1,281 @@
+//! The local end of the connections of a scan, see `--source-ip`,
+//! `--interface` and `--source-port`.
+//!
+//! By default the OS picks the local address and port of every connection
+//! from its routing table, which on hosts with several interfaces is not
+//! always the one the scan is meant to leave from. A `Source` binds the
+//! sockets before connecting them instead.
+use async_io::Async;
+use async_std::io;
+use async_std::net::{TcpStream, UdpSocket};
+use socket2::{Domain, Protocol, SockAddr, Socket, Type};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+/// Where the connections of a scan come from. The default leaves it all to
+/// the OS.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Source {
+    ip: Option<IpAddr>,
+    interface: Option<String>,
+    port: Option<u16>,
+    /// The addresses of `interface`, the sockets are bound to one of them
+    /// when no `ip` is given.
+    interface_ips: Vec<IpAddr>,
+}
+
+impl Source {
+    /// Checks the source against the local interfaces: `ip` has to be one
+    /// of their addresses, `interface` one of their names, and with both,
+    /// `ip` has to be an address of `interface`.
+    pub fn local(
+        ip: Option<IpAddr>,
+        interface: Option<String>,
+        port: Option<u16>,
+    ) -> Result<Self, String> {
+        if ip.is_none() && interface.is_none() && port.is_none() {
+            return Ok(Self::default());
+        }
+        let interfaces = if_addrs::get_if_addrs()
+            .map_err(|e| format!("could not list the local interfaces: {e}"))?;
+
+        if let Some(ip) = ip {
+            if !interfaces.iter().any(|iface| iface.ip() == ip) {
+                return Err(format!("{ip} is not the address of a local interface"));
+            }
+        }
+
+        let mut interface_ips = Vec::new();
+        if let Some(name) = &interface {
+            interface_ips = interfaces
+                .iter()
+                .filter(|iface| iface.name == *name)
+                .map(if_addrs::Interface::ip)
+                .collect();
+            if interface_ips.is_empty() {
+                return Err(format!("{name} is not a local interface with an address"));
+            }
+            if let Some(ip) = ip {
+                if !interface_ips.contains(&ip) {
+                    return Err(format!("{ip} is not an address of {name}"));
+                }
+            }
+        }
+
+        let source = Self {
+            ip,
+            interface,
+            port,
+            interface_ips,
+        };
+        // Binding to a device needs privileges on some systems, better find
+        // out now than with every port failing.
+        if let Some(name) = &source.interface {
+            Socket::new(Domain::IPV4, Type::STREAM, None)
+                .and_then(|socket| source.bind_device(&socket))
+                .map_err(|e| format!("could not bind to {name}: {e}"))?;
+        }
+        Ok(source)
+    }
+
+    /// Whether the OS picks everything, as with no source at all.
+    pub fn is_default(&self) -> bool {
+        self.ip.is_none() && self.interface.is_none() && self.port.is_none()
+    }
+
+    /// Opens a TCP connection to `target` from this source.
+    pub async fn connect(&self, target: SocketAddr) -> io::Result<TcpStream> {
+        if self.is_default() {
+            return TcpStream::connect(target).await;
+        }
+
+        let socket = self.socket(target, Type::STREAM, Protocol::TCP, self.port)?;
+        match socket.connect(&SockAddr::from(target)) {
+            Ok(()) => {}
+            Err(e) if connect_in_progress(&e) => {}
+            Err(e) => return Err(e),
+        }
+        let stream = Async::new(std::net::TcpStream::from(socket))?;
+        stream.writable().await?;
+        if let Some(e) = stream.get_ref().take_error()? {
+            return Err(e);
+        }
+        Ok(TcpStream::from(stream.into_inner()?))
+    }
+
+    /// Opens a UDP socket to send datagrams to `target` from this source.
+    pub async fn udp_socket(&self, target: SocketAddr) -> io::Result<UdpSocket> {
+        if self.is_default() {
+            let local: SocketAddr = if target.is_ipv4() {
+                (Ipv4Addr::UNSPECIFIED, 0).into()
+            } else {
+                (Ipv6Addr::UNSPECIFIED, 0).into()
+            };
+            return UdpSocket::bind(local).await;
+        }
+
+        let socket = self.socket(target, Type::DGRAM, Protocol::UDP, self.port)?;
+        Ok(UdpSocket::from(std::net::UdpSocket::from(socket)))
+    }
+
+    /// Opens an unprivileged ICMP echo socket to ping `target` from this
+    /// source. The OS only hands them out where it is configured to, e.g.
+    /// within `net.ipv4.ping_group_range` on Linux.
+    pub fn ping_socket(&self, target: IpAddr) -> io::Result<UdpSocket> {
+        let protocol = if target.is_ipv4() {
+            Protocol::ICMPV4
+        } else {
+            Protocol::ICMPV6
+        };
+        // The port of a ping socket is its echo identifier, left to the OS.
+        let socket = self.socket(SocketAddr::new(target, 0), Type::DGRAM, protocol, None)?;
+        Ok(UdpSocket::from(std::net::UdpSocket::from(socket)))
+    }
+
+    /// The local port of the connections, when it is not left to the OS.
+    pub fn port(&self) -> Option<u16> {
+        self.port
+    }
+
+    /// The local address the packets to `target` leave from: the source
+    /// address, or else the one the OS routes them from.
+    pub async fn local_ip(&self, target: SocketAddr) -> io::Result<IpAddr> {
+        if let Some(ip) = self.ip {
+            return Ok(ip);
+        }
+        // Connecting a UDP socket sends nothing, but picks the route.
+        let socket = self.udp_socket(target).await?;
+        socket.connect(target).await?;
+        Ok(socket.local_addr()?.ip())
+    }
+
+    /// Opens a raw TCP socket of the family of `v6` on this source, which
+    /// sends and receives whole TCP segments. It needs privileges, e.g.
+    /// CAP_NET_RAW on Linux.
+    pub fn raw_tcp_socket(&self, v6: bool) -> io::Result<UdpSocket> {
+        let any = if v6 {
+            SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0))
+        } else {
+            SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0))
+        };
+        let socket = self.socket(any, Type::RAW, Protocol::TCP, None)?;
+        Ok(UdpSocket::from(std::net::UdpSocket::from(socket)))
+    }
+
+    /// A non blocking socket bound to this source and `port`, of the family
+    /// of `target`.
+    fn socket(
+        &self,
+        target: SocketAddr,
+        kind: Type,
+        protocol: Protocol,
+        port: Option<u16>,
+    ) -> io::Result<Socket> {
+        let socket = Socket::new(Domain::for_address(target), kind, Some(protocol))?;
+        socket.set_nonblocking(true)?;
+        if self.interface.is_some() {
+            self.bind_device(&socket)?;
+        }
+
+        let ip = match self.ip.or_else(|| self.interface_ip(target)) {
+            Some(ip) if ip.is_ipv4() != target.is_ipv4() => {
+                return Err(io::Error::new(
+                    io::ErrorKind::AddrNotAvailable,
+                    format!("{target} can't be reached from {ip}"),
+                ))
+            }
+            Some(ip) => ip,
+            None if target.is_ipv4() => Ipv4Addr::UNSPECIFIED.into(),
+            None => Ipv6Addr::UNSPECIFIED.into(),
+        };
+        if ip.is_unspecified() && port.is_none() {
+            return Ok(socket);
+        }
+        // Connections to different targets can share the source port.
+        if port.is_some() {
+            socket.set_reuse_address(true)?;
+        }
+        socket.bind(&SockAddr::from(SocketAddr::new(ip, port.unwrap_or(0))))?;
+        Ok(socket)
+    }
+
+    /// The address of the interface of the family of `target`. It is all
+    /// there is to binding to the interface where the OS can't bind a
+    /// socket to a device.
+    fn interface_ip(&self, target: SocketAddr) -> Option<IpAddr> {
+        self.interface_ips
+            .iter()
+            .find(|ip| ip.is_ipv4() == target.is_ipv4())
+            .copied()
+    }
+
+    /// Binds `socket` to the interface with SO_BINDTODEVICE.
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    fn bind_device(&self, socket: &Socket) -> io::Result<()> {
+        socket.bind_device(self.interface.as_deref().map(str::as_bytes))
+    }
+
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    fn bind_device(&self, _socket: &Socket) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Whether a non blocking connect returned because it is still going on.
+fn connect_in_progress(error: &io::Error) -> bool {
+    #[cfg(unix)]
+    if error.raw_os_error() == Some(libc::EINPROGRESS) {
+        return true;
+    }
+    error.kind() == io::ErrorKind::WouldBlock
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Source;
+    use crate::test_utils::closed_port;
+    use async_std::task::block_on;
+    use std::net::{IpAddr, SocketAddr, TcpListener};
+
+    fn localhost() -> Option<IpAddr> {
+        Some("127.0.0.1".parse().unwrap())
+    }
+
+    #[test]
+    fn source_must_be_local() {
+        assert!(Source::local(localhost(), None, None).is_ok());
+        assert!(Source::local(Some("192.0.2.1".parse().unwrap()), None, None).is_err());
+        assert!(Source::local(None, Some("no-such-interface0".to_owned()), None).is_err());
+        assert!(Source::local(None, None, Some(40_000))
+            .unwrap()
+            .port
+            .is_some());
+        assert!(Source::local(None, None, None).unwrap().is_default());
+    }
+
+    #[test]
+    fn connections_leave_from_the_source() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let target = listener.local_addr().unwrap();
+        let port = closed_port();
+
+        let source = Source::local(localhost(), None, Some(port)).unwrap();
+        let stream = block_on(source.connect(target)).unwrap();
+        assert_eq!(
+            stream.local_addr().unwrap(),
+            SocketAddr::new(localhost().unwrap(), port)
+        );
+        let (_, peer) = listener.accept().unwrap();
+        assert_eq!(peer.port(), port);
+
+        let v6_target: SocketAddr = "[::1]:80".parse().unwrap();
+        assert!(block_on(source.connect(v6_target)).is_err());
+    }
+
+    #[test]
+    fn refused_connections_keep_their_error() {
+        let target = SocketAddr::from(([127, 0, 0, 1], closed_port()));
+        let source = Source::local(localhost(), None, None).unwrap();
+        let error = block_on(source.connect(target)).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::ConnectionRefused);
+    }
+}
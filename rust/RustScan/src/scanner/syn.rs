@@ -0,0 +1,373 @@
+//! SYN scans through raw sockets, see [`SynScan`].
+//!
+//! A connect scan completes the handshake of every open port, which shows
+//! up in the logs of the services, and holds a file descriptor per socket
+//! in flight. A SYN scan only sends the first segment of the handshake and
+//! reads the answer off a raw socket: a SYN/ACK means the port is open, a
+//! RST that it is closed. The kernel knows nothing about the connection, so
+//! it answers the SYN/ACK with a RST itself and the handshake never
+//! completes.
+//!
+//! All the probes share a single source port, kept from other connections
+//! by a bound TCP socket, and a raw socket per address family. The initial
+//! sequence number of every probe is a keyed hash of its target, so the
+//! answers are told apart from the rest of the TCP traffic the raw sockets
+//! see by their acknowledgment number alone.
+use super::PortState;
+use super::Source;
+use crate::discovery::icmp::checksum;
+use async_std::io;
+use async_std::net::UdpSocket;
+use futures::channel::oneshot;
+use log::debug;
+use socket2::{Domain, SockAddr, Socket, Type};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::hash::{Hash, Hasher};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::Duration;
+
+const FLAG_SYN: u8 = 0x02;
+const FLAG_RST: u8 = 0x04;
+const FLAG_ACK: u8 = 0x10;
+
+/// The TCP header of a probe, with a maximum segment size option like the
+/// SYNs of most systems.
+const SEGMENT_LEN: usize = 24;
+const WINDOW: u16 = 1024;
+const MSS: u16 = 1460;
+
+/// Sends SYNs and waits for the answers, with raw sockets.
+#[derive(Debug, Clone)]
+pub struct SynScan {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    source: Source,
+    port: u16,
+    key: u64,
+    v4: Option<Arc<UdpSocket>>,
+    v6: Option<Arc<UdpSocket>>,
+    /// Keep the source port from other connections. They don't stop the
+    /// kernel from resetting the half-open ones, which nothing listens for.
+    _reserved: Vec<Socket>,
+    /// The probes waiting for an answer.
+    waiting: Mutex<HashMap<SocketAddr, oneshot::Sender<PortState>>>,
+    /// The local address of every target, as routed by the OS.
+    local_ips: Mutex<HashMap<IpAddr, IpAddr>>,
+}
+
+impl SynScan {
+    /// Opens the raw sockets on `source`, which fails without the
+    /// privileges for them.
+    pub fn new(source: &Source) -> io::Result<Self> {
+        if !cfg!(target_os = "linux") {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "SYN scans only work on Linux",
+            ));
+        }
+        // A source address only leaves the raw socket of its family, the
+        // scan needs at least one of them.
+        let v4 = source.raw_tcp_socket(false).map(Arc::new);
+        let v6 = source.raw_tcp_socket(true).map(Arc::new).ok();
+        let v4 = match (v4, &v6) {
+            (Ok(v4), _) => Some(v4),
+            (Err(_), Some(_)) => None,
+            (Err(e), None) => return Err(e),
+        };
+
+        let (port, reserved) = match source.port() {
+            Some(port) => (port, Vec::new()),
+            None => reserve_port()?,
+        };
+
+        let inner = Arc::new(Inner {
+            source: source.clone(),
+            port,
+            key: rand::random(),
+            v4,
+            v6,
+            _reserved: reserved,
+            waiting: Mutex::new(HashMap::new()),
+            local_ips: Mutex::new(HashMap::new()),
+        });
+        for socket in inner.v4.iter().chain(inner.v6.iter()) {
+            async_std::task::spawn(receive(Arc::clone(socket), Arc::downgrade(&inner)));
+        }
+        Ok(Self { inner })
+    }
+
+    /// Sends a SYN to `target` and waits up to `timeout` for the answer,
+    /// `Open` or `Closed`. Without one it fails with `TimedOut`.
+    pub async fn probe(&self, target: SocketAddr, timeout: Duration) -> io::Result<PortState> {
+        let inner = &self.inner;
+        let socket = if target.is_ipv4() {
+            &inner.v4
+        } else {
+            &inner.v6
+        };
+        let Some(socket) = socket else {
+            return Err(io::Error::new(
+                io::ErrorKind::AddrNotAvailable,
+                format!("no raw socket to reach {target}"),
+            ));
+        };
+        let local_ip = inner.local_ip(target).await?;
+        let segment = syn_segment(
+            SocketAddr::new(local_ip, inner.port),
+            target,
+            inner.cookie(target),
+        );
+
+        let (answer, answered) = oneshot::channel();
+        inner.waiting.lock().unwrap().insert(target, answer);
+        let state = async {
+            socket
+                .send_to(&segment, SocketAddr::new(target.ip(), 0))
+                .await?;
+            answered
+                .await
+                .map_err(|_| io::Error::from(io::ErrorKind::TimedOut))
+        };
+        let state = io::timeout(timeout, state).await;
+        inner.waiting.lock().unwrap().remove(&target);
+        state
+    }
+}
+
+impl Inner {
+    /// The initial sequence number of the probes of `target`.
+    fn cookie(&self, target: SocketAddr) -> u32 {
+        let mut hasher = DefaultHasher::new();
+        (self.key, target).hash(&mut hasher);
+        hasher.finish() as u32
+    }
+
+    async fn local_ip(&self, target: SocketAddr) -> io::Result<IpAddr> {
+        if let Some(ip) = self.local_ips.lock().unwrap().get(&target.ip()) {
+            return Ok(*ip);
+        }
+        let ip = self.source.local_ip(target).await?;
+        self.local_ips.lock().unwrap().insert(target.ip(), ip);
+        Ok(ip)
+    }
+
+    /// Hands the state in `packet` to the probe waiting for it, if any.
+    fn answer(&self, from: IpAddr, packet: &[u8]) {
+        let Some(reply) = Reply::parse(from, packet) else {
+            return;
+        };
+        if reply.port != self.port || reply.ack != self.cookie(reply.from).wrapping_add(1) {
+            return;
+        }
+        let state = if reply.flags & (FLAG_SYN | FLAG_ACK) == FLAG_SYN | FLAG_ACK {
+            PortState::Open
+        } else if reply.flags & FLAG_RST != 0 {
+            PortState::Closed
+        } else {
+            return;
+        };
+        if let Some(waiting) = self.waiting.lock().unwrap().remove(&reply.from) {
+            let _ = waiting.send(state);
+        }
+    }
+}
+
+/// Picks a free local port by binding TCP sockets to it, without listening.
+fn reserve_port() -> io::Result<(u16, Vec<Socket>)> {
+    let v4 = Socket::new(Domain::IPV4, Type::STREAM, None)?;
+    v4.bind(&SockAddr::from(SocketAddr::from((
+        Ipv4Addr::UNSPECIFIED,
+        0,
+    ))))?;
+    let port = v4.local_addr()?.as_socket().map_or(0, |local| local.port());
+    let mut reserved = vec![v4];
+    // Best effort, without IPv6 there is nothing to keep it from.
+    if let Ok(v6) = Socket::new(Domain::IPV6, Type::STREAM, None) {
+        let bound = v6.set_only_v6(true).and_then(|()| {
+            v6.bind(&SockAddr::from(SocketAddr::from((
+                Ipv6Addr::UNSPECIFIED,
+                port,
+            ))))
+        });
+        if bound.is_ok() {
+            reserved.push(v6);
+        }
+    }
+    Ok((port, reserved))
+}
+
+/// Reads the segments off `socket` until the scan is gone, or until the
+/// socket fails. The probes waiting then time out.
+async fn receive(socket: Arc<UdpSocket>, inner: Weak<Inner>) {
+    let mut buf = [0; 1500];
+    // Gone or not, the scan is only looked at between segments, so it is
+    // never waited on for longer than this.
+    let wait = Duration::from_secs(1);
+    while inner.strong_count() > 0 {
+        let (len, from) = match io::timeout(wait, socket.recv_from(&mut buf)).await {
+            Ok(received) => received,
+            Err(e) if e.kind() == io::ErrorKind::TimedOut => continue,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => {
+                debug!("The raw socket of the SYN scan failed, no answer is read anymore: {e}");
+                return;
+            }
+        };
+        let Some(inner) = inner.upgrade() else {
+            return;
+        };
+        inner.answer(from.ip(), &buf[..len]);
+    }
+}
+
+/// The parts of a TCP segment that tell what it answers.
+#[derive(Debug, PartialEq, Eq)]
+struct Reply {
+    from: SocketAddr,
+    /// The local port it was sent to.
+    port: u16,
+    ack: u32,
+    flags: u8,
+}
+
+impl Reply {
+    /// Parses a segment from `from`. The raw IPv4 sockets of Linux keep the
+    /// IP header in front of it, the IPv6 ones don't.
+    fn parse(from: IpAddr, packet: &[u8]) -> Option<Self> {
+        let segment = match from {
+            IpAddr::V4(_) => packet.get(usize::from(packet.first()? & 0x0f) * 4..)?,
+            IpAddr::V6(_) => packet,
+        };
+        if segment.len() < 20 {
+            return None;
+        }
+        let u16_at = |i: usize| u16::from_be_bytes([segment[i], segment[i + 1]]);
+        Some(Self {
+            from: SocketAddr::new(from, u16_at(0)),
+            port: u16_at(2),
+            ack: u32::from_be_bytes([segment[8], segment[9], segment[10], segment[11]]),
+            flags: segment[13],
+        })
+    }
+}
+
+/// A SYN from `local` to `target`, starting at sequence number `seq`.
+fn syn_segment(local: SocketAddr, target: SocketAddr, seq: u32) -> Vec<u8> {
+    let mut segment = Vec::with_capacity(SEGMENT_LEN);
+    segment.extend_from_slice(&local.port().to_be_bytes());
+    segment.extend_from_slice(&target.port().to_be_bytes());
+    segment.extend_from_slice(&seq.to_be_bytes());
+    segment.extend_from_slice(&0u32.to_be_bytes());
+    segment.push((SEGMENT_LEN as u8 / 4) << 4);
+    segment.push(FLAG_SYN);
+    segment.extend_from_slice(&WINDOW.to_be_bytes());
+    // The checksum, filled in below, and the urgent pointer.
+    segment.extend_from_slice(&[0, 0, 0, 0]);
+    segment.extend_from_slice(&[2, 4]);
+    segment.extend_from_slice(&MSS.to_be_bytes());
+
+    let checksum = checksum(&[pseudo_header(local.ip(), target.ip()), segment.clone()].concat());
+    segment[16..18].copy_from_slice(&checksum.to_be_bytes());
+    segment
+}
+
+/// The addresses the TCP checksum covers on top of the segment.
+fn pseudo_header(local: IpAddr, target: IpAddr) -> Vec<u8> {
+    let len = u32::try_from(SEGMENT_LEN).unwrap();
+    let mut header = Vec::new();
+    match (local, target) {
+        (IpAddr::V4(local), IpAddr::V4(target)) => {
+            header.extend_from_slice(&local.octets());
+            header.extend_from_slice(&target.octets());
+            header.extend_from_slice(&[0, 6]);
+            header.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        (local, target) => {
+            header.extend_from_slice(&to_ipv6(local).octets());
+            header.extend_from_slice(&to_ipv6(target).octets());
+            header.extend_from_slice(&len.to_be_bytes());
+            header.extend_from_slice(&[0, 0, 0, 6]);
+        }
+    }
+    header
+}
+
+fn to_ipv6(ip: IpAddr) -> Ipv6Addr {
+    match ip {
+        IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+        IpAddr::V6(ip) => ip,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{checksum, pseudo_header, syn_segment, Reply, SynScan, FLAG_SYN};
+    use crate::scanner::{PortState, Source};
+    use crate::test_utils::closed_port;
+    use async_std::task::block_on;
+    use std::io;
+    use std::net::{SocketAddr, TcpListener};
+    use std::time::Duration;
+
+    #[test]
+    fn syn_segments_check_out() {
+        let local: SocketAddr = "10.0.0.1:40000".parse().unwrap();
+        let target: SocketAddr = "10.0.0.2:443".parse().unwrap();
+        let segment = syn_segment(local, target, 0xdead_beef);
+
+        assert_eq!(segment.len(), 24);
+        assert_eq!(segment[13], FLAG_SYN);
+        assert_eq!(
+            checksum(&[pseudo_header(local.ip(), target.ip()), segment.clone()].concat()),
+            0
+        );
+
+        // Seen from the target, a RST for it acknowledges the sequence number.
+        let mut rst = segment;
+        rst.swap(0, 2);
+        rst.swap(1, 3);
+        rst[8..12].copy_from_slice(&0xdead_bef0_u32.to_be_bytes());
+        rst[13] = 0x14;
+        let mut packet = vec![0x45];
+        packet.extend_from_slice(&[0; 19]);
+        packet.extend_from_slice(&rst);
+        assert_eq!(
+            Reply::parse(target.ip(), &packet),
+            Some(Reply {
+                from: target,
+                port: 40000,
+                ack: 0xdead_bef0,
+                flags: 0x14,
+            })
+        );
+        assert_eq!(Reply::parse(target.ip(), &packet[..30]), None);
+    }
+
+    #[test]
+    #[ignore = "needs CAP_NET_RAW"]
+    fn syn_scan_tells_open_and_closed_ports_apart() {
+        let syn = SynScan::new(&Source::default()).unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let open = listener.local_addr().unwrap();
+        let closed = SocketAddr::from(([127, 0, 0, 1], closed_port()));
+        let timeout = Duration::from_secs(1);
+
+        assert_eq!(block_on(syn.probe(open, timeout)).unwrap(), PortState::Open);
+        assert_eq!(
+            block_on(syn.probe(closed, timeout)).unwrap(),
+            PortState::Closed
+        );
+        // The handshake never completed.
+        listener.set_nonblocking(true).unwrap();
+        assert_eq!(
+            listener.accept().unwrap_err().kind(),
+            io::ErrorKind::WouldBlock
+        );
+    }
+}
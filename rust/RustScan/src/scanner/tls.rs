@@ -0,0 +1,252 @@
+//! TLS detection on open TCP ports, see `--tls`.
+//!
+//! A port speaks TLS when a handshake on a fresh connection to it goes
+//! through. Any certificate is accepted, self-signed and expired ones
+//! included, since the point is to describe it, not to trust it. Only
+//! TLS 1.2 and 1.3 are spoken, the older versions go undetected.
+use async_std::io;
+use async_std::net::TcpStream;
+use futures_rustls::TlsConnector;
+use rustls::client::{ServerCertVerified, ServerCertVerifier};
+use rustls::{ClientConfig, ProtocolVersion, ServerName};
+use serde_derive::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use x509_parser::prelude::{FromDer, GeneralName, X509Certificate};
+use x509_parser::time::ASN1Time;
+
+/// What the TLS handshake on a port told.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TlsInfo {
+    /// The negotiated protocol version, e.g. "TLSv1.3".
+    pub version: String,
+    /// The certificate the server presented, `None` when it couldn't be
+    /// parsed.
+    pub certificate: Option<CertificateInfo>,
+}
+
+/// The parts of a certificate that identify it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CertificateInfo {
+    pub subject: String,
+    /// The DNS names and IP addresses of the subject alternative names.
+    pub sans: Vec<String>,
+    pub issuer: String,
+    /// The validity period, in RFC 3339 format.
+    pub not_before: String,
+    pub not_after: String,
+}
+
+impl fmt::Display for TlsInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.version)?;
+        if let Some(certificate) = &self.certificate {
+            write!(
+                f,
+                ", subject {}, valid until {}",
+                certificate.subject, certificate.not_after
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Runs the handshakes of the TLS probes.
+#[derive(Clone)]
+pub(super) struct TlsProbe {
+    connector: TlsConnector,
+}
+
+impl fmt::Debug for TlsProbe {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TlsProbe").finish_non_exhaustive()
+    }
+}
+
+impl TlsProbe {
+    pub fn new() -> Self {
+        let config = ClientConfig::builder()
+            .with_safe_defaults()
+            .with_custom_certificate_verifier(Arc::new(AnyCertificate))
+            .with_no_client_auth();
+        Self {
+            connector: TlsConnector::from(Arc::new(config)),
+        }
+    }
+
+    /// Runs a handshake over `stream`, a fresh connection to `socket`,
+    /// waiting up to `wait` for it. `None` when it doesn't speak TLS.
+    pub async fn probe(
+        &self,
+        stream: TcpStream,
+        socket: SocketAddr,
+        wait: Duration,
+    ) -> Option<TlsInfo> {
+        // Without a host name, no SNI is sent and the server picks its
+        // default certificate.
+        let server_name = ServerName::IpAddress(socket.ip());
+        let tls = io::timeout(wait, self.connector.connect(server_name, stream))
+            .await
+            .ok()?;
+        let (_, connection) = tls.get_ref();
+
+        let version = match connection.protocol_version()? {
+            ProtocolVersion::TLSv1_3 => "TLSv1.3".to_owned(),
+            ProtocolVersion::TLSv1_2 => "TLSv1.2".to_owned(),
+            other => format!("{other:?}"),
+        };
+        let certificate = connection
+            .peer_certificates()
+            .and_then(<[_]>::first)
+            .and_then(|certificate| describe(&certificate.0));
+        Some(TlsInfo {
+            version,
+            certificate,
+        })
+    }
+}
+
+/// Reads the identity of the DER encoded `certificate`.
+fn describe(certificate: &[u8]) -> Option<CertificateInfo> {
+    let (_, certificate) = X509Certificate::from_der(certificate).ok()?;
+    let sans = match certificate.subject_alternative_name() {
+        Ok(Some(extension)) => extension
+            .value
+            .general_names
+            .iter()
+            .filter_map(|name| match name {
+                GeneralName::DNSName(name) => Some((*name).to_owned()),
+                GeneralName::IPAddress(bytes) => ip_from_bytes(bytes).map(|ip| ip.to_string()),
+                _ => None,
+            })
+            .collect(),
+        _ => Vec::new(),
+    };
+    let validity = certificate.validity();
+    Some(CertificateInfo {
+        subject: certificate.subject().to_string(),
+        sans,
+        issuer: certificate.issuer().to_string(),
+        not_before: rfc3339(validity.not_before),
+        not_after: rfc3339(validity.not_after),
+    })
+}
+
+fn ip_from_bytes(bytes: &[u8]) -> Option<IpAddr> {
+    match bytes.len() {
+        4 => Some(IpAddr::from(<[u8; 4]>::try_from(bytes).ok()?)),
+        16 => Some(IpAddr::from(<[u8; 16]>::try_from(bytes).ok()?)),
+        _ => None,
+    }
+}
+
+fn rfc3339(time: ASN1Time) -> String {
+    let time = time.to_datetime();
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        time.year(),
+        u8::from(time.month()),
+        time.day(),
+        time.hour(),
+        time.minute(),
+        time.second()
+    )
+}
+
+/// Accepts every certificate, see the module documentation.
+struct AnyCertificate;
+
+impl ServerCertVerifier for AnyCertificate {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &rustls::Certificate,
+        _intermediates: &[rustls::Certificate],
+        _server_name: &ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        _now: SystemTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TlsProbe;
+    use async_std::net::TcpStream;
+    use async_std::task::block_on;
+    use rustls::{Certificate, PrivateKey, ServerConfig, ServerConnection, StreamOwned};
+    use std::io::Write;
+    use std::net::TcpListener;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    /// A TLS server with a self-signed certificate for `localhost`, that
+    /// handles a single connection.
+    fn tls_server() -> std::net::SocketAddr {
+        let cert = rcgen::generate_simple_self_signed(vec![
+            "localhost".to_owned(),
+            "127.0.0.1".to_owned(),
+        ])
+        .unwrap();
+        let config = ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_single_cert(
+                vec![Certificate(cert.serialize_der().unwrap())],
+                PrivateKey(cert.serialize_private_key_der()),
+            )
+            .unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let connection = ServerConnection::new(Arc::new(config)).unwrap();
+            let mut tls = StreamOwned::new(connection, stream);
+            let _ = tls.write_all(b"hello");
+        });
+        addr
+    }
+
+    #[test]
+    fn handshakes_describe_the_certificate() {
+        let addr = tls_server();
+        let info = block_on(async {
+            let stream = TcpStream::connect(addr).await.unwrap();
+            TlsProbe::new()
+                .probe(stream, addr, Duration::from_secs(2))
+                .await
+        })
+        .unwrap();
+
+        assert_eq!(info.version, "TLSv1.3");
+        let certificate = info.certificate.unwrap();
+        assert_eq!(certificate.subject, "CN=rcgen self signed cert");
+        assert_eq!(certificate.issuer, certificate.subject);
+        assert_eq!(certificate.sans, vec!["localhost", "127.0.0.1"]);
+        assert!(certificate.not_before < certificate.not_after);
+    }
+
+    #[test]
+    fn plain_services_are_not_tls() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let _ = stream.write_all(b"SSH-2.0-test\r\n");
+        });
+
+        let info = block_on(async {
+            let stream = TcpStream::connect(addr).await.unwrap();
+            TlsProbe::new()
+                .probe(stream, addr, Duration::from_secs(2))
+                .await
+        });
+        assert_eq!(info, None);
+    }
+}
@@ -0,0 +1,105 @@
+//! Probe payloads used by the UDP scan mode.
+//!
+//! Most UDP services stay silent unless they receive a datagram they
+//! understand, so sending an empty packet would make almost every port look
+//! `open|filtered`. The payloads below are minimal, well-formed requests for
+//! the well-known services, borrowed from the probes nmap uses for `-sU`.
+
+/// DNS query for the root zone `NS` records.
+const DNS: &[u8] = &[
+    0x12, 0x34, 0x01, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x00,
+    0x01,
+];
+
+/// TFTP read request for a file that is very unlikely to exist, servers
+/// answer with an error packet.
+const TFTP: &[u8] = b"\x00\x01rustscan\x00octet\x00";
+
+/// ONC RPC portmapper `NULL` call.
+const PORTMAP: &[u8] = &[
+    0x72, 0xfe, 0x1d, 0x13, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x00, 0x01, 0x86, 0xa0,
+    0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+];
+
+/// NTP version 4 client request.
+const NTP: &[u8] = &[
+    0xe3, 0x00, 0x04, 0xfa, 0x00, 0x01, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xc5, 0x4f, 0x23, 0x4b, 0x71, 0xb1, 0x52, 0xf3,
+];
+
+/// NetBIOS name service wildcard status request.
+const NETBIOS_NS: &[u8] = &[
+    0x80, 0xf0, 0x00, 0x10, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x20, 0x43, 0x4b, 0x41,
+    0x41, 0x41, 0x41, 0x41, 0x41, 0x41, 0x41, 0x41, 0x41, 0x41, 0x41, 0x41, 0x41, 0x41, 0x41, 0x41,
+    0x41, 0x41, 0x41, 0x41, 0x41, 0x41, 0x41, 0x41, 0x41, 0x41, 0x41, 0x41, 0x41, 0x00, 0x00, 0x21,
+    0x00, 0x01,
+];
+
+/// SNMPv1 `get-request` for `sysDescr.0` with the `public` community.
+const SNMP: &[u8] = &[
+    0x30, 0x29, 0x02, 0x01, 0x00, 0x04, 0x06, 0x70, 0x75, 0x62, 0x6c, 0x69, 0x63, 0xa0, 0x1c, 0x02,
+    0x04, 0x56, 0x9f, 0x5a, 0xdd, 0x02, 0x01, 0x00, 0x02, 0x01, 0x00, 0x30, 0x0e, 0x30, 0x0c, 0x06,
+    0x08, 0x2b, 0x06, 0x01, 0x02, 0x01, 0x01, 0x01, 0x00, 0x05, 0x00,
+];
+
+/// IKEv1 main mode `SA` proposal, answered by any IPsec VPN endpoint.
+const IKE: &[u8] = &[
+    0x5b, 0x5e, 0x64, 0xc0, 0x3e, 0x99, 0xb5, 0x11, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x01, 0x10, 0x02, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x54, 0x00, 0x00, 0x00, 0x38,
+    0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x2c, 0x01, 0x01, 0x00, 0x01,
+    0x00, 0x00, 0x00, 0x24, 0x01, 0x01, 0x00, 0x00, 0x80, 0x01, 0x00, 0x05, 0x80, 0x02, 0x00, 0x02,
+    0x80, 0x03, 0x00, 0x01, 0x80, 0x04, 0x00, 0x02, 0x80, 0x0b, 0x00, 0x01, 0x00, 0x0c, 0x00, 0x04,
+    0x00, 0x00, 0x70, 0x80,
+];
+
+/// SSDP `M-SEARCH` discovery request.
+const SSDP: &[u8] = b"M-SEARCH * HTTP/1.1\r\nHOST: 239.255.255.250:1900\r\nMAN: \"ssdp:discover\"\r\nMX: 1\r\nST: ssdp:all\r\n\r\n";
+
+/// mDNS query for the DNS-SD service enumeration record.
+const MDNS: &[u8] = &[
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x09, 0x5f, 0x73, 0x65,
+    0x72, 0x76, 0x69, 0x63, 0x65, 0x73, 0x07, 0x5f, 0x64, 0x6e, 0x73, 0x2d, 0x73, 0x64, 0x04, 0x5f,
+    0x75, 0x64, 0x70, 0x05, 0x6c, 0x6f, 0x63, 0x61, 0x6c, 0x00, 0x00, 0x0c, 0x00, 0x01,
+];
+
+/// Memcached `stats` command in the UDP frame format.
+const MEMCACHED: &[u8] = b"\x00\x01\x00\x00\x00\x01\x00\x00stats\r\n";
+
+/// Returns the probe payload for the given destination port.
+///
+/// Ports without a specific probe get an empty datagram, which still
+/// triggers an ICMP port unreachable from closed ports.
+pub fn payload_for_port(port: u16) -> &'static [u8] {
+    match port {
+        53 => DNS,
+        69 => TFTP,
+        111 => PORTMAP,
+        123 => NTP,
+        137 => NETBIOS_NS,
+        161 => SNMP,
+        500 => IKE,
+        1900 => SSDP,
+        5353 => MDNS,
+        11211 => MEMCACHED,
+        _ => &[],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::payload_for_port;
+
+    #[test]
+    fn well_known_ports_have_payloads() {
+        for port in [53, 69, 111, 123, 137, 161, 500, 1900, 5353, 11211] {
+            assert!(!payload_for_port(port).is_empty(), "no payload for {}", port);
+        }
+    }
+
+    #[test]
+    fn unknown_ports_get_empty_payload() {
+        assert!(payload_for_port(31_337).is_empty());
+    }
+}
@@ -72,6 +72,13 @@
 //!
 //! If the format is different, the script will be silently discarded and will
 //! not run. With the `Debug` option it's possible to see where it goes wrong.
+//!
+//! When the scan goes through a `--proxy`, scripts get its URL in the
+//! `RUSTSCAN_PROXY` environment variable, to reach the hosts the same way.
+//!
+//! Scripts get the protocol of the open ports, `tcp` or `udp`, in the
+//! `RUSTSCAN_PROTOCOL` environment variable. With `--udp` the default script
+//! runs nmap with `-sU`, as the ports are only open over UDP.
 
 #![allow(clippy::module_name_repetitions)]
 
@@ -89,21 +96,34 @@ use std::string::ToString;
 use subprocess::{Exec, ExitStatus};
 use text_placeholder::Template;
 
+/// The environment variable scripts find the `--proxy` of the scan in.
+pub const PROXY_ENV_VAR: &str = "RUSTSCAN_PROXY";
+
+/// The environment variable scripts find the protocol of the ports in.
+pub const PROTOCOL_ENV_VAR: &str = "RUSTSCAN_PROTOCOL";
+
 static DEFAULT: &str = r#"tags = ["core_approved", "RustScan", "default"]
 developer = [ "RustScan", "https://github.com/RustScan" ]
 ports_separator = ","
 call_format = "nmap -vvv -p {{port}} {{ip}}"
 "#;
 
+static DEFAULT_UDP: &str = r#"tags = ["core_approved", "RustScan", "default"]
+developer = [ "RustScan", "https://github.com/RustScan" ]
+ports_separator = ","
+call_format = "nmap -vvv -sU -p {{port}} {{ip}}"
+"#;
+
 #[cfg(not(tarpaulin_include))]
-pub fn init_scripts(scripts: ScriptsRequired) -> Result<Vec<ScriptFile>> {
+pub fn init_scripts(scripts: ScriptsRequired, udp: bool) -> Result<Vec<ScriptFile>> {
     let mut scripts_to_run: Vec<ScriptFile> = Vec::new();
 
     match scripts {
         ScriptsRequired::None => Ok(scripts_to_run),
         ScriptsRequired::Default => {
+            let default = if udp { DEFAULT_UDP } else { DEFAULT };
             let default_script =
-                toml::from_str::<ScriptFile>(DEFAULT).expect("Failed to parse Script file.");
+                toml::from_str::<ScriptFile>(default).expect("Failed to parse Script file.");
             scripts_to_run.push(default_script);
             Ok(scripts_to_run)
         }
@@ -127,9 +147,8 @@ pub fn init_scripts(scripts: ScriptsRequired) -> Result<Vec<ScriptFile>> {
             debug!("Script config \n{:?}", script_config);
 
             // Only Scripts that contain all the tags found in ScriptConfig will be selected.
-            if script_config.tags.is_some() {
-                let config_hashset: HashSet<String> =
-                    script_config.tags.unwrap().into_iter().collect();
+            if let Some(config_tags) = script_config.tags {
+                let config_hashset: HashSet<String> = config_tags.into_iter().collect();
                 for script in &parsed_scripts {
                     if script.tags.is_some() {
                         let script_hashset: HashSet<String> =
@@ -186,6 +205,9 @@ pub struct Script {
 
     // The format how we want the script to run.
     call_format: Option<String>,
+
+    // Environment variables set for the script, on top of RustScan's own.
+    env: Vec<(String, String)>,
 }
 
 #[derive(Serialize)]
@@ -219,9 +241,17 @@ impl Script {
             ports_separator,
             tags,
             call_format,
+            env: Vec::new(),
         }
     }
 
+    /// Sets the environment variable `name` to `value` for the script.
+    #[must_use]
+    pub fn with_env(mut self, name: &str, value: &str) -> Self {
+        self.env.push((name.to_owned(), value.to_owned()));
+        self
+    }
+
     // Some variables get changed before read, and compiler throws warning on warn(unused_assignments)
     #[allow(unused_assignments)]
     pub fn run(self) -> Result<String> {
@@ -264,14 +294,18 @@ impl Script {
         }
         debug!("\nScript format to run {}", to_run);
 
-        execute_script(&to_run)
+        execute_script(&to_run, &self.env)
     }
 }
 
 #[cfg(not(tarpaulin_include))]
-fn execute_script(script: &str) -> Result<String> {
+fn execute_script(script: &str, env: &[(String, String)]) -> Result<String> {
     debug!("\nScript arguments {}", script);
-    let process = Exec::shell(script);
+    let process = env
+        .iter()
+        .fold(Exec::shell(script), |process, (name, value)| {
+            process.env(name, value)
+        });
     match process.capture() {
         Ok(c) => {
             let es = match c.exit_status {
@@ -376,7 +410,8 @@ impl ScriptConfig {
 
 #[cfg(test)]
 mod tests {
-    use super::{find_scripts, parse_scripts, Script, ScriptFile};
+    use super::{find_scripts, init_scripts, parse_scripts, Script, ScriptFile, PROXY_ENV_VAR};
+    use crate::input::ScriptsRequired;
 
     // Function for testing only, it inserts static values into ip and open_ports
     // Doesn't use impl in case it's implemented in the super module at some point
@@ -482,6 +517,35 @@ mod tests {
         );
     }
 
+    #[test]
+    #[cfg(unix)]
+    fn scripts_get_the_proxy_in_their_environment() {
+        let script = Script::build(
+            None,
+            "127.0.0.1".parse().unwrap(),
+            vec![80],
+            None,
+            None,
+            None,
+            Some("echo $RUSTSCAN_PROXY {{ip}} {{port}}".to_owned()),
+        )
+        .with_env(PROXY_ENV_VAR, "socks5://127.0.0.1:1080");
+        let output = script.run().unwrap();
+        assert_eq!(output.trim(), "socks5://127.0.0.1:1080 127.0.0.1 80");
+    }
+
+    #[test]
+    fn default_script_scans_udp_ports_over_udp() {
+        let call_format = |udp| {
+            init_scripts(ScriptsRequired::Default, udp).unwrap()[0]
+                .call_format
+                .clone()
+                .unwrap()
+        };
+        assert_eq!(call_format(false), "nmap -vvv -p {{port}} {{ip}}");
+        assert_eq!(call_format(true), "nmap -vvv -sU -p {{port}} {{ip}}");
+    }
+
     #[test]
     #[cfg(unix)]
     fn run_perl_script() {
@@ -0,0 +1,268 @@
+//! Service detection on open TCP ports, see `--services`.
+//!
+//! A service is told by what it answers to the probes of a database, in a
+//! subset of the nmap-service-probes format, see [`parse`]. The probe with
+//! nothing to send comes first, its answer is the banner of the services
+//! that talk first, then the probes meant for the port, then the others,
+//! each over a fresh connection, until a `match` of the probe recognizes
+//! the answer. A `softmatch` only names the service, the probes that
+//! follow it are the ones with a `match` for that service, which may tell
+//! the product and version.
+//!
+//! A database of the common services is built in, see [`DEFAULT_PROBES`].
+use async_std::io;
+use async_std::net::TcpStream;
+use async_std::prelude::*;
+use log::debug;
+use regex::bytes::Regex;
+use serde_derive::{Deserialize, Serialize};
+use std::fmt;
+use std::future::Future;
+use std::ops::RangeInclusive;
+use std::path::Path;
+use std::time::Duration;
+
+mod parse;
+
+/// The built-in probe database.
+pub const DEFAULT_PROBES: &str = include_str!("service-probes");
+
+/// The most bytes of an answer that are matched.
+const MAX_RESPONSE_LEN: usize = 16 * 1024;
+
+/// How long to wait for more of an answer once it started coming.
+const TRAILING_WAIT: Duration = Duration::from_millis(200);
+
+/// The service found listening on a port.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Service {
+    /// The name of the protocol, e.g. "ssh" or "http".
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub product: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    /// Anything else the answer told, e.g. the operating system.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub info: Option<String>,
+}
+
+impl fmt::Display for Service {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.name)?;
+        for detail in self.product.iter().chain(&self.version) {
+            write!(f, " {detail}")?;
+        }
+        if let Some(info) = &self.info {
+            write!(f, " ({info})")?;
+        }
+        Ok(())
+    }
+}
+
+/// A probe database.
+#[derive(Debug)]
+pub struct ServiceProbes {
+    probes: Vec<Probe>,
+}
+
+/// What is sent to a port, and how to read the answers.
+#[derive(Debug)]
+struct Probe {
+    name: String,
+    payload: Vec<u8>,
+    /// The ports the probe is meant for, it is sent to those first.
+    ports: Vec<RangeInclusive<u16>>,
+    matches: Vec<Match>,
+}
+
+/// Recognizes the answers of a service.
+#[derive(Debug)]
+struct Match {
+    service: String,
+    pattern: Regex,
+    /// Templates of the service details, with the groups of the pattern.
+    product: Option<String>,
+    version: Option<String>,
+    info: Option<String>,
+    soft: bool,
+}
+
+impl Default for ServiceProbes {
+    fn default() -> Self {
+        Self::parse(DEFAULT_PROBES).expect("the built-in service probes are valid")
+    }
+}
+
+impl ServiceProbes {
+    /// Reads a probe database, e.g. `Probe TCP NULL q||` followed by its
+    /// `match` lines. The error tells the first line that isn't valid.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        parse::probes(text).map(|probes| Self { probes })
+    }
+
+    /// Reads the probe database at `path`.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let text = std::fs::read_to_string(path)?;
+        Self::parse(&text).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    /// Finds the service on `port` by sending it the probes, each over a
+    /// new connection from `connect`, and waiting up to `wait` for every
+    /// answer. `None` when no probe recognized it.
+    pub async fn detect<F, C>(&self, port: u16, wait: Duration, connect: F) -> Option<Service>
+    where
+        F: Fn() -> C,
+        C: Future<Output = io::Result<TcpStream>>,
+    {
+        let mut soft_match: Option<Service> = None;
+        for probe in self.in_order(port) {
+            if let Some(service) = &soft_match {
+                if !probe.can_tell(&service.name) {
+                    continue;
+                }
+            }
+            debug!("Sending the {} probe to port {}", probe.name, port);
+            let Ok(mut stream) = connect().await else {
+                break;
+            };
+            if !probe.payload.is_empty() && stream.write_all(&probe.payload).await.is_err() {
+                continue;
+            }
+            let response = read_response(&mut stream, wait).await;
+            if response.is_empty() {
+                continue;
+            }
+
+            for service_match in &probe.matches {
+                let other_service =
+                    |soft: &Service| service_match.soft || service_match.service != soft.name;
+                if soft_match.as_ref().is_some_and(other_service) {
+                    continue;
+                }
+                let Some(service) = service_match.service(&response) else {
+                    continue;
+                };
+                if !service_match.soft {
+                    return Some(service);
+                }
+                soft_match = Some(service);
+            }
+        }
+        soft_match
+    }
+
+    /// The probes in the order they are sent to `port`: the ones with no
+    /// payload, then the ones meant for the port, then the others.
+    fn in_order(&self, port: u16) -> Vec<&Probe> {
+        let mut probes: Vec<&Probe> = self.probes.iter().collect();
+        probes.sort_by_key(|probe| {
+            (
+                !probe.payload.is_empty(),
+                !probe.ports.iter().any(|ports| ports.contains(&port)),
+            )
+        });
+        probes
+    }
+}
+
+impl Probe {
+    /// Whether a `match` of the probe recognizes `service`.
+    fn can_tell(&self, service: &str) -> bool {
+        self.matches
+            .iter()
+            .any(|service_match| !service_match.soft && service_match.service == service)
+    }
+}
+
+/// Reads the answer on `stream`, waiting up to `wait` for it to start and
+/// a little more for the rest of it. Empty when nothing came.
+async fn read_response(stream: &mut TcpStream, mut wait: Duration) -> Vec<u8> {
+    let mut response = Vec::new();
+    let mut buf = [0; 4096];
+    while response.len() < MAX_RESPONSE_LEN {
+        match io::timeout(wait, stream.read(&mut buf)).await {
+            Ok(0) | Err(_) => break,
+            Ok(n) => response.extend_from_slice(&buf[..n]),
+        }
+        wait = wait.min(TRAILING_WAIT);
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Service, ServiceProbes};
+    use async_std::net::TcpStream;
+    use async_std::task::block_on;
+    use std::io::{Read, Write};
+    use std::net::{SocketAddr, TcpListener};
+    use std::thread;
+    use std::time::Duration;
+
+    /// A server that greets with `greeting`, and answers any request with
+    /// `answer`.
+    fn server(greeting: &'static [u8], answer: &'static [u8]) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let _ = stream.write_all(greeting);
+                let mut request = [0; 1024];
+                if matches!(stream.read(&mut request), Ok(n) if n > 0) {
+                    let _ = stream.write_all(answer);
+                }
+            }
+        });
+        addr
+    }
+
+    fn detect(addr: SocketAddr) -> Option<Service> {
+        block_on(
+            ServiceProbes::default().detect(addr.port(), Duration::from_millis(300), || {
+                TcpStream::connect(addr)
+            }),
+        )
+    }
+
+    #[test]
+    fn the_built_in_probes_are_valid() {
+        let probes = ServiceProbes::default();
+        assert!(probes.probes.iter().any(|probe| probe.name == "NULL"));
+    }
+
+    #[test]
+    fn services_that_talk_first_are_told_by_their_banner() {
+        let service = detect(server(b"SSH-2.0-OpenSSH_9.6p1 Ubuntu-3ubuntu13\r\n", b"")).unwrap();
+        assert_eq!(service.name, "ssh");
+        assert_eq!(service.product.as_deref(), Some("OpenSSH"));
+        assert_eq!(service.version.as_deref(), Some("9.6p1 Ubuntu 3ubuntu13"));
+        assert_eq!(service.info.as_deref(), Some("Ubuntu Linux; protocol 2.0"));
+        assert_eq!(
+            service.to_string(),
+            "ssh OpenSSH 9.6p1 Ubuntu 3ubuntu13 (Ubuntu Linux; protocol 2.0)"
+        );
+    }
+
+    #[test]
+    fn services_that_wait_are_told_by_their_answer() {
+        let addr = server(
+            b"",
+            b"HTTP/1.0 200 OK\r\nServer: SimpleHTTP/0.6 Python/3.12.3\r\n\r\n",
+        );
+        let service = detect(addr).unwrap();
+        assert_eq!(service.name, "http");
+        assert_eq!(service.product.as_deref(), Some("SimpleHTTPServer"));
+        assert_eq!(service.version.as_deref(), Some("0.6"));
+        assert_eq!(service.info.as_deref(), Some("Python 3.12.3"));
+    }
+
+    #[test]
+    fn unknown_services_are_not_told() {
+        assert_eq!(detect(server(b"", b"")), None);
+        let soft = detect(server(b"", b"HTTP/1.1 200 OK\r\nServer: unknown\r\n\r\n")).unwrap();
+        assert_eq!(soft.name, "http");
+        assert_eq!(soft.product, None);
+    }
+}
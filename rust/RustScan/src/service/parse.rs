@@ -0,0 +1,360 @@
+//! Reads probe databases in a subset of the nmap-service-probes format.
+//!
+//! Understood are the `Probe`, `ports`, `match` and `softmatch` lines, the
+//! `i` and `s` flags of the patterns and the `p`, `v` and `i` fields of the
+//! matches, with `$1` to `$9` and `$P(1)` to `$P(9)` standing for the
+//! groups of the pattern. The other directives and fields are skipped, as
+//! are the UDP probes, and a pattern only has the syntax of the `regex`
+//! crate. The matches whose pattern needs the lookarounds, backreferences
+//! or atomic groups of PCRE are skipped, so the nmap database can still be
+//! loaded, only without them.
+use super::{Match, Probe, Service};
+use log::debug;
+use regex::bytes::{Captures, RegexBuilder};
+use std::ops::RangeInclusive;
+
+/// The probes in `text`, or the first line that isn't valid.
+pub(super) fn probes(text: &str) -> Result<Vec<Probe>, String> {
+    let mut probes: Vec<Probe> = Vec::new();
+    // The lines after a UDP probe belong to it, and are skipped with it.
+    let mut in_udp_probe = false;
+    let mut skipped = 0;
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let error = |message: &str| format!("line {}: {message}", number + 1);
+        let (directive, rest) = line.split_once(' ').unwrap_or((line, ""));
+
+        if directive == "Probe" {
+            match probe(rest).map_err(|e| error(&e))? {
+                Some(probe) => {
+                    probes.push(probe);
+                    in_udp_probe = false;
+                }
+                None => in_udp_probe = true,
+            }
+            continue;
+        }
+        if in_udp_probe {
+            continue;
+        }
+        let Some(probe) = probes.last_mut() else {
+            return Err(error("expected a Probe first"));
+        };
+        let soft = match directive {
+            "ports" => {
+                probe.ports = ports(rest).ok_or_else(|| error("invalid ports"))?;
+                continue;
+            }
+            "match" => false,
+            "softmatch" => true,
+            _ => continue,
+        };
+        match service_match(rest, soft).map_err(|e| error(&e))? {
+            Some(service_match) => probe.matches.push(service_match),
+            None => {
+                debug!("{}", error("skipped a pattern that needs PCRE"));
+                skipped += 1;
+            }
+        }
+    }
+    if skipped > 0 {
+        debug!("Skipped {} service patterns that need PCRE", skipped);
+    }
+    Ok(probes)
+}
+
+/// `TCP <name> q|<payload>|`, `None` for a UDP probe.
+fn probe(line: &str) -> Result<Option<Probe>, String> {
+    let mut fields = line.splitn(3, ' ');
+    let (protocol, name, payload) = match (fields.next(), fields.next(), fields.next()) {
+        (Some(protocol), Some(name), Some(payload)) => (protocol, name, payload),
+        _ => return Err("expected Probe <protocol> <name> q|<payload>|".to_owned()),
+    };
+    match protocol {
+        "TCP" => {}
+        "UDP" => return Ok(None),
+        _ => return Err(format!("unknown protocol {protocol}")),
+    }
+    let payload = payload
+        .strip_prefix('q')
+        .and_then(delimited)
+        .ok_or("expected the payload as q|<payload>|")?
+        .0;
+
+    Ok(Some(Probe {
+        name: name.to_owned(),
+        payload: unescape(payload),
+        ports: Vec::new(),
+        matches: Vec::new(),
+    }))
+}
+
+/// `80,443,8000-8100`
+fn ports(list: &str) -> Option<Vec<RangeInclusive<u16>>> {
+    list.trim()
+        .split(',')
+        .map(|range| match range.split_once('-') {
+            Some((start, end)) => Some(start.parse().ok()?..=end.parse().ok()?),
+            None => {
+                let port = range.parse().ok()?;
+                Some(port..=port)
+            }
+        })
+        .collect()
+}
+
+/// `<service> m|<pattern>|<flags> p/<product>/ v/<version>/ i/<info>/`,
+/// `None` when the pattern needs PCRE.
+fn service_match(line: &str, soft: bool) -> Result<Option<Match>, String> {
+    let (service, rest) = line
+        .split_once(' ')
+        .ok_or("expected <service> m|<pattern>|")?;
+    let (pattern, rest) = rest
+        .strip_prefix('m')
+        .and_then(delimited)
+        .ok_or("expected the pattern as m|<pattern>|")?;
+    let (flags, mut rest) = rest.split_at(rest.find(' ').unwrap_or(rest.len()));
+
+    // Backreferences would otherwise be read as octal escapes.
+    if needs_pcre(pattern) {
+        return Ok(None);
+    }
+    let pattern = RegexBuilder::new(pattern)
+        .unicode(false)
+        .octal(true)
+        .case_insensitive(flags.contains('i'))
+        .dot_matches_new_line(flags.contains('s'))
+        .build()
+        .map_err(|e| format!("invalid pattern: {e}"))?;
+    let mut service_match = Match {
+        service: service.to_owned(),
+        pattern,
+        product: None,
+        version: None,
+        info: None,
+        soft,
+    };
+
+    loop {
+        rest = rest.trim_start();
+        if rest.is_empty() {
+            break;
+        }
+        let (field, after_field) = match rest.strip_prefix("cpe:") {
+            Some(after) => ("cpe", after),
+            None => rest.split_at(rest.chars().next().map_or(0, char::len_utf8)),
+        };
+        let (value, after_value) =
+            delimited(after_field).ok_or_else(|| format!("unterminated {field} field"))?;
+        let value = Some(value.to_owned());
+        match field {
+            "p" => service_match.product = value,
+            "v" => service_match.version = value,
+            "i" => service_match.info = value,
+            _ => {}
+        }
+        // Flags, like the `a` of `cpe:/a:...`, are skipped.
+        rest = after_value.trim_start_matches(|c: char| !c.is_whitespace());
+    }
+    Ok(Some(service_match))
+}
+
+/// Whether `pattern` uses what only PCRE has: lookarounds, atomic groups or
+/// backreferences.
+fn needs_pcre(pattern: &str) -> bool {
+    let mut rest = pattern.as_bytes();
+    while let Some((&byte, after)) = rest.split_first() {
+        rest = after;
+        match (byte, rest) {
+            (b'\\', [b'1'..=b'9', ..]) => return true,
+            // Escaped characters can't start anything.
+            (b'\\', [_, after @ ..]) => rest = after,
+            (b'(', [b'?', b'=' | b'!' | b'>', ..] | [b'?', b'<', b'=' | b'!', ..]) => return true,
+            _ => {}
+        }
+    }
+    false
+}
+
+/// Splits `|<value>|<rest>` into the value and the rest, whatever the
+/// first character is, nmap lets each field pick its delimiter.
+fn delimited(field: &str) -> Option<(&str, &str)> {
+    let delimiter = field.chars().next()?;
+    let field = &field[delimiter.len_utf8()..];
+    let end = field.find(delimiter)?;
+    Some((&field[..end], &field[end + delimiter.len_utf8()..]))
+}
+
+/// Reads the C style escapes of a probe payload, like `\r\n` or `\x00`.
+fn unescape(payload: &str) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(payload.len());
+    let mut rest = payload.as_bytes();
+    while let Some((&byte, after)) = rest.split_first() {
+        rest = after;
+        if byte != b'\\' {
+            bytes.push(byte);
+            continue;
+        }
+        let Some((&escaped, after)) = rest.split_first() else {
+            bytes.push(b'\\');
+            break;
+        };
+        rest = after;
+        let hex = rest
+            .get(..2)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        bytes.push(match escaped {
+            b'0' => 0,
+            b'a' => 0x07,
+            b'b' => 0x08,
+            b'f' => 0x0c,
+            b'n' => b'\n',
+            b'r' => b'\r',
+            b't' => b'\t',
+            b'v' => 0x0b,
+            b'x' if hex.is_some() => {
+                rest = &rest[2..];
+                hex.unwrap_or_default()
+            }
+            other => other,
+        });
+    }
+    bytes
+}
+
+impl Match {
+    /// The service `response` comes from, when it matches.
+    pub(super) fn service(&self, response: &[u8]) -> Option<Service> {
+        let groups = self.pattern.captures(response)?;
+        let fill = |template: &Option<String>| {
+            template
+                .as_deref()
+                .map(|template| substitute(template, &groups))
+                .filter(|value| !value.is_empty())
+        };
+        Some(Service {
+            name: self.service.clone(),
+            product: fill(&self.product),
+            version: fill(&self.version),
+            info: fill(&self.info),
+        })
+    }
+}
+
+/// Replaces the `$1` and `$P(1)` of `template` by the groups of the match.
+/// `$P` only keeps the printable characters, the plain groups are read
+/// lossily as UTF-8.
+fn substitute(template: &str, groups: &Captures<'_>) -> String {
+    let group = |index: u8| {
+        groups
+            .get(usize::from(index - b'0'))
+            .map_or(&[][..], |group| group.as_bytes())
+    };
+    let mut value = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(dollar) = rest.find('$') {
+        value.push_str(&rest[..dollar]);
+        rest = &rest[dollar..];
+        match rest.as_bytes() {
+            [b'$', index @ b'1'..=b'9', ..] => {
+                value.push_str(&String::from_utf8_lossy(group(*index)));
+                rest = &rest[2..];
+            }
+            [b'$', b'P', b'(', index @ b'1'..=b'9', b')', ..] => {
+                let printable = group(*index).iter().filter(|b| matches!(b, b' '..=b'~'));
+                value.extend(printable.map(|&b| char::from(b)));
+                rest = &rest[5..];
+            }
+            _ => {
+                value.push('$');
+                rest = &rest[1..];
+            }
+        }
+    }
+    value.push_str(rest);
+    value.trim().to_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{probes, unescape};
+
+    const PROBES: &str = r"
+# A comment.
+Probe TCP NULL q||
+totalwaitms 6000
+match ssh m|^SSH-([\d.]+)-OpenSSH_([\w.]+)\r?\n| p/OpenSSH/ v/$2/ i/protocol $1/ cpe:/a:openbsd:openssh:$2/a
+softmatch ftp m|^220 .*ftp|i
+
+Probe UDP DNSStatusRequest q|\0\0\x10\0\0\0\0\0\0\0\0\0|
+match dns m|^\0\0\x90| p/ignored/
+
+Probe TCP GetRequest q|GET / HTTP/1.0\r\n\r\n|
+ports 80,8000-8010
+match http m|^HTTP/1\.[01] .*\r\nServer: nginx/([\d.]+)|s p/nginx/ v/$P(1)/
+";
+
+    #[test]
+    fn probes_are_read() {
+        let probes = probes(PROBES).unwrap();
+        assert_eq!(probes.len(), 2);
+
+        let null = &probes[0];
+        assert_eq!(null.name, "NULL");
+        assert!(null.payload.is_empty());
+        assert_eq!(null.matches.len(), 2);
+        let ssh = null.matches[0].service(b"SSH-2.0-OpenSSH_9.6\r\n").unwrap();
+        assert_eq!(ssh.name, "ssh");
+        assert_eq!(ssh.product.as_deref(), Some("OpenSSH"));
+        assert_eq!(ssh.version.as_deref(), Some("9.6"));
+        assert_eq!(ssh.info.as_deref(), Some("protocol 2.0"));
+        assert!(null.matches[1].soft);
+        assert!(null.matches[1].service(b"220 FTP server ready").is_some());
+
+        let get = &probes[1];
+        assert_eq!(get.payload, b"GET / HTTP/1.0\r\n\r\n");
+        assert_eq!(get.ports, vec![80..=80, 8000..=8010]);
+        let http = get.matches[0]
+            .service(b"HTTP/1.1 200 OK\r\nServer: nginx/1.24.0\r\n\r\n")
+            .unwrap();
+        assert_eq!(http.version.as_deref(), Some("1.24.0"));
+        assert!(get.matches[0].service(b"HTTP/1.1 200 OK\r\n\r\n").is_none());
+    }
+
+    #[test]
+    fn invalid_lines_are_reported() {
+        assert_eq!(
+            probes("match ssh m|^SSH-|").unwrap_err(),
+            "line 1: expected a Probe first"
+        );
+        assert!(probes("Probe TCP NULL q||\nmatch ssh m|^SSH-(|")
+            .unwrap_err()
+            .starts_with("line 2: invalid pattern"));
+        assert!(probes("Probe TCP NULL q||\nmatch ssh m|^SSH-| p/OpenSSH").is_err());
+        assert!(probes("Probe SCTP NULL q||").is_err());
+    }
+
+    #[test]
+    fn pcre_only_patterns_are_skipped() {
+        let probes = probes(
+            r"Probe TCP NULL q||
+match ftp m|^220 (?!.*vsftpd)| p/ftp/
+match telnet m|^(\xff.)\1|
+match ssh m|^SSH-| p/ssh/
+",
+        )
+        .unwrap();
+
+        assert_eq!(probes[0].matches.len(), 1);
+        assert_eq!(probes[0].matches[0].service, "ssh");
+    }
+
+    #[test]
+    fn payload_escapes() {
+        assert_eq!(unescape(r"a\r\n\0\x41\\\|"), b"a\r\n\0A\\|");
+    }
+}
@@ -0,0 +1,11 @@
+//! Helpers shared by the tests of several modules.
+use std::net::TcpListener;
+
+/// A local TCP port nothing listens on, from a listener that is gone again.
+pub fn closed_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port()
+}